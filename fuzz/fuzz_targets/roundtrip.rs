@@ -0,0 +1,36 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+
+fn check(compressed: &[u8], expected: &[u8]) {
+    assert!(compressed.len() <= fdeflate::compress_bound(expected.len()));
+
+    let decompressed = fdeflate::decompress_to_vec(compressed).unwrap();
+    assert_eq!(decompressed, expected);
+
+    let reference = miniz_oxide::inflate::decompress_to_vec_zlib(compressed).unwrap();
+    assert_eq!(reference, expected);
+}
+
+fuzz_target!(|data: Vec<Vec<u8>>| {
+    let input = data.concat();
+
+    let mut compressor = fdeflate::Compressor::new(Vec::new()).unwrap();
+    for chunk in &data {
+        compressor.write_data(chunk).unwrap();
+    }
+    check(&compressor.finish().unwrap(), &input);
+
+    check(&fdeflate::compress_to_vec(&input), &input);
+
+    let mut compressor = fdeflate::StoredOnlyCompressor::new(Cursor::new(Vec::new())).unwrap();
+    for chunk in &data {
+        compressor.write_data(chunk).unwrap();
+    }
+    check(&compressor.finish().unwrap().into_inner(), &input);
+
+    check(
+        &fdeflate::compress_stored_vectored(&input, Vec::new()).unwrap(),
+        &input,
+    );
+});
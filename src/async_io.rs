@@ -0,0 +1,412 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
+
+use crate::windowed::OutputWindow;
+use crate::{DecompressionError, Decompressor, RECOMMENDED_INPUT_BUFFER_SIZE};
+
+/// Like `std::task::ready!`, which needs a newer compiler than the crate supports.
+macro_rules! ready {
+    ($e:expr) => {
+        match $e {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        }
+    };
+}
+
+/// Decompresses a zlib stream read from an underlying [`AsyncRead`].
+///
+/// This is the asynchronous version of [`DecompressReader`](crate::DecompressReader). It
+/// implements [`AsyncRead`] and [`AsyncBufRead`] from `futures-io`, which runtimes like tokio
+/// support through their compatibility layers. Decompression itself happens inline while
+/// polling, a few dozen kilobytes at a time, so no blocking tasks are needed.
+pub struct AsyncDecompressReader<R> {
+    reader: R,
+    decompressor: Box<Decompressor>,
+    input: Vec<u8>,
+    input_start: usize,
+    input_end: usize,
+    reader_done: bool,
+    output: OutputWindow,
+}
+
+impl<R: AsyncRead + Unpin> AsyncDecompressReader<R> {
+    /// Create a new reader that decompresses the zlib stream read from `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            decompressor: Box::default(),
+            input: vec![0; RECOMMENDED_INPUT_BUFFER_SIZE],
+            input_start: 0,
+            input_end: 0,
+            reader_done: false,
+            output: OutputWindow::new(),
+        }
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the underlying reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the underlying reader.
+    ///
+    /// Any input that was read from it but not yet decompressed is lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Move any unconsumed input to the start of the buffer and read more after it.
+    fn poll_refill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.input.copy_within(self.input_start..self.input_end, 0);
+        self.input_end -= self.input_start;
+        self.input_start = 0;
+
+        let n =
+            ready!(Pin::new(&mut self.reader).poll_read(cx, &mut self.input[self.input_end..]))?;
+        self.input_end += n;
+        self.reader_done = n == 0;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Decompress until some output is buffered or the stream ends.
+    fn poll_fill_output(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.output.buffered().is_empty() && !self.decompressor.is_done() {
+            let (consumed, produced) = self.output.decode(
+                &mut self.decompressor,
+                &self.input[self.input_start..self.input_end],
+                self.reader_done,
+            )?;
+            self.input_start += consumed;
+
+            if consumed == 0 && produced == 0 && !self.decompressor.is_done() {
+                if self.reader_done {
+                    return Poll::Ready(Err(DecompressionError::InsufficientInput.into()));
+                }
+                ready!(self.poll_refill(cx))?;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncDecompressReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_fill_output(cx))?;
+        let available = this.output.buffered();
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        this.output.consume(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncBufRead for AsyncDecompressReader<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        ready!(this.poll_fill_output(cx))?;
+        Poll::Ready(Ok(this.output.buffered()))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().output.consume(amt);
+    }
+}
+
+/// Decompresses a zlib stream written to it, forwarding the output to an underlying
+/// [`AsyncWrite`].
+///
+/// This is the asynchronous version of [`DecompressWriter`](crate::DecompressWriter). Closing it
+/// fails if the stream is incomplete, and writes after the end of the stream return `Ok(0)`.
+pub struct AsyncDecompressWriter<W> {
+    writer: W,
+    decompressor: Box<Decompressor>,
+    /// Input that couldn't be consumed yet, because it was too short to make progress.
+    pending: Vec<u8>,
+    /// Output not yet passed to `writer`.
+    output: OutputWindow,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncDecompressWriter<W> {
+    /// Create a new writer that forwards the decompressed data to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            decompressor: Box::default(),
+            pending: Vec::new(),
+            output: OutputWindow::new(),
+        }
+    }
+
+    /// Returns whether the end of the compressed stream has been reached.
+    pub fn is_done(&self) -> bool {
+        self.decompressor.is_done()
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Returns the underlying writer.
+    ///
+    /// Any output that wasn't passed to it yet is lost, so the adapter should be flushed or
+    /// closed first.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Pass all buffered output to the underlying writer.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.output.buffered().is_empty() {
+            let n = ready!(Pin::new(&mut self.writer).poll_write(cx, self.output.buffered()))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.output.consume(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Decompress from `input` into the output buffer.
+    fn decompress(&mut self, input: &[u8], end_of_input: bool) -> io::Result<(usize, usize)> {
+        Ok(self
+            .output
+            .decode(&mut self.decompressor, input, end_of_input)?)
+    }
+
+    /// Decompress the pending input, as far as the output buffer allows.
+    ///
+    /// Returns whether progress was made.
+    fn decompress_pending(&mut self, end_of_input: bool) -> io::Result<bool> {
+        let pending = std::mem::take(&mut self.pending);
+        let result = self.decompress(&pending, end_of_input);
+        self.pending = pending;
+        let (consumed, produced) = result?;
+        self.pending.drain(..consumed);
+        if self.decompressor.is_done() {
+            self.pending.clear();
+        }
+        Ok(consumed > 0 || produced > 0)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncDecompressWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            ready!(this.poll_drain(cx))?;
+            if this.decompressor.is_done() {
+                return Poll::Ready(Ok(0));
+            }
+
+            if !this.pending.is_empty() {
+                if !this.decompress_pending(false)? {
+                    this.pending.extend_from_slice(buf);
+                    return Poll::Ready(Ok(buf.len()));
+                }
+                continue;
+            }
+
+            let accepted = this.decompressor.total_input();
+            let (consumed, produced) = this.decompress(buf, false)?;
+            if this.decompressor.is_done() {
+                // The decompressor may have read ahead past the end of the stream.
+                let stream_end = this.decompressor.byte_position();
+                return Poll::Ready(Ok(stream_end.saturating_sub(accepted) as usize));
+            } else if consumed > 0 {
+                return Poll::Ready(Ok(consumed));
+            } else if produced == 0 {
+                this.pending.extend_from_slice(buf);
+                return Poll::Ready(Ok(buf.len()));
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.writer).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            ready!(this.poll_drain(cx))?;
+            if this.decompressor.is_done() {
+                break;
+            } else if !this.decompress_pending(true)? {
+                return Poll::Ready(Err(DecompressionError::InsufficientInput.into()));
+            }
+        }
+        Pin::new(&mut this.writer).poll_close(cx)
+    }
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// Polls `f` until it completes, which works as long as everything it awaits is in memory.
+    fn poll_until_ready<T>(mut f: impl FnMut(&mut Context<'_>) -> Poll<T>) -> T {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(result) = f(&mut cx) {
+                return result;
+            }
+        }
+    }
+
+    /// An in-memory reader and writer that alternates between pending and handling one byte.
+    #[derive(Default)]
+    struct Trickle {
+        data: Vec<u8>,
+        position: usize,
+        ready: bool,
+    }
+
+    impl Trickle {
+        fn poll_turn(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+            self.ready = !self.ready;
+            if self.ready {
+                Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    impl AsyncRead for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            ready!(self.poll_turn(cx));
+            let n = buf.len().min(self.data.len() - self.position).min(1);
+            buf[..n].copy_from_slice(&self.data[self.position..][..n]);
+            self.position += n;
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl AsyncWrite for Trickle {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            ready!(self.poll_turn(cx));
+            let n = buf.len().min(1000);
+            self.data.extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn test_data() -> (Vec<u8>, Vec<u8>) {
+        let data = crate::test_utils::test_data(100_000);
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+        (data, compressed)
+    }
+
+    #[test]
+    fn async_reader() {
+        let (data, compressed) = test_data();
+        let trickle = Trickle {
+            data: compressed.clone(),
+            ..Trickle::default()
+        };
+        let mut reader = AsyncDecompressReader::new(trickle);
+        let mut output = Vec::new();
+        let mut buf = [0; 3000];
+        loop {
+            let n = poll_until_ready(|cx| Pin::new(&mut reader).poll_read(cx, &mut buf)).unwrap();
+            if n == 0 {
+                break;
+            }
+            output.extend_from_slice(&buf[..n]);
+        }
+        assert!(output == data);
+
+        let truncated = Trickle {
+            data: compressed[..compressed.len() / 2].to_vec(),
+            ..Trickle::default()
+        };
+        let mut reader = AsyncDecompressReader::new(truncated);
+        let err = loop {
+            match poll_until_ready(|cx| Pin::new(&mut reader).poll_read(cx, &mut buf)) {
+                Ok(n) => assert!(n > 0),
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn async_writer() {
+        let (data, compressed) = test_data();
+        for chunk_size in [1, 7, 5000, compressed.len()] {
+            let mut writer = AsyncDecompressWriter::new(Trickle::default());
+            for mut chunk in compressed.chunks(chunk_size) {
+                while !chunk.is_empty() {
+                    let n =
+                        poll_until_ready(|cx| Pin::new(&mut writer).poll_write(cx, chunk)).unwrap();
+                    assert!(n > 0);
+                    chunk = &chunk[n..];
+                }
+            }
+            poll_until_ready(|cx| Pin::new(&mut writer).poll_close(cx)).unwrap();
+            assert!(writer.into_inner().data == data);
+        }
+
+        let mut writer = AsyncDecompressWriter::new(Trickle::default());
+        let mut truncated = &compressed[..compressed.len() - 2];
+        while !truncated.is_empty() {
+            let n = poll_until_ready(|cx| Pin::new(&mut writer).poll_write(cx, truncated)).unwrap();
+            assert!(n > 0);
+            truncated = &truncated[n..];
+        }
+        let err = poll_until_ready(|cx| Pin::new(&mut writer).poll_close(cx)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
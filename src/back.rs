@@ -0,0 +1,115 @@
+use crate::decompress::WINDOW_SIZE;
+use crate::windowed::OutputWindow;
+use crate::{CustomDecompressor, DecompressionError};
+
+/// Decompress a whole zlib stream in a single pass, passing the output to a callback.
+///
+/// This is modeled on zlib's `inflateBack`. Instead of owning an output buffer, the caller provides
+/// `window`, which holds the 32 KiB of history needed to resolve back-references plus room for new
+/// output, and `output` is called with each chunk of decompressed data as soon as it is produced.
+/// Apart from the tables of `decompressor`, no other state is kept and nothing is allocated, which
+/// makes this the cheapest way to consume a complete stream with a fixed amount of memory.
+///
+/// `window` must be longer than 32 KiB, and its contents are overwritten. A length of at least
+/// `32 * 1024 + RECOMMENDED_OUTPUT_BUFFER_SIZE` avoids frequently moving the history around.
+/// `decompressor` must not have been used to decode anything yet.
+///
+/// Returns the number of bytes of `input` consumed, which is less than its length if there is
+/// data after the end of the stream. An error returned by `output` stops decompression and is
+/// passed through.
+///
+/// ```
+/// # #[cfg(feature = "compress")] {
+/// # use fdeflate::{inflate_back, Decompressor, DecompressionError};
+/// # let compressed = fdeflate::compress_to_vec(&[42; 100_000]);
+/// let mut window = vec![0; 64 * 1024];
+/// let mut total = 0;
+/// inflate_back(&mut Decompressor::new(), &compressed, &mut window, |chunk| {
+///     total += chunk.len();
+///     Ok::<_, DecompressionError>(())
+/// })
+/// .unwrap();
+/// assert_eq!(total, 100_000);
+/// # }
+/// ```
+///
+/// # Panics
+///
+/// Panics if `window` is not longer than 32 KiB.
+pub fn inflate_back<F, E, const LITLEN_TABLE_SIZE: usize>(
+    decompressor: &mut CustomDecompressor<LITLEN_TABLE_SIZE>,
+    input: &[u8],
+    window: &mut [u8],
+    mut output: F,
+) -> Result<usize, E>
+where
+    F: FnMut(&[u8]) -> Result<(), E>,
+    E: From<DecompressionError>,
+{
+    assert!(
+        window.len() > WINDOW_SIZE + 1,
+        "window must be longer than 32 KiB"
+    );
+
+    let mut window = OutputWindow::from_buffer(window);
+    let mut input_position = 0;
+    while !decompressor.is_done() {
+        let (consumed, produced) = window.decode(decompressor, &input[input_position..], true)?;
+        if produced > 0 {
+            output(window.buffered())?;
+            window.consume(produced);
+        }
+        input_position += consumed;
+    }
+    // The decoder may have read ahead past the end of the stream.
+    Ok(decompressor.byte_position() as usize)
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use super::*;
+    use crate::{Decompressor, SmallDecompressor};
+
+    #[test]
+    fn window_sizes() {
+        let data = crate::test_utils::test_data(300_000);
+        let mut compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+        compressed.extend_from_slice(b"trailer");
+
+        for window_size in [WINDOW_SIZE + 2, WINDOW_SIZE + 1000, 1 << 20] {
+            let mut window = vec![0xff; window_size];
+            let mut output = Vec::new();
+            let consumed = inflate_back(
+                &mut SmallDecompressor::new(),
+                &compressed,
+                &mut window,
+                |chunk| {
+                    output.extend_from_slice(chunk);
+                    Ok::<_, DecompressionError>(())
+                },
+            )
+            .unwrap();
+            assert_eq!(consumed, compressed.len() - b"trailer".len());
+            assert!(output == data);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let compressed = crate::compress_to_vec(&[7; 100_000]);
+        let mut window = vec![0; 64 * 1024];
+
+        let result = inflate_back(
+            &mut Decompressor::new(),
+            &compressed[..compressed.len() - 3],
+            &mut window,
+            |_| Ok::<_, DecompressionError>(()),
+        );
+        assert!(matches!(result, Err(DecompressionError::InsufficientInput)));
+
+        let result = inflate_back(&mut Decompressor::new(), &compressed, &mut window, |_| {
+            Err(std::io::Error::new(std::io::ErrorKind::Other, "stop"))
+        });
+        assert_eq!(result.unwrap_err().to_string(), "stop");
+    }
+}
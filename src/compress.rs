@@ -1,19 +1,108 @@
 use simd_adler32::Adler32;
 use std::{
     convert::TryInto,
-    io::{self, Seek, SeekFrom, Write},
+    io::{self, IoSlice, Seek, SeekFrom, Write},
+    mem,
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Arc,
 };
 
+use crate::huffman::HuffmanTable;
 use crate::tables::{
-    BITMASKS, HUFFMAN_CODES, HUFFMAN_LENGTHS, LENGTH_TO_LEN_EXTRA, LENGTH_TO_SYMBOL,
+    BITMASKS, DIST_SYM_TO_DIST_BASE, DIST_SYM_TO_DIST_EXTRA, LENGTH_TO_LEN_EXTRA, LENGTH_TO_SYMBOL,
+    LEN_SYM_TO_LEN_BASE, LEN_SYM_TO_LEN_EXTRA,
 };
 
+/// Size of each output chunk buffered by [`Compressor`] before it is handed to the writer.
+const OUTPUT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Number of full output chunks that [`Compressor`] buffers before passing them to the writer.
+const MAX_PENDING_CHUNKS: usize = 8;
+
+/// Write every byte of `bufs` using vectored writes.
+fn write_all_vectored<W: Write>(writer: &mut W, bufs: &[&[u8]]) -> io::Result<()> {
+    let mut slices: Vec<IoSlice> = bufs.iter().map(|buf| IoSlice::new(buf)).collect();
+    let mut index = 0;
+    let mut offset = 0;
+    loop {
+        // Skip over any fully written (or empty) slices.
+        while index < bufs.len() && offset >= bufs[index].len() {
+            offset -= bufs[index].len();
+            index += 1;
+        }
+        if index == bufs.len() {
+            return Ok(());
+        }
+
+        // Only the first unwritten slice needs to change after a partial write.
+        slices[index] = IoSlice::new(&bufs[index][offset..]);
+        match writer.write_vectored(&slices[index..]) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => offset += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Progress of a [`Compressor`], as reported to its progress callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionProgress {
+    /// Number of uncompressed bytes passed to the compressor so far.
+    pub bytes_consumed: u64,
+    /// Number of compressed bytes produced so far, including any still buffered internally.
+    pub bytes_emitted: u64,
+}
+impl CompressionProgress {
+    /// Returns the ratio of compressed to uncompressed size so far, or 1.0 if no input has been
+    /// consumed yet.
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_consumed == 0 {
+            1.0
+        } else {
+            self.bytes_emitted as f64 / self.bytes_consumed as f64
+        }
+    }
+}
+
+/// A registered progress callback, see [`Compressor::set_progress_callback`].
+struct ProgressCallback {
+    callback: Box<dyn FnMut(CompressionProgress) + Send>,
+    interval: u64,
+    next_report: u64,
+}
+
 /// Compressor that produces fdeflate compressed streams.
+///
+/// Compressed bytes are accumulated in a list of fixed size chunks rather than one contiguous
+/// buffer. Whenever enough output is pending (and on [`Compressor::flush`] or
+/// [`Compressor::finish`]), all chunks are passed to the writer in a single
+/// [`Write::write_vectored`] call, so writers backed by sockets or files can emit them with one
+/// `writev` and without another copy.
+///
+/// # Thread safety
+///
+/// A compressor is `Send` whenever its writer is, so a partially written stream can be moved to
+/// another worker thread. It is not `Sync`, because the progress callback is only required to be
+/// `Send`; all writing methods take `&mut self`, so sharing one between threads has no use.
 pub struct Compressor<W: Write> {
     checksum: Adler32,
     buffer: u64,
     nbits: u8,
+    chunks: Vec<Vec<u8>>,
+    output: Vec<u8>,
     writer: W,
+    bytes_consumed: u64,
+    /// Number of bytes passed to the writer so far.
+    bytes_written: u64,
+    progress: Option<ProgressCallback>,
+    cancellation_flag: Option<Arc<AtomicBool>>,
+    /// Whether a dynamic block has been started and not yet ended.
+    in_block: bool,
+    table: HuffmanTable,
+    /// Whether the stream is raw deflate, without the zlib header and checksum.
+    raw: bool,
 }
 impl<W: Write> Compressor<W> {
     fn write_bits(&mut self, bits: u64, nbits: u8) -> io::Result<()> {
@@ -23,7 +112,10 @@ impl<W: Write> Compressor<W> {
         self.nbits += nbits;
 
         if self.nbits >= 64 {
-            self.writer.write_all(&self.buffer.to_le_bytes())?;
+            self.output.extend_from_slice(&self.buffer.to_le_bytes());
+            if self.output.len() >= OUTPUT_CHUNK_SIZE {
+                self.push_chunk()?;
+            }
             self.nbits -= 64;
             self.buffer = bits.checked_shr((nbits - self.nbits) as u32).unwrap_or(0);
         }
@@ -31,39 +123,79 @@ impl<W: Write> Compressor<W> {
         Ok(())
     }
 
-    fn flush(&mut self) -> io::Result<()> {
+    /// Move the full output buffer to the list of pending chunks, and pass them to the writer once
+    /// [`MAX_PENDING_CHUNKS`] have accumulated.
+    fn push_chunk(&mut self) -> io::Result<()> {
+        let chunk = mem::replace(&mut self.output, Vec::with_capacity(OUTPUT_CHUNK_SIZE));
+        self.chunks.push(chunk);
+        self.check_cancelled()?;
+        if self.chunks.len() >= MAX_PENDING_CHUNKS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn align_to_byte(&mut self) -> io::Result<()> {
         if self.nbits % 8 != 0 {
             self.write_bits(0, 8 - self.nbits % 8)?;
         }
         if self.nbits > 0 {
-            self.writer
-                .write_all(&self.buffer.to_le_bytes()[..self.nbits as usize / 8])
-                .unwrap();
+            self.output
+                .extend_from_slice(&self.buffer.to_le_bytes()[..self.nbits as usize / 8]);
             self.buffer = 0;
             self.nbits = 0;
         }
         Ok(())
     }
 
+    /// Pass all buffered output to the writer.
+    ///
+    /// The pending chunks are emitted as a list of [`IoSlice`]s via [`Write::write_vectored`].
+    /// Up to 63 bits that don't yet form complete output words stay buffered inside the
+    /// compressor, so this does not end the stream or make all input so far decodable.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let bufs: Vec<&[u8]> = self
+            .chunks
+            .iter()
+            .map(|chunk| &chunk[..])
+            .chain(std::iter::once(&self.output[..]))
+            .collect();
+        write_all_vectored(&mut self.writer, &bufs)?;
+        self.bytes_written += bufs.iter().map(|buf| buf.len() as u64).sum::<u64>();
+        self.chunks.clear();
+        self.output.clear();
+        Ok(())
+    }
+
+    fn write_literal(&mut self, b: u8) -> io::Result<()> {
+        self.write_bits(
+            self.table.codes[b as usize] as u64,
+            self.table.lengths[b as usize],
+        )
+    }
+
     fn write_run(&mut self, mut run: u32) -> io::Result<()> {
-        self.write_bits(HUFFMAN_CODES[0] as u64, HUFFMAN_LENGTHS[0])?;
+        self.write_literal(0)?;
         run -= 1;
 
         while run >= 258 {
-            self.write_bits(HUFFMAN_CODES[285] as u64, HUFFMAN_LENGTHS[285] + 1)?;
+            self.write_bits(self.table.codes[285] as u64, self.table.lengths[285] + 1)?;
             run -= 258;
         }
 
         if run > 4 {
             let sym = LENGTH_TO_SYMBOL[run as usize - 3] as usize;
-            self.write_bits(HUFFMAN_CODES[sym] as u64, HUFFMAN_LENGTHS[sym])?;
+            self.write_bits(self.table.codes[sym] as u64, self.table.lengths[sym])?;
 
             let len_extra = LENGTH_TO_LEN_EXTRA[run as usize - 3];
             let extra = ((run - 3) & BITMASKS[len_extra as usize]) as u64;
             self.write_bits(extra, len_extra + 1)?;
+        } else if self.table.codes[0] == 0 {
+            self.write_bits(0, run as u8 * self.table.lengths[0])?;
         } else {
-            debug_assert_eq!(HUFFMAN_CODES[0], 0);
-            self.write_bits(0, run as u8 * HUFFMAN_LENGTHS[0])?;
+            for _ in 0..run {
+                self.write_literal(0)?;
+            }
         }
 
         Ok(())
@@ -71,24 +203,98 @@ impl<W: Write> Compressor<W> {
 
     /// Create a new Compressor.
     pub fn new(writer: W) -> io::Result<Self> {
-        let mut compressor = Self {
+        Self::with_table(writer, &HuffmanTable::default())
+    }
+
+    /// Create a new Compressor that encodes literals and runs with the codes of `table`.
+    ///
+    /// Streams compressed with a table other than the default one are not recognized by
+    /// [`is_fdeflate_stream`](crate::is_fdeflate_stream), and [`compress_bound`] doesn't apply
+    /// to them.
+    pub fn with_table(writer: W, table: &HuffmanTable) -> io::Result<Self> {
+        Self::with_table_and_header(writer, table, ZlibHeader::FDEFLATE)
+    }
+
+    /// Create a new Compressor that starts the stream with `header` instead of
+    /// [`ZlibHeader::FDEFLATE`].
+    ///
+    /// This is for tools that expect particular values in the header. Since the header has no
+    /// effect on the compressed data, any window size is accepted by decompressors that support
+    /// it. Returns an error of kind [`io::ErrorKind::InvalidInput`] if a field is out of range.
+    pub fn with_zlib_header(writer: W, header: ZlibHeader) -> io::Result<Self> {
+        if !(8..=15).contains(&header.window_bits) || header.level > 3 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "zlib header fields out of range",
+            ));
+        }
+        Self::with_table_and_header(writer, &HuffmanTable::default(), header)
+    }
+
+    /// Create a new Compressor that writes a raw deflate stream, without the zlib header and
+    /// Adler-32 checksum.
+    ///
+    /// This is the format of the compressed data in gzip members and zip entries, which frame
+    /// the stream with their own headers and checksums.
+    pub fn new_raw(writer: W) -> io::Result<Self> {
+        let mut compressor = Self::without_headers(writer);
+        compressor.raw = true;
+        compressor.output.reserve(OUTPUT_CHUNK_SIZE);
+        compressor.write_block_header()?;
+        Ok(compressor)
+    }
+
+    fn with_table_and_header(
+        writer: W,
+        table: &HuffmanTable,
+        header: ZlibHeader,
+    ) -> io::Result<Self> {
+        let mut compressor = Self::without_headers(writer);
+        compressor.output.reserve(OUTPUT_CHUNK_SIZE);
+        compressor.table = table.clone();
+        compressor.write_bits(u16::from_le_bytes(header.to_bytes(false)) as u64, 16)?;
+        compressor.write_block_header()?;
+        Ok(compressor)
+    }
+
+    fn without_headers(writer: W) -> Self {
+        Self {
             checksum: Adler32::new(),
             buffer: 0,
             nbits: 0,
+            chunks: Vec::new(),
+            output: Vec::new(),
             writer,
-        };
-        compressor.write_headers()?;
-        Ok(compressor)
+            bytes_consumed: 0,
+            bytes_written: 0,
+            progress: None,
+            cancellation_flag: None,
+            in_block: false,
+            table: HuffmanTable::default(),
+            raw: false,
+        }
     }
 
-    fn write_headers(&mut self) -> io::Result<()> {
-        self.write_bits(0x0178, 16)?; // zlib header
+    /// Add uncompressed data to the checksum, unless the stream is raw and has none.
+    fn update_checksum(&mut self, data: &[u8]) {
+        if !self.raw {
+            self.checksum.write(data);
+        }
+    }
+
+    fn write_block_header(&mut self) -> io::Result<()> {
+        self.in_block = true;
+        self.write_dynamic_header(false, &[1])
+    }
 
-        self.write_bits(0b1, 1)?; // BFINAL
+    /// Write the header of a dynamic block using the literal/length code lengths of the table and
+    /// the given distance code lengths, each of which must be at most 15.
+    fn write_dynamic_header(&mut self, last: bool, dist_lengths: &[u8]) -> io::Result<()> {
+        self.write_bits(last as u64, 1)?; // BFINAL
         self.write_bits(0b10, 2)?; // Dynamic Huffman block
 
-        self.write_bits((HUFFMAN_LENGTHS.len() - 257) as u64, 5)?; // # of length / literal codes
-        self.write_bits(0, 5)?; // 1 distance code
+        self.write_bits((self.table.lengths.len() - 257) as u64, 5)?; // # of length / literal codes
+        self.write_bits((dist_lengths.len() - 1) as u64, 5)?; // # of distance codes
         self.write_bits(15, 4)?; // 16 code length codes
 
         // Write code lengths for code length alphabet
@@ -100,109 +306,558 @@ impl<W: Write> Compressor<W> {
         }
 
         // Write code lengths for length/literal alphabet
-        for &len in &HUFFMAN_LENGTHS {
+        for i in 0..self.table.lengths.len() {
+            let len = self.table.lengths[i];
             self.write_bits((len.reverse_bits() >> 4) as u64, 4)?;
         }
 
         // Write code lengths for distance alphabet
-        for _ in 0..1 {
-            self.write_bits(0b1000, 4)?;
+        for &len in dist_lengths {
+            self.write_bits((len.reverse_bits() >> 4) as u64, 4)?;
         }
 
         Ok(())
     }
 
+    /// Returns the Huffman table used to encode literals and runs.
+    pub fn table(&self) -> &HuffmanTable {
+        &self.table
+    }
+
+    /// Register a callback that reports the progress of compression.
+    ///
+    /// The callback is invoked at the end of each call to [`Compressor::write_data`] once at least
+    /// `interval` bytes of input have been consumed since the previous report, and once more by
+    /// [`Compressor::finish`]. It doesn't affect the compressed output.
+    pub fn set_progress_callback<F>(&mut self, interval: u64, callback: F)
+    where
+        F: FnMut(CompressionProgress) + Send + 'static,
+    {
+        self.progress = Some(ProgressCallback {
+            callback: Box::new(callback),
+            interval,
+            next_report: 0,
+        });
+    }
+
+    /// Abort compression once `flag` is set.
+    ///
+    /// The flag is checked at the start of every call to [`Compressor::write_data`] and after
+    /// every 64 KiB of compressed output, so another thread can stop a single large write
+    /// promptly. Once cancelled, writes fail with an error of kind [`io::ErrorKind::Other`]
+    /// and the compressor can't be used any further.
+    pub fn set_cancellation_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.cancellation_flag = Some(flag);
+    }
+
+    fn check_cancelled(&self) -> io::Result<()> {
+        match &self.cancellation_flag {
+            Some(flag) if flag.load(Ordering::Relaxed) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "compression cancelled",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the current progress of the compressor.
+    pub fn progress(&self) -> CompressionProgress {
+        let buffered: usize = self.chunks.iter().map(Vec::len).sum::<usize>()
+            + self.output.len()
+            + self.nbits as usize / 8;
+        CompressionProgress {
+            bytes_consumed: self.bytes_consumed,
+            bytes_emitted: self.bytes_written + buffered as u64,
+        }
+    }
+
+    fn report_progress(&mut self, force: bool) {
+        let progress = self.progress();
+        if let Some(callback) = &mut self.progress {
+            if force || progress.bytes_consumed >= callback.next_report {
+                (callback.callback)(progress);
+                callback.next_report = progress
+                    .bytes_consumed
+                    .saturating_add(callback.interval.max(1));
+            }
+        }
+    }
+
     /// Write data to the compressor.
     pub fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
-        self.checksum.write(data);
+        self.write_data_vectored(&[data])
+    }
 
-        let mut run = 0;
-        let mut chunks = data.chunks_exact(8);
-        for chunk in &mut chunks {
-            let ichunk = u64::from_le_bytes(chunk.try_into().unwrap());
-
-            if ichunk == 0 {
-                run += 8;
-                continue;
-            } else if run > 0 {
-                let run_extra = ichunk.trailing_zeros() / 8;
-                self.write_run(run + run_extra)?;
-                run = 0;
+    /// Write the concatenation of several slices to the compressor.
+    ///
+    /// This produces exactly the same output as a single call to [`Compressor::write_data`] with
+    /// the slices concatenated, without having to copy them into one buffer first. Separate calls
+    /// to `write_data` for each slice may compress slightly worse, since runs of zeros aren't
+    /// joined across calls.
+    pub fn write_data_vectored(&mut self, data: &[&[u8]]) -> io::Result<()> {
+        self.check_cancelled()?;
+        let len: usize = data.iter().map(|fragment| fragment.len()).sum();
+        if !self.in_block && len != 0 {
+            self.write_block_header()?;
+        }
+        for fragment in data {
+            self.update_checksum(fragment);
+        }
+        self.bytes_consumed += len as u64;
 
-                if run_extra > 0 {
-                    run = ichunk.leading_zeros() / 8;
-                    for &b in &chunk[run_extra as usize..8 - run as usize] {
-                        self.write_bits(
-                            HUFFMAN_CODES[b as usize] as u64,
-                            HUFFMAN_LENGTHS[b as usize],
-                        )?;
-                    }
+        let mut run = 0;
+        // Bytes from the end of the previous slice that don't make up a whole chunk yet.
+        let mut pending = [0; 8];
+        let mut num_pending = 0;
+        for &fragment in data {
+            let mut fragment = fragment;
+            if num_pending > 0 {
+                let n = (8 - num_pending).min(fragment.len());
+                pending[num_pending..][..n].copy_from_slice(&fragment[..n]);
+                num_pending += n;
+                fragment = &fragment[n..];
+                if num_pending < 8 {
                     continue;
                 }
+                self.write_chunk(&pending, &mut run)?;
             }
 
-            let run_start = ichunk.leading_zeros() / 8;
-            if run_start > 0 {
-                for &b in &chunk[..8 - run_start as usize] {
-                    self.write_bits(
-                        HUFFMAN_CODES[b as usize] as u64,
-                        HUFFMAN_LENGTHS[b as usize],
-                    )?;
-                }
-                run = run_start;
-                continue;
+            let mut chunks = fragment.chunks_exact(8);
+            for chunk in &mut chunks {
+                self.write_chunk(chunk, &mut run)?;
+            }
+            let remainder = chunks.remainder();
+            pending[..remainder.len()].copy_from_slice(remainder);
+            num_pending = remainder.len();
+        }
+
+        self.end_write(run, &pending[..num_pending])
+    }
+
+    /// Write 16-bit values to the compressor, serializing each one as two big-endian bytes.
+    ///
+    /// This produces the same output as passing the big-endian bytes to
+    /// [`Compressor::write_data`], but converts them a small block at a time as they are
+    /// compressed, so encoders of 16-bit images don't need a byte-swapped copy of the whole image.
+    pub fn write_data_u16_be(&mut self, data: &[u16]) -> io::Result<()> {
+        self.check_cancelled()?;
+        if !self.in_block && !data.is_empty() {
+            self.write_block_header()?;
+        }
+        self.bytes_consumed += data.len() as u64 * 2;
+
+        let mut run = 0;
+        let mut buffer = [0; 2048];
+        let mut pending: &[u8] = &[];
+        // Every block but the last is a whole number of 8 byte chunks, so the input is split into
+        // chunks exactly as if it had been converted all at once.
+        for values in data.chunks(buffer.len() / 2) {
+            let bytes = &mut buffer[..values.len() * 2];
+            for (pair, value) in bytes.chunks_exact_mut(2).zip(values) {
+                pair.copy_from_slice(&value.to_be_bytes());
             }
+            self.update_checksum(bytes);
+
+            let mut chunks = bytes.chunks_exact(8);
+            for chunk in &mut chunks {
+                self.write_chunk(chunk, &mut run)?;
+            }
+            pending = chunks.remainder();
+        }
+        let mut remainder = [0; 8];
+        remainder[..pending.len()].copy_from_slice(pending);
+        self.end_write(run, &remainder[..pending.len()])
+    }
+
+    /// Write `len` zero bytes to the compressor.
+    ///
+    /// See [`Compressor::write_repeated_byte`].
+    pub fn write_zero_run(&mut self, len: u64) -> io::Result<()> {
+        self.write_repeated_byte(0, len)
+    }
+
+    /// Write `len` copies of `byte` to the compressor.
+    ///
+    /// The run is encoded directly as a literal followed by back-references, without scanning
+    /// any input, so callers that already know about long constant runs (like blank image rows
+    /// or holes in sparse files) neither need to materialize them nor pay for finding them. Runs
+    /// aren't joined with data from other writes.
+    pub fn write_repeated_byte(&mut self, byte: u8, len: u64) -> io::Result<()> {
+        self.check_cancelled()?;
+        if len == 0 {
+            return Ok(());
+        }
+        if !self.in_block {
+            self.write_block_header()?;
+        }
+        let block = [byte; 4096];
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(block.len() as u64) as usize;
+            self.update_checksum(&block[..n]);
+            remaining -= n as u64;
+        }
+        self.bytes_consumed += len;
 
-            let n0 = HUFFMAN_LENGTHS[chunk[0] as usize];
-            let n1 = HUFFMAN_LENGTHS[chunk[1] as usize];
-            let n2 = HUFFMAN_LENGTHS[chunk[2] as usize];
-            let n3 = HUFFMAN_LENGTHS[chunk[3] as usize];
-            let bits = HUFFMAN_CODES[chunk[0] as usize] as u64
-                | ((HUFFMAN_CODES[chunk[1] as usize] as u64) << n0)
-                | ((HUFFMAN_CODES[chunk[2] as usize] as u64) << (n0 + n1))
-                | ((HUFFMAN_CODES[chunk[3] as usize] as u64) << (n0 + n1 + n2));
-            self.write_bits(bits, n0 + n1 + n2 + n3)?;
-
-            let n4 = HUFFMAN_LENGTHS[chunk[4] as usize];
-            let n5 = HUFFMAN_LENGTHS[chunk[5] as usize];
-            let n6 = HUFFMAN_LENGTHS[chunk[6] as usize];
-            let n7 = HUFFMAN_LENGTHS[chunk[7] as usize];
-            let bits2 = HUFFMAN_CODES[chunk[4] as usize] as u64
-                | ((HUFFMAN_CODES[chunk[5] as usize] as u64) << n4)
-                | ((HUFFMAN_CODES[chunk[6] as usize] as u64) << (n4 + n5))
-                | ((HUFFMAN_CODES[chunk[7] as usize] as u64) << (n4 + n5 + n6));
-            self.write_bits(bits2, n4 + n5 + n6 + n7)?;
+        self.write_literal(byte)?;
+        let mut run = len - 1;
+        while run >= 258 {
+            self.write_bits(self.table.codes[285] as u64, self.table.lengths[285] + 1)?;
+            run -= 258;
+        }
+        // Encode the rest as a back-reference or as literals, whichever is shorter.
+        if run >= 3 {
+            let sym = LENGTH_TO_SYMBOL[run as usize - 3] as usize;
+            let len_extra = LENGTH_TO_LEN_EXTRA[run as usize - 3];
+            let match_bits = (self.table.lengths[sym] + len_extra + 1) as u64;
+            if match_bits < run * self.table.lengths[byte as usize] as u64 {
+                self.write_bits(self.table.codes[sym] as u64, self.table.lengths[sym])?;
+                let extra = ((run as u32 - 3) & BITMASKS[len_extra as usize]) as u64;
+                self.write_bits(extra, len_extra + 1)?;
+                run = 0;
+            }
         }
+        for _ in 0..run {
+            self.write_literal(byte)?;
+        }
+        self.end_write(0, &[])
+    }
 
+    /// Finish a write by encoding the pending run of zeros and the last few bytes of input.
+    fn end_write(&mut self, run: u32, remainder: &[u8]) -> io::Result<()> {
         if run > 0 {
             self.write_run(run)?;
         }
 
-        for &b in chunks.remainder() {
+        for &b in remainder {
             self.write_bits(
-                HUFFMAN_CODES[b as usize] as u64,
-                HUFFMAN_LENGTHS[b as usize],
+                self.table.codes[b as usize] as u64,
+                self.table.lengths[b as usize],
             )?;
         }
 
+        if !self.chunks.is_empty() {
+            self.flush()?;
+        }
+        self.report_progress(false);
+
+        Ok(())
+    }
+
+    /// Encode eight bytes of input, where `run` is the length of the run of zeros before them
+    /// that hasn't been written yet.
+    #[inline(always)]
+    fn write_chunk(&mut self, chunk: &[u8], run: &mut u32) -> io::Result<()> {
+        let ichunk = u64::from_le_bytes(chunk.try_into().unwrap());
+
+        if ichunk == 0 {
+            *run += 8;
+            return Ok(());
+        } else if *run > 0 {
+            let run_extra = ichunk.trailing_zeros() / 8;
+            self.write_run(*run + run_extra)?;
+            *run = 0;
+
+            if run_extra > 0 {
+                *run = ichunk.leading_zeros() / 8;
+                for &b in &chunk[run_extra as usize..8 - *run as usize] {
+                    self.write_bits(
+                        self.table.codes[b as usize] as u64,
+                        self.table.lengths[b as usize],
+                    )?;
+                }
+                return Ok(());
+            }
+        }
+
+        let run_start = ichunk.leading_zeros() / 8;
+        if run_start > 0 {
+            for &b in &chunk[..8 - run_start as usize] {
+                self.write_bits(
+                    self.table.codes[b as usize] as u64,
+                    self.table.lengths[b as usize],
+                )?;
+            }
+            *run = run_start;
+            return Ok(());
+        }
+
+        let n0 = self.table.lengths[chunk[0] as usize];
+        let n1 = self.table.lengths[chunk[1] as usize];
+        let n2 = self.table.lengths[chunk[2] as usize];
+        let n3 = self.table.lengths[chunk[3] as usize];
+        let bits = self.table.codes[chunk[0] as usize] as u64
+            | ((self.table.codes[chunk[1] as usize] as u64) << n0)
+            | ((self.table.codes[chunk[2] as usize] as u64) << (n0 + n1))
+            | ((self.table.codes[chunk[3] as usize] as u64) << (n0 + n1 + n2));
+        self.write_bits(bits, n0 + n1 + n2 + n3)?;
+
+        let n4 = self.table.lengths[chunk[4] as usize];
+        let n5 = self.table.lengths[chunk[5] as usize];
+        let n6 = self.table.lengths[chunk[6] as usize];
+        let n7 = self.table.lengths[chunk[7] as usize];
+        let bits2 = self.table.codes[chunk[4] as usize] as u64
+            | ((self.table.codes[chunk[5] as usize] as u64) << n4)
+            | ((self.table.codes[chunk[6] as usize] as u64) << (n4 + n5))
+            | ((self.table.codes[chunk[7] as usize] as u64) << (n4 + n5 + n6));
+        self.write_bits(bits2, n4 + n5 + n6 + n7)?;
+
+        Ok(())
+    }
+
+    /// Write `data` as stored blocks, bypassing the entropy coder.
+    ///
+    /// This is meant for ranges that are already compressed or otherwise known not to compress,
+    /// whose stored encoding only adds five bytes per 64 KiB. The current block is ended, and a
+    /// new one started by the next call to [`Compressor::write_data`], which costs about 150
+    /// bytes for its header. Output written this way isn't covered by [`compress_bound`].
+    pub fn write_stored(&mut self, data: &[u8]) -> io::Result<()> {
+        self.check_cancelled()?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.update_checksum(data);
+        self.bytes_consumed += data.len() as u64;
+
+        self.end_block()?;
+        for block in data.chunks(u16::MAX as usize) {
+            self.write_bits(0b000, 3)?; // BFINAL = 0, stored block
+            self.align_to_byte()?;
+            let len = block.len() as u16;
+            self.output.extend_from_slice(&len.to_le_bytes());
+            self.output.extend_from_slice(&(!len).to_le_bytes());
+            for piece in block.chunks(OUTPUT_CHUNK_SIZE) {
+                self.output.extend_from_slice(piece);
+                if self.output.len() >= OUTPUT_CHUNK_SIZE {
+                    self.push_chunk()?;
+                }
+            }
+        }
+
+        if !self.chunks.is_empty() {
+            self.flush()?;
+        }
+        self.report_progress(false);
+        Ok(())
+    }
+
+    /// End the current block with a full flush, and pass all output to the writer.
+    ///
+    /// This is zlib's `Z_FULL_FLUSH`: the stream is padded to a byte boundary with an empty
+    /// stored block. Since the compressor never refers back to data from before the flush,
+    /// decompression can start right after it without any preceding data, as done by
+    /// [`SeekableCompressor`]. Returns the number of compressed bytes written so far, which is the
+    /// offset of the next block. A new block is started by the next call to
+    /// [`Compressor::write_data`], which costs about 150 bytes for its header.
+    pub fn full_flush(&mut self) -> io::Result<u64> {
+        self.end_block()?;
+        self.write_bits(0b000, 3)?; // BFINAL = 0, stored block
+        self.align_to_byte()?;
+        self.output.extend_from_slice(&[0, 0, 0xff, 0xff]);
+        self.flush()?;
+        Ok(self.bytes_written)
+    }
+
+    fn end_block(&mut self) -> io::Result<()> {
+        if self.in_block {
+            self.write_bits(self.table.codes[256] as u64, self.table.lengths[256])?;
+            self.in_block = false;
+        }
         Ok(())
     }
 
     /// Write the remainder of the stream and return the inner writer.
     pub fn finish(mut self) -> io::Result<W> {
-        // Write end of block
-        self.write_bits(HUFFMAN_CODES[256] as u64, HUFFMAN_LENGTHS[256])?;
-        self.flush()?;
+        self.end_block()?;
+        // The blocks written so far aren't final, so end with an empty final fixed Huffman block.
+        self.write_bits(0b011, 10)?;
+        self.write_trailer()
+    }
+
+    fn write_trailer(mut self) -> io::Result<W> {
+        self.align_to_byte()?;
 
         // Write Adler32 checksum
-        let checksum: u32 = self.checksum.finish();
-        self.writer
-            .write_all(checksum.to_be_bytes().as_ref())
-            .unwrap();
+        if !self.raw {
+            let checksum: u32 = self.checksum.finish();
+            self.output.extend_from_slice(&checksum.to_be_bytes());
+        }
+        self.flush()?;
+        self.report_progress(true);
         Ok(self.writer)
     }
 }
 
+/// Low-level writer for zlib streams whose blocks are encoded by the caller.
+///
+/// This is an escape hatch for tools that build their own deflate blocks, like PNG recompressors
+/// or deflate stream editors. The zlib header is written on creation; after that, block headers
+/// and symbols are written with [`BlockWriter::write_bits`] using the same buffered bit writer as
+/// [`Compressor`]. The caller is responsible for producing valid blocks, including marking the
+/// last one as final, while the writer keeps track of the bit position and the Adler-32 checksum
+/// of the uncompressed data passed to [`BlockWriter::update_checksum`].
+///
+/// ```
+/// # use fdeflate::BlockWriter;
+/// let mut writer = BlockWriter::new(Vec::new()).unwrap();
+/// writer.write_bits(0b011, 3).unwrap(); // BFINAL = 1, fixed Huffman block
+/// writer.write_bits(0b1000_1110, 8).unwrap(); // literal 'A'
+/// writer.write_bits(0, 7).unwrap(); // end of block
+/// writer.update_checksum(b"A");
+/// let compressed = writer.finish().unwrap();
+/// # #[cfg(feature = "decompress")]
+/// assert_eq!(fdeflate::decompress_to_vec(&compressed).unwrap(), b"A");
+/// ```
+pub struct BlockWriter<W: Write> {
+    inner: Compressor<W>,
+}
+impl<W: Write> BlockWriter<W> {
+    /// Create a new writer and write the zlib header.
+    pub fn new(writer: W) -> io::Result<Self> {
+        let mut inner = Compressor::without_headers(writer);
+        let header = ZlibHeader {
+            window_bits: 15,
+            level: 0,
+        };
+        inner.write_bits(u16::from_le_bytes(header.to_bytes(false)) as u64, 16)?;
+        Ok(Self { inner })
+    }
+
+    /// Write the low `nbits` bits of `bits`, least significant bit first.
+    ///
+    /// As in the deflate format, Huffman codes must be passed with their bits reversed so that
+    /// their first bit is the least significant one. Any bits of `bits` above `nbits` are ignored.
+    pub fn write_bits(&mut self, bits: u64, nbits: u8) -> io::Result<()> {
+        assert!(nbits <= 64);
+        let bits = bits & u64::MAX.checked_shr(64 - nbits as u32).unwrap_or(0);
+        self.inner.write_bits(bits, nbits)?;
+        if self.inner.chunks.len() > 1 {
+            self.inner.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Pad the output with zero bits up to the next byte boundary, as required before the
+    /// length fields of a stored block.
+    pub fn align_to_byte(&mut self) -> io::Result<()> {
+        self.inner.align_to_byte()
+    }
+
+    /// Returns the number of bits written so far, including the zlib header.
+    pub fn bit_position(&self) -> u64 {
+        let bytes = self.inner.bytes_written
+            + self
+                .inner
+                .chunks
+                .iter()
+                .map(|c| c.len() as u64)
+                .sum::<u64>()
+            + self.inner.output.len() as u64;
+        bytes * 8 + self.inner.nbits as u64
+    }
+
+    /// Add `data` to the checksum of the uncompressed data.
+    ///
+    /// Every byte that the written blocks decompress to must be passed here, in order, for the
+    /// checksum written by [`BlockWriter::finish`] to be correct.
+    pub fn update_checksum(&mut self, data: &[u8]) {
+        self.inner.checksum.write(data);
+        self.inner.bytes_consumed += data.len() as u64;
+    }
+
+    /// Pass all complete bytes written so far to the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Pad the last block to a byte boundary, write the Adler-32 checksum and return the
+    /// underlying writer.
+    ///
+    /// This doesn't write an end of block symbol or a final block; the blocks written so far must
+    /// already form a complete deflate stream.
+    pub fn finish(self) -> io::Result<W> {
+        self.inner.write_trailer()
+    }
+}
+
+/// Compressor for many small buffers that share one Huffman table.
+///
+/// For inputs of a few KiB, the roughly 150 byte header of each stream and the time spent
+/// writing it dominate. A batch compressor encodes the headers once up front, and then only
+/// copies them for each buffer in [`BatchCompressor::compress`].
+///
+/// With a custom framing that stores the header once for the whole batch,
+/// [`BatchCompressor::compress_without_header`] omits it altogether: concatenating
+/// [`BatchCompressor::header`] with such an output gives the same zlib stream as `compress`.
+///
+/// ```
+/// # use fdeflate::{BatchCompressor, HuffmanTable};
+/// let payloads: Vec<Vec<u8>> = (0..100)
+///     .map(|i| format!("payload {}", i).repeat(50).into_bytes())
+///     .collect();
+/// let samples: Vec<&[u8]> = payloads.iter().take(10).map(|p| &p[..]).collect();
+/// let batch = BatchCompressor::new(&HuffmanTable::from_samples(&samples, 12));
+///
+/// for payload in &payloads {
+///     let body = batch.compress_without_header(payload);
+///     let stream = [batch.header(), &body].concat();
+/// #     #[cfg(feature = "decompress")]
+///     assert_eq!(fdeflate::decompress_to_vec(&stream).unwrap(), *payload);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct BatchCompressor {
+    table: HuffmanTable,
+    /// The complete bytes of the zlib and block headers.
+    header: Vec<u8>,
+    /// The remaining bits of the headers, which share a byte with the first symbol.
+    header_bits: u64,
+    header_nbits: u8,
+}
+impl BatchCompressor {
+    /// Create a batch compressor that encodes every buffer with `table`.
+    pub fn new(table: &HuffmanTable) -> Self {
+        let mut compressor = Compressor::with_table(Vec::new(), table).unwrap();
+        compressor.flush().unwrap();
+        let mut header = compressor.writer;
+        let complete_bytes = compressor.nbits / 8;
+        header.extend_from_slice(&compressor.buffer.to_le_bytes()[..complete_bytes as usize]);
+
+        Self {
+            table: table.clone(),
+            header,
+            header_bits: compressor.buffer >> (complete_bytes * 8),
+            header_nbits: compressor.nbits % 8,
+        }
+    }
+
+    /// Returns the bytes that all streams produced by [`BatchCompressor::compress`] start with.
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    /// Compress `data` into a complete zlib stream.
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        self.compress_after(self.header.clone(), data)
+    }
+
+    /// Compress `data` into a zlib stream without the bytes of [`BatchCompressor::header`].
+    pub fn compress_without_header(&self, data: &[u8]) -> Vec<u8> {
+        self.compress_after(Vec::new(), data)
+    }
+
+    fn compress_after(&self, mut writer: Vec<u8>, data: &[u8]) -> Vec<u8> {
+        writer.reserve(data.len() / 4);
+        let mut compressor = Compressor::without_headers(writer);
+        compressor.table = self.table.clone();
+        compressor.buffer = self.header_bits;
+        compressor.nbits = self.header_nbits;
+        compressor.in_block = true;
+        compressor.write_data(data).unwrap();
+        compressor.finish().unwrap()
+    }
+}
+
 /// Compressor that only writes the stored blocks.
 ///
 /// This is useful for writing files that are not compressed, but still need to be wrapped in a
@@ -215,7 +870,7 @@ pub struct StoredOnlyCompressor<W> {
 impl<W: Write + Seek> StoredOnlyCompressor<W> {
     /// Creates a new `StoredOnlyCompressor` that writes to the given writer.
     pub fn new(mut writer: W) -> io::Result<Self> {
-        writer.write_all(&[0x78, 0x01])?; // zlib header
+        writer.write_all(&ZlibHeader::FDEFLATE.to_bytes(false))?;
         writer.write_all(&[0; 5])?; // placeholder stored block header
 
         Ok(Self {
@@ -282,6 +937,321 @@ impl<W> StoredOnlyCompressor<W> {
     }
 }
 
+/// Wraps `input` in a zlib stream made only of stored blocks and writes it to `writer`.
+///
+/// Unlike [`StoredOnlyCompressor`], the writer doesn't need to implement [`Seek`] and the input is
+/// never copied: the 5-byte block headers are interleaved with borrowed slices of `input` and the
+/// whole stream is emitted with [`Write::write_vectored`]. The output is byte-for-byte identical to
+/// what [`StoredOnlyCompressor`] produces.
+pub fn compress_stored_vectored<W: Write>(input: &[u8], mut writer: W) -> io::Result<W> {
+    let mut checksum = Adler32::new();
+    checksum.write(input);
+    let checksum = checksum.finish().to_be_bytes();
+
+    let mut blocks = input.chunks(u16::MAX as usize).peekable();
+    let mut headers = Vec::with_capacity(input.len() / u16::MAX as usize + 1);
+    if blocks.peek().is_none() {
+        headers.push([1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let size = block.len() as u16;
+        let last = blocks.peek().is_none();
+        headers.push([
+            last as u8,
+            (size & 0xFF) as u8,
+            ((size >> 8) & 0xFF) as u8,
+            (!size & 0xFF) as u8,
+            ((!size >> 8) & 0xFF) as u8,
+        ]);
+    }
+
+    let mut bufs: Vec<&[u8]> = Vec::with_capacity(headers.len() * 2 + 2);
+    let zlib_header = ZlibHeader::FDEFLATE.to_bytes(false);
+    bufs.push(&zlib_header);
+    let mut blocks = input.chunks(u16::MAX as usize);
+    for header in &headers {
+        bufs.push(header);
+        if let Some(block) = blocks.next() {
+            bufs.push(block);
+        }
+    }
+    bufs.push(&checksum);
+
+    write_all_vectored(&mut writer, &bufs)?;
+    Ok(writer)
+}
+
+/// Writer that splits its output into chunks of a fixed maximum size.
+///
+/// Each chunk is passed to a callback as soon as it is complete, along with its byte range
+/// within the output. This lets encoders for chunked containers, like the IDAT chunks of PNG
+/// images, frame the compressed data while it is being produced:
+///
+/// ```
+/// # use fdeflate::{ChunkedWriter, Compressor};
+/// let mut chunks = Vec::new();
+/// let writer = ChunkedWriter::new(8192, |chunk: &[u8], range| {
+///     chunks.push((chunk.to_vec(), range));
+///     Ok(())
+/// });
+/// let mut compressor = Compressor::new(writer).unwrap();
+/// compressor.write_data(&[0; 100_000]).unwrap();
+/// compressor.finish().unwrap().finish().unwrap();
+/// ```
+pub struct ChunkedWriter<F> {
+    on_chunk: F,
+    max_chunk_size: usize,
+    buffer: Vec<u8>,
+    position: u64,
+}
+impl<F: FnMut(&[u8], Range<u64>) -> io::Result<()>> ChunkedWriter<F> {
+    /// Create a writer that passes chunks of at most `max_chunk_size` bytes to `on_chunk`.
+    pub fn new(max_chunk_size: usize, on_chunk: F) -> Self {
+        assert!(max_chunk_size > 0);
+        Self {
+            on_chunk,
+            max_chunk_size,
+            buffer: Vec::with_capacity(max_chunk_size),
+            position: 0,
+        }
+    }
+
+    fn emit(&mut self, chunk: &[u8]) -> io::Result<()> {
+        let end = self.position + chunk.len() as u64;
+        (self.on_chunk)(chunk, self.position..end)?;
+        self.position = end;
+        Ok(())
+    }
+
+    /// Emit the last, possibly shorter, chunk.
+    pub fn finish(mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let buffer = mem::take(&mut self.buffer);
+            self.emit(&buffer)?;
+        }
+        Ok(())
+    }
+}
+impl<F: FnMut(&[u8], Range<u64>) -> io::Result<()>> Write for ChunkedWriter<F> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len();
+
+        if !self.buffer.is_empty() {
+            let n = buf.len().min(self.max_chunk_size - self.buffer.len());
+            self.buffer.extend_from_slice(&buf[..n]);
+            buf = &buf[n..];
+            if self.buffer.len() == self.max_chunk_size {
+                let buffer = mem::take(&mut self.buffer);
+                self.emit(&buffer)?;
+                self.buffer = buffer;
+                self.buffer.clear();
+            }
+        }
+
+        // Whole chunks are passed on without copying them.
+        while buf.len() >= self.max_chunk_size {
+            let (chunk, rest) = buf.split_at(self.max_chunk_size);
+            self.emit(chunk)?;
+            buf = rest;
+        }
+        self.buffer.extend_from_slice(buf);
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A position at which decompression can start in the output of a [`SeekableCompressor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushPoint {
+    /// Offset in the compressed stream, counted from the start of the zlib header.
+    pub compressed_offset: u64,
+    /// Offset in the uncompressed data.
+    pub uncompressed_offset: u64,
+}
+
+/// Compressor that makes its output seekable by inserting full flushes at regular intervals.
+///
+/// Every `interval` bytes of input, the stream is ended with [`Compressor::full_flush`] and the
+/// resulting [`FlushPoint`] is recorded. The flush points form a sidecar index that the caller
+/// can store next to the compressed data in any format, for instance to serve byte ranges of
+/// the uncompressed data, and that can be turned into a
+/// [`SeekIndex`](crate::SeekIndex) for [`SeekableReader`](crate::SeekableReader). Each flush
+/// costs about 155 bytes, so intervals much smaller than 64 KiB noticeably hurt compression.
+///
+/// ```
+/// # use fdeflate::SeekableCompressor;
+/// let mut compressor = SeekableCompressor::new(Vec::new(), 1 << 20).unwrap();
+/// compressor.write_data(&vec![1; 5 << 20]).unwrap();
+/// let (compressed, flush_points) = compressor.finish().unwrap();
+/// assert_eq!(flush_points.len(), 4);
+/// assert_eq!(flush_points[0].uncompressed_offset, 1 << 20);
+/// # #[cfg(feature = "decompress")]
+/// # assert!(fdeflate::decompress_to_vec(&compressed).unwrap() == vec![1; 5 << 20]);
+/// ```
+pub struct SeekableCompressor<W: Write> {
+    compressor: Compressor<W>,
+    interval: u64,
+    next_flush: u64,
+    flush_points: Vec<FlushPoint>,
+}
+
+impl<W: Write> SeekableCompressor<W> {
+    /// Create a compressor that inserts a full flush every `interval` bytes of input.
+    pub fn new(writer: W, interval: u64) -> io::Result<Self> {
+        assert!(interval > 0);
+        Ok(Self {
+            compressor: Compressor::new(writer)?,
+            interval,
+            next_flush: interval,
+            flush_points: Vec::new(),
+        })
+    }
+
+    /// Write data to the compressor.
+    pub fn write_data(&mut self, mut data: &[u8]) -> io::Result<()> {
+        loop {
+            let until_flush = self.next_flush - self.compressor.bytes_consumed;
+            if (data.len() as u64) < until_flush {
+                return self.compressor.write_data(data);
+            }
+
+            let (before, after) = data.split_at(until_flush as usize);
+            self.compressor.write_data(before)?;
+            data = after;
+            // Flushing at the very end would only leave an empty block behind.
+            if data.is_empty() {
+                return Ok(());
+            }
+            self.record_flush_point()?;
+        }
+    }
+
+    /// Insert a full flush and record its position.
+    fn record_flush_point(&mut self) -> io::Result<()> {
+        let compressed_offset = self.compressor.full_flush()?;
+        self.flush_points.push(FlushPoint {
+            compressed_offset,
+            uncompressed_offset: self.compressor.bytes_consumed,
+        });
+        self.next_flush += self.interval;
+        Ok(())
+    }
+
+    /// Returns the flush points recorded so far.
+    pub fn flush_points(&self) -> &[FlushPoint] {
+        &self.flush_points
+    }
+
+    /// Write the remainder of the stream, returning the inner writer and all flush points.
+    pub fn finish(self) -> io::Result<(W, Vec<FlushPoint>)> {
+        Ok((self.compressor.finish()?, self.flush_points))
+    }
+}
+
+/// Version of the compressed output produced by this crate.
+///
+/// For a given input and sequence of calls, [`Compressor`], [`compress_to_vec`] and
+/// [`StoredOnlyCompressor`] produce byte-identical output on every platform and regardless of the
+/// CPU features in use. Any change to the output bumps this constant, so it can be stored
+/// alongside content-addressed or reproducible artifacts to detect when recompressing the same
+/// data would give different bytes.
+pub const FORMAT_VERSION: u32 = 3;
+
+// Compressors must remain `Send` so they can be moved between the threads of a pool.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Compressor<Vec<u8>>>();
+    assert_send::<StoredOnlyCompressor<Vec<u8>>>();
+    assert_send::<BlockWriter<Vec<u8>>>();
+};
+
+/// Fields of the two-byte zlib header at the start of a stream.
+///
+/// The header declares how large a window decompressors need for the stream's back-references,
+/// and, for information only, how hard the compressor tried to make the output small.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZlibHeader {
+    /// Base-2 logarithm of the window size, from 8 (256 bytes) to 15 (32 KiB). This is the
+    /// `CINFO` field plus eight.
+    pub window_bits: u8,
+    /// Compression level, from 0 (fastest) to 3 (maximum compression). This is the `FLEVEL`
+    /// field.
+    pub level: u8,
+}
+
+impl ZlibHeader {
+    /// The header of streams written by [`Compressor`] and [`StoredOnlyCompressor`].
+    ///
+    /// Their only back-references have a distance of one byte, so they declare the smallest
+    /// window, along with the level of the fastest compressors.
+    pub const FDEFLATE: Self = Self {
+        window_bits: 8,
+        level: 0,
+    };
+
+    /// Encode the header, with the `FDICT` flag set if `preset_dictionary` is true.
+    const fn to_bytes(self, preset_dictionary: bool) -> [u8; 2] {
+        let cmf = ((self.window_bits - 8) << 4) | 8;
+        let flg = (self.level << 6) | ((preset_dictionary as u8) << 5);
+        let check = (31 - (cmf as u16 * 256 + flg as u16) % 31) % 31;
+        [cmf, flg | check as u8]
+    }
+}
+
+/// Returns an upper bound on the size of the compressed output for `input_len` bytes of input.
+///
+/// The bound holds for [`Compressor`], [`compress_to_vec`], [`StoredOnlyCompressor`] and
+/// [`compress_stored_vectored`], regardless of the contents of the input or how it is split
+/// between calls to `write_data`.
+pub fn compress_bound(input_len: usize) -> usize {
+    // The headers of a `Compressor` stream take 1238 bits. Each input byte then needs at most 12
+    // bits, since runs of zeros are never encoded with more bits than the literals they replace,
+    // followed by the 12-bit end of block symbol, the 10-bit final empty block, padding, and the
+    // 4-byte checksum.
+    input_len
+        .saturating_add((input_len + 1) / 2)
+        .saturating_add(163)
+}
+
+/// A complete zlib stream with no contents.
+///
+/// It is made of the zlib header, a single final fixed Huffman block holding only the end of
+/// block symbol, and the Adler-32 checksum of the empty input.
+pub const EMPTY_ZLIB_STREAM: [u8; 8] = [0x08, 0x1d, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01];
+
+/// Append a final empty block to a raw deflate stream whose last block isn't marked as final.
+///
+/// This closes out a body that ends with a sync flush or another non-final block, for instance
+/// one that was cut at a flush point. `unused_bits` is the number of high bits of the last byte
+/// of `body` that aren't part of the stream yet; the new block starts right after the used ones.
+/// The block is ten bits long and the last byte is padded with zeros.
+///
+/// # Panics
+///
+/// Panics if `unused_bits` is 8 or more, or if it is nonzero and `body` is empty.
+pub fn append_final_empty_block(body: &mut Vec<u8>, unused_bits: u8) {
+    assert!(unused_bits < 8);
+
+    // BFINAL = 1 and BTYPE = 01 (fixed Huffman), followed by the 7-bit end of block code, which
+    // is all zeros.
+    let mut bits: u16 = 0b011;
+    let mut nbits = 10;
+    if unused_bits > 0 {
+        let last = body.last_mut().expect("no byte holds the unused bits");
+        *last |= (bits << (8 - unused_bits)) as u8;
+        bits >>= unused_bits;
+        nbits -= unused_bits;
+    }
+    body.push(bits as u8);
+    if nbits > 8 {
+        body.push((bits >> 8) as u8);
+    }
+}
+
 /// Compresses the given data.
 pub fn compress_to_vec(input: &[u8]) -> Vec<u8> {
     let mut compressor = Compressor::new(Vec::with_capacity(input.len() / 4)).unwrap();
@@ -289,6 +1259,135 @@ pub fn compress_to_vec(input: &[u8]) -> Vec<u8> {
     compressor.finish().unwrap()
 }
 
+/// Distance code lengths used by [`compress_with_base`]. Two codes are a bit shorter so that the
+/// tree is complete, which decoders require when there is more than one distance code.
+const BASE_DIST_LENGTHS: [u8; 30] = [
+    5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 4, 4,
+];
+const BASE_DIST_CODES: [u16; 30] = match crate::compute_codes(&BASE_DIST_LENGTHS) {
+    Some(codes) => codes,
+    None => panic!("BASE_DIST_LENGTHS is invalid"),
+};
+
+/// Compresses `new` using `base` as a preset dictionary.
+///
+/// Unlike the other compressors in this crate, this searches for matches: both within `new` and
+/// against the last 32 KiB of `base`. When `new` is a revision of `base`, as when storing many
+/// versions of a similar blob, the unchanged parts are encoded as back-references into `base`
+/// and take only a few bits each.
+///
+/// The output is a zlib stream with a preset dictionary, whose header records the Adler-32
+/// checksum of `base`. It can be decompressed with
+/// [`decompress_with_base`](crate::decompress_with_base) given the same `base`, or by zlib with
+/// `base` passed to `inflateSetDictionary`.
+pub fn compress_with_base(base: &[u8], new: &[u8]) -> Vec<u8> {
+    write_with_base(base, new).unwrap()
+}
+
+fn write_with_base(base: &[u8], new: &[u8]) -> io::Result<Vec<u8>> {
+    const HASH_BITS: u32 = 15;
+    const MAX_DISTANCE: usize = 32768;
+    const MIN_MATCH: usize = 4;
+    const MAX_MATCH: usize = 258;
+    const MAX_CHAIN: usize = 32;
+
+    let dictionary = &base[base.len().saturating_sub(MAX_DISTANCE)..];
+    let data = [dictionary, new].concat();
+    let hash = |i: usize| {
+        let bytes = u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        (bytes.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    };
+    // Hash chains of the positions of each sequence of four bytes: `head` holds the most recent
+    // position plus one for each hash, and `prev` links each position to the previous one with
+    // the same hash. Zero marks the end of a chain.
+    let mut head = vec![0u32; 1 << HASH_BITS];
+    let mut prev = vec![0u32; data.len()];
+    let hash_end = data.len().saturating_sub(MIN_MATCH - 1);
+    let mut insert = |i: usize, prev: &mut [u32]| {
+        let h = hash(i);
+        prev[i] = head[h];
+        head[h] = i as u32 + 1;
+        prev[i]
+    };
+    for i in 0..dictionary.len().min(hash_end) {
+        insert(i, &mut prev);
+    }
+
+    let mut compressor = Compressor::without_headers(Vec::with_capacity(new.len() / 4));
+    compressor.checksum.write(new);
+    let header = ZlibHeader {
+        window_bits: 15,
+        level: 1,
+    };
+    compressor.write_bits(u16::from_le_bytes(header.to_bytes(true)) as u64, 16)?;
+    let dictionary_id = simd_adler32::adler32(&base);
+    compressor.write_bits(dictionary_id.swap_bytes() as u64, 32)?;
+    compressor.write_dynamic_header(true, &BASE_DIST_LENGTHS)?;
+
+    let mut i = dictionary.len();
+    while i < data.len() {
+        let mut length = 0;
+        let mut distance = 0;
+        if i < hash_end {
+            let limit = (data.len() - i).min(MAX_MATCH);
+            let mut candidate = insert(i, &mut prev) as usize;
+            for _ in 0..MAX_CHAIN {
+                if candidate == 0 || i - (candidate - 1) > MAX_DISTANCE {
+                    break;
+                }
+                let start = candidate - 1;
+                let len = (0..limit)
+                    .position(|j| data[start + j] != data[i + j])
+                    .unwrap_or(limit);
+                if len > length {
+                    length = len;
+                    distance = i - start;
+                }
+                candidate = prev[start] as usize;
+            }
+        }
+
+        if length < MIN_MATCH {
+            compressor.write_literal(data[i])?;
+            i += 1;
+            continue;
+        }
+
+        let sym = LEN_SYM_TO_LEN_BASE
+            .iter()
+            .rposition(|&base| base <= length)
+            .unwrap();
+        let extra = LEN_SYM_TO_LEN_EXTRA[sym];
+        compressor.write_bits(
+            compressor.table.codes[257 + sym] as u64,
+            compressor.table.lengths[257 + sym],
+        )?;
+        compressor.write_bits((length - LEN_SYM_TO_LEN_BASE[sym]) as u64, extra)?;
+
+        let sym = DIST_SYM_TO_DIST_BASE
+            .iter()
+            .rposition(|&base| base as usize <= distance)
+            .unwrap();
+        let extra = DIST_SYM_TO_DIST_EXTRA[sym];
+        compressor.write_bits(BASE_DIST_CODES[sym] as u64, BASE_DIST_LENGTHS[sym])?;
+        compressor.write_bits(
+            (distance - DIST_SYM_TO_DIST_BASE[sym] as usize) as u64,
+            extra,
+        )?;
+
+        for j in i + 1..(i + length).min(hash_end) {
+            insert(j, &mut prev);
+        }
+        i += length;
+    }
+
+    compressor.write_bits(
+        compressor.table.codes[256] as u64,
+        compressor.table.lengths[256],
+    )?;
+    compressor.write_trailer()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -300,6 +1399,216 @@ mod tests {
         assert_eq!(&decompressed, data);
     }
 
+    #[test]
+    fn empty_stream_helpers() {
+        let decompressed = miniz_oxide::inflate::decompress_to_vec_zlib(&EMPTY_ZLIB_STREAM);
+        assert_eq!(decompressed.unwrap(), b"");
+
+        // A non-final stored block followed by `n` non-final empty fixed blocks of ten bits each,
+        // so that the body ends at every even bit offset.
+        for n in 0..4 {
+            let mut body = vec![0x00, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+            let bits = (0..n).fold(0u64, |bits, i| bits | (0b010 << (10 * i)));
+            body.extend_from_slice(&bits.to_le_bytes()[..(10 * n + 7) / 8]);
+            let unused_bits = ((8 - 10 * n % 8) % 8) as u8;
+
+            append_final_empty_block(&mut body, unused_bits);
+            let decompressed = miniz_oxide::inflate::decompress_to_vec(&body).unwrap();
+            assert_eq!(decompressed, b"abc");
+        }
+    }
+
+    #[test]
+    fn block_writer() {
+        let mut data: Vec<u8> = (0..200_000u32).map(|i| (i % 13) as u8).collect();
+        data[0] = b'A';
+
+        let mut writer = BlockWriter::new(Vec::new()).unwrap();
+        // A non-final fixed Huffman block holding the literal 'A'.
+        writer.write_bits(0b010, 3).unwrap();
+        writer.write_bits(0b1000_1110, 8).unwrap();
+        writer.write_bits(0, 7).unwrap();
+        assert_eq!(writer.bit_position(), 16 + 18);
+
+        // Stored blocks for the rest, the last of which is final.
+        let mut chunks = data[1..].chunks(u16::MAX as usize).peekable();
+        while let Some(chunk) = chunks.next() {
+            writer
+                .write_bits(chunks.peek().is_none() as u64, 3)
+                .unwrap();
+            writer.align_to_byte().unwrap();
+            let len = chunk.len() as u64;
+            writer.write_bits(len | (!len & 0xffff) << 16, 32).unwrap();
+            for &b in chunk {
+                writer.write_bits(b as u64, 8).unwrap();
+            }
+        }
+        writer.update_checksum(&data[..1]);
+        writer.update_checksum(&data[1..]);
+
+        let compressed = writer.finish().unwrap();
+        let decompressed = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn bit_writer_byte_order() {
+        let mut compressor = Compressor {
+            checksum: Adler32::new(),
+            buffer: 0,
+            nbits: 0,
+            chunks: Vec::new(),
+            output: Vec::new(),
+            writer: Vec::new(),
+            bytes_consumed: 0,
+            bytes_written: 0,
+            progress: None,
+            cancellation_flag: None,
+            in_block: false,
+            table: HuffmanTable::default(),
+            raw: false,
+        };
+        compressor.write_bits(0x301, 12).unwrap();
+        compressor.write_bits(0x452, 12).unwrap();
+        compressor.write_bits(0x0123_4567_89ab, 48).unwrap();
+        compressor.align_to_byte().unwrap();
+        compressor.flush().unwrap();
+        assert_eq!(
+            compressor.writer,
+            [0x01, 0x23, 0x45, 0xab, 0x89, 0x67, 0x45, 0x23, 0x01]
+        );
+    }
+
+    #[test]
+    fn vectored_output() {
+        struct VectoredWriter {
+            data: Vec<u8>,
+            max_slices: usize,
+        }
+        impl Write for VectoredWriter {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                panic!("expected only vectored writes");
+            }
+            fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+                self.max_slices = self.max_slices.max(bufs.len());
+                // Accept a short write to exercise resuming partway through a slice.
+                let mut written = 0;
+                for buf in bufs.iter().take(2) {
+                    let n = buf.len().min(100_000 - written);
+                    self.data.extend_from_slice(&buf[..n]);
+                    written += n;
+                }
+                Ok(written)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut rng = rand::thread_rng();
+        let mut data = vec![0; 1024 * 1024];
+        for byte in &mut data {
+            *byte = rng.gen();
+        }
+
+        let writer = VectoredWriter {
+            data: Vec::new(),
+            max_slices: 0,
+        };
+        let mut compressor = Compressor::new(writer).unwrap();
+        compressor.write_data(&data[..1000]).unwrap();
+        compressor.flush().unwrap();
+        assert!(!compressor.writer.data.is_empty());
+        compressor.write_data(&data[1000..]).unwrap();
+        let writer = compressor.finish().unwrap();
+
+        assert!(writer.max_slices > 1);
+        assert!(writer.max_slices <= MAX_PENDING_CHUNKS + 1);
+        assert_eq!(writer.data, compress_to_vec(&data));
+        let decompressed = miniz_oxide::inflate::decompress_to_vec_zlib(&writer.data).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn stored_vectored() {
+        let mut rng = rand::thread_rng();
+        for len in [
+            0,
+            1,
+            1000,
+            u16::MAX as usize,
+            u16::MAX as usize + 1,
+            200_000,
+        ] {
+            let mut data = vec![0; len];
+            for byte in &mut data {
+                *byte = rng.gen();
+            }
+
+            let mut compressor = StoredOnlyCompressor::new(io::Cursor::new(Vec::new())).unwrap();
+            compressor.write_data(&data).unwrap();
+            let expected = compressor.finish().unwrap().into_inner();
+
+            let compressed = compress_stored_vectored(&data, Vec::new()).unwrap();
+            assert_eq!(compressed, expected);
+            let decompressed = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap();
+            assert_eq!(decompressed, data);
+        }
+    }
+
+    #[test]
+    fn chunked_writer() {
+        let mut rng = rand::thread_rng();
+        let mut data = vec![0; 300_000];
+        for byte in &mut data {
+            *byte = rng.gen();
+        }
+
+        let mut output = Vec::new();
+        let writer = ChunkedWriter::new(8192, |chunk: &[u8], range: Range<u64>| {
+            let position: usize = output.iter().map(Vec::len).sum();
+            assert_eq!(range.start, position as u64);
+            assert_eq!(range.end - range.start, chunk.len() as u64);
+            output.push(chunk.to_vec());
+            Ok(())
+        });
+        let mut compressor = Compressor::new(writer).unwrap();
+        compressor.write_data(&data).unwrap();
+        compressor.finish().unwrap().finish().unwrap();
+
+        let (last, full) = output.split_last().unwrap();
+        assert!(full.iter().all(|chunk| chunk.len() == 8192));
+        assert!(!last.is_empty() && last.len() <= 8192);
+        assert_eq!(output.concat(), compress_to_vec(&data));
+    }
+
+    #[test]
+    fn progress_callback() {
+        use std::sync::{Arc, Mutex};
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let reports2 = Arc::clone(&reports);
+        let mut compressor = Compressor::new(Vec::new()).unwrap();
+        compressor.set_progress_callback(100_000, move |progress| {
+            reports2.lock().unwrap().push(progress);
+        });
+        let data = vec![0; 60_000];
+        for _ in 0..10 {
+            compressor.write_data(&data).unwrap();
+        }
+        let compressed = compressor.finish().unwrap();
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 6);
+        assert_eq!(
+            reports.iter().map(|p| p.bytes_consumed).collect::<Vec<_>>(),
+            [60_000, 180_000, 300_000, 420_000, 540_000, 600_000]
+        );
+        let last = reports.last().unwrap();
+        assert_eq!(last.bytes_emitted, compressed.len() as u64);
+        assert!(last.ratio() < 0.01);
+    }
+
     #[test]
     fn it_works() {
         roundtrip(b"Hello world!");
@@ -324,4 +1633,297 @@ mod tests {
             roundtrip(&data);
         }
     }
+
+    #[test]
+    fn cancellation() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut compressor = Compressor::new(Vec::new()).unwrap();
+        compressor.set_cancellation_flag(Arc::clone(&flag));
+        compressor.write_data(&[1; 1000]).unwrap();
+
+        flag.store(true, Ordering::Relaxed);
+        let err = compressor.write_data(&[1; 1000]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn move_between_threads() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let (first, second) = data.split_at(data.len() / 2);
+
+        let mut compressor = Compressor::new(Vec::new()).unwrap();
+        compressor.write_data(first).unwrap();
+        let second = second.to_vec();
+        let compressed = std::thread::spawn(move || {
+            compressor.write_data(&second).unwrap();
+            compressor.finish().unwrap()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(compressed, compress_to_vec(&data));
+    }
+
+    #[test]
+    fn write_data_u16_be() {
+        let values: Vec<u16> = (0..100_003u32)
+            .map(|i| if i % 3000 < 1000 { 0 } else { (i * 7) as u16 })
+            .collect();
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+
+        let mut compressor = Compressor::new(Vec::new()).unwrap();
+        compressor.write_data_u16_be(&values).unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert_eq!(compressed, compress_to_vec(&bytes));
+    }
+
+    #[test]
+    fn repeated_bytes() {
+        let mut compressor = Compressor::new(Vec::new()).unwrap();
+        let mut expected = Vec::new();
+        for (i, len) in [0, 1, 2, 3, 5, 258, 259, 260, 261, 1000, 100_000]
+            .iter()
+            .enumerate()
+        {
+            let byte = [0, 7, 255][i % 3];
+            compressor.write_data(b"abc").unwrap();
+            compressor.write_repeated_byte(byte, *len).unwrap();
+            expected.extend_from_slice(b"abc");
+            expected.resize(expected.len() + *len as usize, byte);
+        }
+        compressor.write_zero_run(1 << 20).unwrap();
+        expected.resize(expected.len() + (1 << 20), 0);
+        let compressed = compressor.finish().unwrap();
+        assert!(compressed.len() < expected.len() / 150);
+        assert!(miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap() == expected);
+    }
+
+    #[test]
+    fn zlib_header() {
+        let data = b"zlib header".repeat(100);
+        assert_eq!(compress_to_vec(&data)[..2], [0x08, 0x1d]);
+
+        let header = ZlibHeader {
+            window_bits: 15,
+            level: 2,
+        };
+        let mut compressor = Compressor::with_zlib_header(Vec::new(), header).unwrap();
+        compressor.write_data(&data).unwrap();
+        let compressed = compressor.finish().unwrap();
+        assert_eq!(compressed[..2], [0x78, 0x9c]);
+        assert_eq!(
+            miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap(),
+            data
+        );
+
+        for (window_bits, level) in [(7, 0), (16, 0), (8, 4)] {
+            let header = ZlibHeader { window_bits, level };
+            let err = Compressor::with_zlib_header(Vec::new(), header)
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn raw_stream() {
+        let data = crate::test_utils::test_data(100_000);
+        let compress = |mut compressor: Compressor<Vec<u8>>| {
+            compressor.write_data(&data[..50_000]).unwrap();
+            compressor.write_stored(&data[50_000..60_000]).unwrap();
+            compressor.write_data(&data[60_000..]).unwrap();
+            compressor.finish().unwrap()
+        };
+        let raw = compress(Compressor::new_raw(Vec::new()).unwrap());
+        assert!(miniz_oxide::inflate::decompress_to_vec(&raw).unwrap() == data);
+
+        // The deflate data is the same as in a zlib stream, just without the framing.
+        let zlib = compress(Compressor::new(Vec::new()).unwrap());
+        assert!(raw[..] == zlib[2..zlib.len() - 4]);
+    }
+
+    #[test]
+    fn golden_vectors() {
+        // (length, Adler-32) of the output of `compress_to_vec` and `StoredOnlyCompressor` for
+        // each of `golden_inputs`. These may only change together with `FORMAT_VERSION`.
+        assert_eq!(FORMAT_VERSION, 3);
+        let expected = [
+            ((162, 0x52ad6322), (11, 0x0c840226)),
+            ((181, 0x6fcb70af), (24, 0x5d1f07ba)),
+            ((164, 0x1a2763a9), (51, 0x6314024e)),
+            ((40784, 0xc4aafdc4), (100016, 0xfe445005)),
+            ((89306, 0x7f764dea), (300031, 0xcef309a0)),
+        ];
+
+        for (data, &(compressed, stored)) in golden_inputs().iter().zip(expected.iter()) {
+            let output = compress_to_vec(data);
+            assert_eq!(
+                (output.len(), simd_adler32::adler32(&&output[..])),
+                compressed
+            );
+
+            let mut compressor = StoredOnlyCompressor::new(io::Cursor::new(Vec::new())).unwrap();
+            compressor.write_data(data).unwrap();
+            let output = compressor.finish().unwrap().into_inner();
+            assert_eq!((output.len(), simd_adler32::adler32(&&output[..])), stored);
+        }
+    }
+
+    #[test]
+    fn write_stored() {
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 97) as u8).collect();
+        let mut rng = rand::thread_rng();
+        let mut random = vec![0; 150_000];
+        rng.fill(&mut random[..]);
+
+        let mut compressor = Compressor::new(Vec::new()).unwrap();
+        compressor.write_stored(b"stored first").unwrap();
+        compressor.write_data(&data).unwrap();
+        compressor.write_stored(&random).unwrap();
+        compressor.write_stored(&[]).unwrap();
+        compressor.write_data(&data[..1000]).unwrap();
+        compressor.write_stored(b"stored last").unwrap();
+        let compressed = compressor.finish().unwrap();
+
+        let expected = [
+            &b"stored first"[..],
+            &data,
+            &random,
+            &data[..1000],
+            b"stored last",
+        ]
+        .concat();
+        let decompressed = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap();
+        assert_eq!(decompressed, expected);
+        assert!(compressed.len() < compress_bound(data.len() + 1000) + random.len() + 500);
+    }
+
+    #[test]
+    fn delta_against_base() {
+        use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+        use miniz_oxide::inflate::TINFLStatus;
+
+        let base: Vec<u8> = (0..30_000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let mut new = base.clone();
+        new[10_000..10_010].copy_from_slice(b"0123456789");
+        new.extend_from_slice(b"appended");
+
+        let compressed = compress_with_base(&base, &new);
+        assert!(compressed.len() < 1000);
+        assert_eq!(compressed[1] & 0x20, 0x20);
+        assert_eq!(
+            compressed[2..6],
+            simd_adler32::adler32(&&base[..]).to_be_bytes()
+        );
+
+        // Decode the deflate body with an independent decoder, with `base` as its history.
+        let mut output = vec![0; base.len() + new.len()];
+        output[..base.len()].copy_from_slice(&base);
+        let (status, _, produced) = decompress(
+            &mut DecompressorOxide::new(),
+            &compressed[6..],
+            &mut output,
+            base.len(),
+            inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+        );
+        assert_eq!(status, TINFLStatus::Done);
+        assert_eq!(&output[base.len()..][..produced], &new[..]);
+    }
+
+    #[test]
+    fn write_data_vectored() {
+        let mut data = vec![0; 1000];
+        data.extend((0..100_000u32).map(|i| if i % 300 < 50 { (i % 7) as u8 } else { 0 }));
+        let expected = compress_to_vec(&data);
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let mut fragments = Vec::new();
+            let mut rest = &data[..];
+            while !rest.is_empty() {
+                let (fragment, tail) = rest.split_at(rng.gen_range(0..=rest.len().min(20)));
+                fragments.push(fragment);
+                rest = tail;
+            }
+            let mut compressor = Compressor::new(Vec::new()).unwrap();
+            compressor.write_data_vectored(&fragments).unwrap();
+            assert_eq!(compressor.finish().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn custom_table() {
+        let text: Vec<u8> = (0..20_000u32)
+            .flat_map(|i| format!("line {} of some text\n", i % 977).into_bytes())
+            .chain(vec![0; 1000])
+            .collect();
+
+        let default_size = compress_to_vec(&text).len();
+        for max_code_length in [9, 12, 15] {
+            let table = HuffmanTable::from_data(&text, max_code_length);
+            let mut compressor = Compressor::with_table(Vec::new(), &table).unwrap();
+            compressor.write_data(&text).unwrap();
+            compressor
+                .write_data(&[0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2])
+                .unwrap();
+            let compressed = compressor.finish().unwrap();
+            assert!(compressed.len() < default_size);
+
+            let decompressed = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap();
+            assert_eq!(&decompressed[..text.len()], &text[..]);
+            assert_eq!(
+                &decompressed[text.len()..],
+                [0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2]
+            );
+        }
+    }
+
+    #[test]
+    fn batch_compressor() {
+        let payloads: Vec<Vec<u8>> = (0..50u32)
+            .map(|i| (0..i * 100).map(|j| (j % (i + 2)) as u8).collect())
+            .collect();
+        let samples: Vec<&[u8]> = payloads.iter().map(|p| &p[..]).collect();
+        let table = HuffmanTable::from_samples(&samples, 12);
+        let batch = BatchCompressor::new(&table);
+
+        for payload in &payloads {
+            let mut compressor = Compressor::with_table(Vec::new(), &table).unwrap();
+            compressor.write_data(payload).unwrap();
+            let expected = compressor.finish().unwrap();
+
+            assert_eq!(batch.compress(payload), expected);
+            let body = batch.compress_without_header(payload);
+            assert_eq!([batch.header(), &body].concat(), expected);
+            assert!(body.len() + 150 < expected.len());
+        }
+    }
+
+    fn golden_inputs() -> Vec<Vec<u8>> {
+        vec![
+            Vec::new(),
+            b"Hello, world!".to_vec(),
+            vec![0; 40],
+            (0..100_000u32)
+                .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8 & (i % 7) as u8)
+                .collect(),
+            (0..300_000u32)
+                .map(|i| if i % 1000 < 600 { 0 } else { (i % 13) as u8 })
+                .collect(),
+        ]
+    }
+
+    #[test]
+    fn bound() {
+        let mut rng = rand::thread_rng();
+        for len in [0, 1, 7, 1000, 65535, 200_000] {
+            let mut data = vec![0; len];
+            rng.fill(&mut data[..]);
+            assert!(compress_to_vec(&data).len() <= compress_bound(len));
+
+            let stored = compress_stored_vectored(&data, Vec::new()).unwrap();
+            assert!(stored.len() <= compress_bound(len));
+        }
+    }
 }
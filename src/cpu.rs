@@ -0,0 +1,129 @@
+//! Runtime CPU feature detection and the kernels the decoder spends most of its time in outside
+//! the Huffman decoding loop.
+//!
+//! The features of the running CPU are detected once and cached, so that variants of the kernels
+//! specialized for an instruction set can coexist in one binary without distributors having to
+//! build for the lowest common denominator. So far only portable kernels exist, which the compiler
+//! vectorizes for the baseline target, and which are called directly so that they get inlined
+//! into the decode loop. A specialized variant should be selected once per call into the
+//! decoder, not through a function pointer per symbol. The Adler-32 checksum isn't handled
+//! here, since `simd_adler32` already selects its implementation at runtime.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Instruction set extensions relevant to the kernels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct CpuFeatures {
+    pub sse2: bool,
+    pub avx2: bool,
+    pub bmi2: bool,
+    pub neon: bool,
+}
+
+impl CpuFeatures {
+    const SSE2: u8 = 1 << 0;
+    const AVX2: u8 = 1 << 1;
+    const BMI2: u8 = 1 << 2;
+    const NEON: u8 = 1 << 3;
+    /// Set once detection has run, so that a CPU without any of the features isn't re-detected.
+    const DETECTED: u8 = 1 << 7;
+
+    fn detect() -> Self {
+        #[allow(unused_mut)]
+        let mut features = Self::default();
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            features.sse2 = std::is_x86_feature_detected!("sse2");
+            features.avx2 = std::is_x86_feature_detected!("avx2");
+            features.bmi2 = std::is_x86_feature_detected!("bmi2");
+        }
+        // NEON is part of the baseline of the aarch64 targets, and runtime detection needs a
+        // newer compiler than the minimum supported one.
+        #[cfg(target_arch = "aarch64")]
+        {
+            features.neon = cfg!(target_feature = "neon");
+        }
+        features
+    }
+
+    fn to_bits(self) -> u8 {
+        let mut bits = Self::DETECTED;
+        for (present, bit) in [
+            (self.sse2, Self::SSE2),
+            (self.avx2, Self::AVX2),
+            (self.bmi2, Self::BMI2),
+            (self.neon, Self::NEON),
+        ] {
+            if present {
+                bits |= bit;
+            }
+        }
+        bits
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        Self {
+            sse2: bits & Self::SSE2 != 0,
+            avx2: bits & Self::AVX2 != 0,
+            bmi2: bits & Self::BMI2 != 0,
+            neon: bits & Self::NEON != 0,
+        }
+    }
+}
+
+/// Returns the features of the running CPU, detecting them on the first call.
+#[allow(dead_code)]
+pub(crate) fn cpu_features() -> CpuFeatures {
+    static FEATURES: AtomicU8 = AtomicU8::new(0);
+
+    let bits = FEATURES.load(Ordering::Relaxed);
+    if bits & CpuFeatures::DETECTED != 0 {
+        return CpuFeatures::from_bits(bits);
+    }
+    let features = CpuFeatures::detect();
+    FEATURES.store(features.to_bits(), Ordering::Relaxed);
+    features
+}
+
+/// Copy `len` bytes starting `dist` bytes before `index` to `index`. The ranges may overlap, in
+/// which case the copied bytes repeat with a period of `dist`.
+#[inline]
+pub(crate) fn copy_match(output: &mut [u8], index: usize, dist: usize, len: usize) {
+    if dist >= len {
+        output.copy_within(index - dist..index - dist + len, index);
+    } else {
+        for i in index..index + len {
+            output[i] = output[i - dist];
+        }
+    }
+}
+
+/// Set `output` to a run of `byte`.
+#[inline]
+pub(crate) fn fill(output: &mut [u8], byte: u8) {
+    output.fill(byte);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detection_is_cached() {
+        let features = cpu_features();
+        assert_eq!(cpu_features(), features);
+        assert_eq!(CpuFeatures::from_bits(features.to_bits()), features);
+        #[cfg(target_arch = "x86_64")]
+        assert!(features.sse2);
+    }
+
+    #[test]
+    fn kernels() {
+        let mut output = *b"abcdefgh..........";
+        copy_match(&mut output, 8, 8, 4);
+        copy_match(&mut output, 12, 3, 6);
+        assert_eq!(&output, b"abcdefghabcdbcdbcd");
+        fill(&mut output[16..], b'z');
+        assert_eq!(&output, b"abcdefghabcdbcdbzz");
+    }
+}
@@ -0,0 +1,46 @@
+/// Incremental CRC-32 checksum, as used in the trailer of gzip streams.
+///
+/// The checksum is computed with the carry-less multiply (PCLMULQDQ) instructions on x86 and the
+/// CRC32 instructions on ARMv8 when they are detected at runtime, falling back to a table based
+/// implementation otherwise. Note that the SSE 4.2 `crc32` instruction uses a different polynomial
+/// (CRC-32C) and so can't be used for gzip.
+#[derive(Clone, Default)]
+pub struct Crc32(crc32fast::Hasher);
+
+impl Crc32 {
+    /// Create a new checksum with no data written to it.
+    pub fn new() -> Self {
+        Self(crc32fast::Hasher::new())
+    }
+
+    /// Add `data` to the checksum.
+    pub fn write(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    /// Returns the checksum of all data written so far.
+    pub fn finish(&self) -> u32 {
+        self.0.clone().finalize()
+    }
+}
+
+#[cfg(feature = "decompress")]
+impl crate::Update for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        self.write(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        let mut crc = Crc32::new();
+        assert_eq!(crc.finish(), 0);
+        crc.write(b"1234");
+        crc.write(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+}
@@ -1,12 +1,39 @@
+use std::any::Any;
+use std::borrow::Cow;
 use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use simd_adler32::Adler32;
 
+use crate::cpu;
 use crate::tables::{
     self, CLCL_ORDER, DIST_SYM_TO_DIST_BASE, DIST_SYM_TO_DIST_EXTRA, FDEFLATE_DIST_DECODE_TABLE,
     FDEFLATE_LITLEN_DECODE_TABLE, FIXED_CODE_LENGTHS, LEN_SYM_TO_LEN_BASE, LEN_SYM_TO_LEN_EXTRA,
 };
 
+/// Recommended size of the input buffer passed to [`Decompressor::read`].
+///
+/// Smaller buffers work, but each call has some fixed overhead and the bit buffer can only be
+/// refilled eight bytes at a time near the end of the input.
+pub const RECOMMENDED_INPUT_BUFFER_SIZE: usize = 32 * 1024;
+
+/// Recommended number of bytes of free space in the output buffer passed to
+/// [`Decompressor::read`].
+///
+/// Any amount of space works, but the fast decoding loop only runs while there is room for the
+/// longest possible match, so the last few hundred bytes of each call are decoded one symbol at a
+/// time. Along with the fixed overhead of each call, this makes small buffers noticeably slower;
+/// 32 KiB keeps both costs negligible while the buffer still fits in cache.
+pub const RECOMMENDED_OUTPUT_BUFFER_SIZE: usize = 32 * 1024;
+
+/// Maximum back-reference distance of deflate streams, and so the amount of preceding output that
+/// has to be kept around while decoding.
+pub(crate) const WINDOW_SIZE: usize = 32 * 1024;
+
 /// An error encountered while decompressing a deflate stream.
 #[derive(Debug)]
 pub enum DecompressionError {
@@ -43,11 +70,144 @@ pub enum DecompressionError {
     WrongChecksum,
     /// Extra input data.
     ExtraInput,
+    /// The resume point passed to [`Decompressor::resume`] doesn't match the input.
+    InvalidResumePoint,
+    /// The stream decompresses to more than the limit set by [`Decompressor::set_output_limit`].
+    OutputLimitExceeded,
+    /// The ratio of output to input exceeds the limit set by
+    /// [`Decompressor::set_max_expansion_ratio`].
+    ExpansionRatioExceeded,
+    /// Decompression was cancelled through the flag set by
+    /// [`Decompressor::set_cancellation_flag`].
+    Cancelled,
+    /// The decompressed size didn't match the size passed to [`decompress_exact`].
+    OutputSizeMismatch,
+}
+
+/// A position in a deflate stream from which decoding can later be resumed.
+///
+/// Resume points are returned by [`Decompressor::resume_point`] and consumed by
+/// [`Decompressor::resume`]. They may be in the middle of a block, in which case resuming
+/// re-reads the header of the block to reconstruct the huffman tables.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ResumePoint {
+    /// Bit position of the header of the block containing the resume point.
+    pub block_start: u64,
+    /// Bit position at which decoding should resume.
+    pub position: u64,
+}
+
+/// The type of a deflate block.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockType {
+    /// A block containing uncompressed data.
+    Stored,
+    /// A block compressed with the fixed huffman codes defined by the deflate specification.
+    Fixed,
+    /// A block compressed with huffman codes that are specified in the block header.
+    Dynamic,
+}
+
+/// Why [`Decompressor::read_exact`] returned.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ReadStatus {
+    /// The output buffer is full.
+    OutputFull,
+    /// All of the input was consumed and more is needed to make progress.
+    NeedsInput,
+    /// The end of the stream was reached.
+    StreamEnd,
+}
+
+/// Information about the header of an upcoming deflate block.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BlockHeaderInfo {
+    /// The type of the block.
+    pub block_type: BlockType,
+    /// Whether this is the final block of the stream.
+    pub last_block: bool,
+    /// The number of bytes in the block, if it is a stored block.
+    pub stored_len: Option<u16>,
+}
+
+impl fmt::Display for DecompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DecompressionError::BadZlibHeader => "the zlib header is corrupt",
+            DecompressionError::InsufficientInput => "unexpected end of input",
+            DecompressionError::InvalidBlockType => "invalid block type",
+            DecompressionError::InvalidUncompressedBlockLength => {
+                "invalid uncompressed block length"
+            }
+            DecompressionError::InvalidHlit => "too many literal/length codes",
+            DecompressionError::InvalidHdist => "too many distance codes",
+            DecompressionError::InvalidCodeLengthRepeat => "invalid code length repeat",
+            DecompressionError::BadCodeLengthHuffmanTree => "invalid code length huffman tree",
+            DecompressionError::BadLiteralLengthHuffmanTree => {
+                "invalid literal/length huffman tree"
+            }
+            DecompressionError::BadDistanceHuffmanTree => "invalid distance huffman tree",
+            DecompressionError::InvalidLiteralLengthCode => "invalid literal/length code",
+            DecompressionError::InvalidDistanceCode => "invalid distance code",
+            DecompressionError::InputStartsWithRun => "stream starts with a back-reference",
+            DecompressionError::DistanceTooFarBack => "back-reference distance is too far back",
+            DecompressionError::WrongChecksum => "wrong checksum",
+            DecompressionError::ExtraInput => "extra input after the end of the stream",
+            DecompressionError::InvalidResumePoint => "resume point doesn't match the input",
+            DecompressionError::OutputLimitExceeded => "output limit exceeded",
+            DecompressionError::ExpansionRatioExceeded => "expansion ratio limit exceeded",
+            DecompressionError::Cancelled => "decompression cancelled",
+            DecompressionError::OutputSizeMismatch => {
+                "decompressed size doesn't match the expected size"
+            }
+        })
+    }
+}
+
+impl std::error::Error for DecompressionError {}
+
+impl From<DecompressionError> for io::Error {
+    fn from(err: DecompressionError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// A problem with a stream that doesn't affect the decompressed data.
+///
+/// When [lenient](Decompressor::set_lenient) decoding is enabled, these conditions are recorded
+/// rather than causing decoding to fail, and can be retrieved with [`Decompressor::warnings`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DecompressionWarning {
+    /// The checksum at the end of the stream didn't match the decompressed data.
+    WrongChecksum,
+    /// There was more input after the end of the stream.
+    TrailingData,
+    /// The length recorded in the trailer of a gzip member didn't match the decompressed data.
+    WrongLength,
+}
+
+impl DecompressionError {
+    /// Returns whether the error only concerns the stream's metadata, rather than the
+    /// decompressed data itself.
+    ///
+    /// In [lenient](Decompressor::set_lenient) mode, these conditions are reported as
+    /// [`DecompressionWarning`]s instead.
+    pub fn is_metadata_error(&self) -> bool {
+        matches!(
+            self,
+            DecompressionError::WrongChecksum | DecompressionError::ExtraInput
+        )
+    }
 }
 
+#[derive(Clone)]
 struct BlockHeader {
     hlit: usize,
     hdist: usize,
+    /// Number of code length code lengths in the header, or zero once their table is built.
+    hclen: usize,
     num_lengths_read: usize,
 
     /// Low 3-bits are code length code length, high 5-bits are code length code.
@@ -55,14 +215,18 @@ struct BlockHeader {
     code_lengths: [u8; 320],
 }
 
+/// Number of bytes of output to decode before folding them into the checksum, so that they are
+/// still in cache when read back.
+const CHECKSUM_CHUNK_SIZE: usize = 8 * 1024;
+
 const LITERAL_ENTRY: u32 = 0x8000;
 const EXCEPTIONAL_ENTRY: u32 = 0x4000;
 const SECONDARY_TABLE_ENTRY: u32 = 0x2000;
 
 /// The Decompressor state for a compressed block.
 ///
-/// The main litlen_table uses a 12-bit input to lookup the meaning of the symbol. The table is
-/// split into 4 sections:
+/// The main litlen_table uses a `log2(LITLEN_TABLE_SIZE)`-bit input (12 bits by default) to lookup
+/// the meaning of the symbol. The table is split into 4 sections:
 ///
 ///   aaaaaaaa_bbbbbbbb_1000yyyy_0000xxxx  x = input_advance_bits, y = output_advance_bytes (literal)
 ///   0000000z_zzzzzzzz_00000yyy_0000xxxx  x = input_advance_bits, y = extra_bits, z = distance_base (length)
@@ -76,8 +240,8 @@ const SECONDARY_TABLE_ENTRY: u32 = 0x2000;
 ///   zzzzzzzz_zzzzzzzz_0000yyyy_0000xxxx     x = input_advance_bits, y = extra_bits, z = distance_base
 #[repr(align(64))]
 #[derive(Eq, PartialEq, Debug)]
-struct CompressedBlock {
-    litlen_table: [u32; 4096],
+struct CompressedBlock<const LITLEN_TABLE_SIZE: usize> {
+    litlen_table: [u32; LITLEN_TABLE_SIZE],
     dist_table: [u32; 512],
 
     dist_symbol_lengths: [u8; 30],
@@ -90,7 +254,7 @@ struct CompressedBlock {
     eof_bits: u8,
 }
 
-const FDEFLATE_COMPRESSED_BLOCK: CompressedBlock = CompressedBlock {
+const FDEFLATE_COMPRESSED_BLOCK: CompressedBlock<4096> = CompressedBlock {
     litlen_table: FDEFLATE_LITLEN_DECODE_TABLE,
     dist_table: FDEFLATE_DIST_DECODE_TABLE,
     dist_symbol_lengths: [
@@ -110,6 +274,24 @@ const FDEFLATE_COMPRESSED_BLOCK: CompressedBlock = CompressedBlock {
     eof_bits: 0xc,
 };
 
+impl<const LITLEN_TABLE_SIZE: usize> CompressedBlock<LITLEN_TABLE_SIZE> {
+    /// Load the precomputed tables for fdeflate's own Huffman codes. Only valid for the default
+    /// table size.
+    fn load_fdeflate(&mut self) {
+        self.litlen_table
+            .copy_from_slice(&FDEFLATE_LITLEN_DECODE_TABLE);
+        self.dist_table = FDEFLATE_DIST_DECODE_TABLE;
+        self.dist_symbol_lengths = FDEFLATE_COMPRESSED_BLOCK.dist_symbol_lengths;
+        self.dist_symbol_masks = FDEFLATE_COMPRESSED_BLOCK.dist_symbol_masks;
+        self.dist_symbol_codes = FDEFLATE_COMPRESSED_BLOCK.dist_symbol_codes;
+        self.secondary_table.clear();
+        self.eof_code = FDEFLATE_COMPRESSED_BLOCK.eof_code;
+        self.eof_mask = FDEFLATE_COMPRESSED_BLOCK.eof_mask;
+        self.eof_bits = FDEFLATE_COMPRESSED_BLOCK.eof_bits;
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Copy, Clone, Eq, PartialEq)]
 enum State {
     ZlibHeader,
@@ -121,10 +303,169 @@ enum State {
     Done,
 }
 
+/// Number and total size of the blocks of one type, see [`BlockSummary`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockTypeSummary {
+    /// Number of blocks.
+    pub count: u64,
+    /// Compressed size of the blocks in bits, including their headers.
+    pub compressed_bits: u64,
+    /// Number of bytes of output produced by the blocks.
+    pub uncompressed_bytes: u64,
+}
+
+/// Number and sizes of the blocks of each type in a stream.
+///
+/// Returned by [`Decompressor::block_summary`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockSummary {
+    /// Stored (uncompressed) blocks.
+    pub stored: BlockTypeSummary,
+    /// Blocks using the fixed Huffman codes.
+    pub fixed: BlockTypeSummary,
+    /// Blocks using dynamic Huffman codes.
+    pub dynamic: BlockTypeSummary,
+}
+
+impl BlockSummary {
+    fn get_mut(&mut self, block_type: BlockType) -> &mut BlockTypeSummary {
+        match block_type {
+            BlockType::Stored => &mut self.stored,
+            BlockType::Fixed => &mut self.fixed,
+            BlockType::Dynamic => &mut self.dynamic,
+        }
+    }
+}
+
+/// Counters describing how the decompressor spent its time, for tuning the table encodings.
+///
+/// Only available with the `perf-stats` feature. See [`Decompressor::perf_stats`].
+#[cfg(feature = "perf-stats")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PerfStats {
+    /// Literals decoded directly from the primary table by the fast paths.
+    pub fast_path_symbols: u64,
+    /// Symbols decoded by the slow path: lengths with their distances, end of block markers and
+    /// literals with long codes.
+    pub slow_path_symbols: u64,
+    /// Symbols whose code didn't fit in the primary table and needed a secondary table lookup.
+    pub secondary_table_hits: u64,
+    /// Bit buffer refills that had to fall back to byte-at-a-time reads because fewer than eight
+    /// bytes of input were available.
+    pub refill_stalls: u64,
+    /// Bytes of output produced by stored blocks.
+    pub stored_bytes: u64,
+    /// Bytes of output produced by blocks using the fixed Huffman codes.
+    pub fixed_bytes: u64,
+    /// Bytes of output produced by blocks using dynamic Huffman codes.
+    pub dynamic_bytes: u64,
+}
+
+/// Memory that a decompressor uses for temporary tables, owned by the caller.
+///
+/// The only table a decompressor allocates on the heap holds the codes that are too long for
+/// its primary lookup table. It is allocated at its maximum size when the decompressor is
+/// created, and reused for every dynamic block. Passing scratch memory to
+/// [`CustomDecompressor::with_scratch`] and getting it back with
+/// [`CustomDecompressor::take_scratch`] lets callers move that allocation between decompressors
+/// or keep it in a pool, and scratch memory from [`DecompressorScratch::preallocated`] is large
+/// enough that creating a decompressor with it doesn't allocate either.
+#[derive(Debug, Default)]
+pub struct DecompressorScratch {
+    secondary_table: Vec<u16>,
+}
+
+impl DecompressorScratch {
+    /// Create empty scratch memory, leaving the allocation to the decompressor that uses it.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create scratch memory large enough for any stream and any table size, so that
+    /// decompressors using it never allocate while decoding.
+    pub fn preallocated() -> Self {
+        Self::from_vec(Vec::with_capacity(1 << 15))
+    }
+
+    /// Use the allocation of `buffer` as scratch memory. Its contents are discarded.
+    pub fn from_vec(mut buffer: Vec<u16>) -> Self {
+        buffer.clear();
+        Self {
+            secondary_table: buffer,
+        }
+    }
+
+    /// Returns the underlying allocation, for example to return it to a pool.
+    pub fn into_vec(self) -> Vec<u16> {
+        self.secondary_table
+    }
+
+    /// Returns the number of bytes of scratch memory allocated.
+    pub fn allocated_bytes(&self) -> usize {
+        self.secondary_table.capacity() * 2
+    }
+}
+
 /// Decompressor for arbitrary zlib streams.
-pub struct Decompressor {
+///
+/// This is a [`CustomDecompressor`] with the default 4096-entry (12-bit) primary lookup table.
+pub type Decompressor = CustomDecompressor<4096>;
+
+/// Decompressor with a small 512-entry (9-bit) primary lookup table.
+///
+/// Its primary decoding tables take about 4 KiB instead of the 18 KiB used by [`Decompressor`],
+/// which makes it suitable for memory-constrained environments. Codes longer than 9 bits are
+/// decoded with a second lookup, so decompression is somewhat slower, and the heap-allocated
+/// table for them can take up to 18 KiB instead of 2 KiB.
+pub type SmallDecompressor = CustomDecompressor<512>;
+
+/// Decompressor for arbitrary zlib streams with a configurable primary lookup table size.
+///
+/// `LITLEN_TABLE_SIZE` is the number of entries in the table used to decode literal/length
+/// symbols, and must be a power of two between 256 (8 bits) and 8192 (13 bits). Each entry takes
+/// four bytes. Smaller tables reduce the memory and cache footprint of the decompressor at the
+/// cost of more lookups in the slower secondary table for long codes, while larger ones can help
+/// with streams that use many long codes such as text. Most users should use [`Decompressor`].
+///
+/// # Thread safety
+///
+/// Decompressors are `Send`, so a partially decoded stream can be parked and resumed on a
+/// different worker thread. They are not `Sync`: the progress callback is only required to be
+/// `Send`, and since every decoding method takes `&mut self` there would be nothing to gain from
+/// sharing a decompressor between threads anyway.
+///
+/// # Memory allocation
+///
+/// All memory needed for decoding is allocated when the decompressor is created, so
+/// [`read`](CustomDecompressor::read) never allocates, whatever the stream contains. The only
+/// exceptions are the progress callback and output hasher, which run caller-supplied code, and
+/// decoding after [`take_scratch`](CustomDecompressor::take_scratch).
+///
+/// # Serialization
+///
+/// With the `serde` feature, decompressors implement `Serialize` and `Deserialize`, so that a
+/// partially decoded stream can be suspended and resumed later or in another process. Decoding
+/// continues with the input following [`byte_position`](CustomDecompressor::byte_position), and
+/// unlike with a [`ResumePoint`] the checksum is still verified. Back-references may reach up to
+/// 32 KiB before `output_position`, so that much of the previous output must be saved along with
+/// the decompressor, except when using [`read_windowed`](CustomDecompressor::read_windowed) and
+/// related methods, whose window is serialized with it. The decoding tables are rebuilt after
+/// deserializing. Callbacks, the cancellation flag, the output hasher and the performance
+/// counters are not serialized, and can be set again on the restored decompressor.
+///
+/// # Cloning
+///
+/// Cloning a decompressor forks the stream: the copy continues independently from the same
+/// position, for instance to decode ahead speculatively while keeping the original as a restore
+/// point, or to start several workers from a preconfigured template. Each copy needs its own copy
+/// of the previous output (up to 32 KiB) for back-references to refer to. The copy shares the
+/// [cancellation flag](CustomDecompressor::set_cancellation_flag), but has no progress callback or
+/// output hasher, since those can't be duplicated.
+pub struct CustomDecompressor<const LITLEN_TABLE_SIZE: usize> {
     /// State for decoding a compressed block.
-    compression: CompressedBlock,
+    compression: CompressedBlock<LITLEN_TABLE_SIZE>,
     // State for decoding a block header.
     header: BlockHeader,
     // Number of bytes left for uncompressed block.
@@ -132,6 +473,18 @@ pub struct Decompressor {
 
     buffer: u64,
     nbits: u8,
+    /// Number of input bytes consumed so far, including any that are still held in `buffer`.
+    bytes_read: u64,
+    /// Bit position of the header of the current block.
+    block_start: u64,
+    /// Type of the current block.
+    block_type: Option<BlockType>,
+    /// Kind of tables that still need to be built before decoding the current block.
+    pending_tables: Option<BlockType>,
+    /// Whether `compression` currently holds the tables for the fixed Huffman codes.
+    fixed_tables_built: bool,
+    /// Number of block headers that have been read.
+    block_count: u64,
 
     queued_rle: Option<(u8, usize)>,
     queued_backref: Option<(usize, usize)>,
@@ -140,18 +493,128 @@ pub struct Decompressor {
     state: State,
     checksum: Adler32,
     ignore_adler32: bool,
+    /// Whether the stream is raw deflate data, without a zlib header and checksum.
+    raw: bool,
+    lenient: bool,
+    borrow_stored: bool,
+    stop_at_block_boundaries: bool,
+    /// Whether the last call to `read` stopped at a block boundary.
+    at_block_boundary: bool,
+    /// Whether any input was passed past the end of the stream.
+    trailing_data: bool,
+    warnings: Vec<DecompressionWarning>,
+    max_output_per_call: usize,
+    max_distance: usize,
+    output_limit: u64,
+    /// Maximum ratio of output to input, and the amount of output before it is enforced.
+    max_expansion_ratio: Option<(u64, u64)>,
+    /// Total number of bytes of output produced so far.
+    total_output: u64,
+    #[cfg(feature = "perf-stats")]
+    stats: PerfStats,
+    summary: BlockSummary,
+    /// Type and starting bit position of the block being decoded, until its end is reached.
+    open_block: Option<(BlockType, u64)>,
+    progress: Option<ProgressCallback>,
+    cancellation_flag: Option<Arc<AtomicBool>>,
+    output_hasher: Option<Box<dyn OutputHasher>>,
+    /// Value of `total_output` corresponding to index zero of the current output buffer.
+    output_base: u64,
+    /// History buffer used by [`Decompressor::read_windowed`], allocated on first use.
+    internal_window: Vec<u8>,
+    /// End of the decompressed data in `internal_window`.
+    internal_window_end: usize,
+}
+
+/// A hash function or other sink that can be fed the decompressed data.
+///
+/// See [`Decompressor::set_output_hasher`]. Wrappers around hashers from other crates, such as
+/// SHA-256 implementations, only need to forward `update` to them.
+pub trait Update {
+    /// Process the next piece of data.
+    fn update(&mut self, data: &[u8]);
+}
+
+/// Object-safe wrapper around [`Update`] that allows getting the concrete type back.
+trait OutputHasher: Send {
+    fn update(&mut self, data: &[u8]);
+    fn as_any(&self) -> &dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<H: Update + Send + 'static> OutputHasher for H {
+    fn update(&mut self, data: &[u8]) {
+        Update::update(self, data);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// A registered progress callback, see [`Decompressor::set_progress_callback`].
+struct ProgressCallback {
+    callback: Box<dyn FnMut(u64, u64) + Send>,
+    interval: u64,
+    next_report: u64,
 }
 
-impl Decompressor {
+impl<const LITLEN_TABLE_SIZE: usize> CustomDecompressor<LITLEN_TABLE_SIZE> {
+    /// Number of bits used to index the primary literal/length table.
+    const TABLE_BITS: u8 = LITLEN_TABLE_SIZE.trailing_zeros() as u8;
+    /// Number of additional bits used to index the secondary table for codes that are too long
+    /// for the primary table.
+    const SECONDARY_BITS: u8 = 15 - Self::TABLE_BITS;
+    /// Upper bound on the length of the secondary table. Since literal/length codes must be
+    /// complete, each primary table entry that leads to the secondary table is shared by at least
+    /// two of the 288 codes.
+    const MAX_SECONDARY_TABLE_LEN: usize = if LITLEN_TABLE_SIZE < 144 {
+        LITLEN_TABLE_SIZE
+    } else {
+        144
+    } << Self::SECONDARY_BITS;
+
     /// Create a new decompressor.
     pub fn new() -> Self {
+        Self::with_secondary_table(Vec::with_capacity(Self::MAX_SECONDARY_TABLE_LEN))
+    }
+
+    /// Create a decompressor for a raw deflate stream, which has no zlib header or checksum.
+    ///
+    /// This is the format of the compressed data in gzip members and zip entries. The stream
+    /// ends after its final block, and [`Decompressor::compressed_len`] tells where the data
+    /// following it starts. To continue a raw stream from earlier data, use
+    /// [`Decompressor::with_window`] instead.
+    pub fn new_raw() -> Self {
+        let mut decompressor = Self::new();
+        decompressor.ignore_adler32 = true;
+        decompressor.raw = true;
+        decompressor.state = State::BlockHeader;
+        decompressor
+    }
+
+    fn with_secondary_table(secondary_table: Vec<u16>) -> Self {
+        assert!(
+            LITLEN_TABLE_SIZE.is_power_of_two() && (256..=8192).contains(&LITLEN_TABLE_SIZE),
+            "LITLEN_TABLE_SIZE must be a power of two between 256 and 8192"
+        );
         Self {
             buffer: 0,
             nbits: 0,
+            bytes_read: 0,
+            block_start: 0,
+            block_type: None,
+            pending_tables: None,
+            fixed_tables_built: false,
+            block_count: 0,
             compression: CompressedBlock {
-                litlen_table: [0; 4096],
+                litlen_table: [0; LITLEN_TABLE_SIZE],
                 dist_table: [0; 512],
-                secondary_table: Vec::new(),
+                secondary_table,
                 dist_symbol_lengths: [0; 30],
                 dist_symbol_masks: [0; 30],
                 dist_symbol_codes: [0xffff; 30],
@@ -163,6 +626,7 @@ impl Decompressor {
                 hlit: 0,
                 hdist: 0,
                 table: [0; 128],
+                hclen: 0,
                 num_lengths_read: 0,
                 code_lengths: [0; 320],
             },
@@ -173,6 +637,86 @@ impl Decompressor {
             state: State::ZlibHeader,
             last_block: false,
             ignore_adler32: false,
+            raw: false,
+            lenient: false,
+            borrow_stored: false,
+            stop_at_block_boundaries: false,
+            at_block_boundary: false,
+            trailing_data: false,
+            warnings: Vec::new(),
+            max_output_per_call: usize::MAX,
+            max_distance: 32768,
+            output_limit: u64::MAX,
+            max_expansion_ratio: None,
+            total_output: 0,
+            #[cfg(feature = "perf-stats")]
+            stats: PerfStats::default(),
+            summary: BlockSummary::default(),
+            open_block: None,
+            progress: None,
+            cancellation_flag: None,
+            output_hasher: None,
+            output_base: 0,
+            internal_window: Vec::new(),
+            internal_window_end: 0,
+        }
+    }
+
+    /// Create a new decompressor that uses `scratch` for its heap-allocated tables.
+    ///
+    /// See [`DecompressorScratch`] for details.
+    pub fn with_scratch(scratch: DecompressorScratch) -> Self {
+        let mut secondary_table = scratch.secondary_table;
+        secondary_table.reserve(Self::MAX_SECONDARY_TABLE_LEN);
+        Self::with_secondary_table(secondary_table)
+    }
+
+    /// Take back the scratch memory of the decompressor, so that it can be reused elsewhere.
+    ///
+    /// The decompressor allocates new scratch memory if it decodes further blocks that need it,
+    /// so it should normally be discarded afterwards.
+    pub fn take_scratch(&mut self) -> DecompressorScratch {
+        let mut secondary_table = std::mem::take(&mut self.compression.secondary_table);
+        secondary_table.clear();
+        DecompressorScratch { secondary_table }
+    }
+
+    /// Prepare the decompressor to decode a new stream from the start.
+    ///
+    /// This is cheaper than creating a new decompressor, since the decoding tables and their
+    /// allocation are kept. Settings like limits, callbacks and [lenient](Self::set_lenient) mode
+    /// stay in effect, as do the [performance counters](Self::perf_stats) if enabled. Everything
+    /// about the previous stream, including its warnings, is discarded.
+    pub fn reset(&mut self) {
+        self.buffer = 0;
+        self.nbits = 0;
+        self.bytes_read = 0;
+        self.block_start = 0;
+        self.block_type = None;
+        self.pending_tables = None;
+        self.block_count = 0;
+        self.header.hclen = 0;
+        self.header.num_lengths_read = 0;
+        self.uncompressed_bytes_left = 0;
+        self.queued_rle = None;
+        self.queued_backref = None;
+        self.last_block = false;
+        self.checksum = Adler32::new();
+        self.state = if self.raw {
+            State::BlockHeader
+        } else {
+            State::ZlibHeader
+        };
+        self.at_block_boundary = false;
+        self.trailing_data = false;
+        self.warnings.clear();
+        self.total_output = 0;
+        self.summary = BlockSummary::default();
+        self.open_block = None;
+        self.output_base = 0;
+        self.internal_window_end = 0;
+        if let Some(progress) = &mut self.progress {
+            progress.next_report = 0;
         }
     }
 
@@ -181,20 +725,275 @@ impl Decompressor {
         self.ignore_adler32 = true;
     }
 
+    /// Accept streams with minor violations of the deflate specification that zlib tolerates.
+    ///
+    /// This allows dynamic block headers to declare up to 288 literal/length codes and 32
+    /// distance codes (as produced by some old encoders), provided that the extra codes are never
+    /// actually used. Problems that don't affect the decompressed data, like an incorrect
+    /// checksum, are recorded as [warnings](Decompressor::warnings) instead of errors.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+        // Each kind of warning is only recorded once.
+        self.warnings.reserve(3);
+    }
+
+    /// Returns whether [lenient](Decompressor::set_lenient) mode is enabled.
+    pub fn is_lenient(&self) -> bool {
+        self.lenient
+    }
+
+    /// Returns the warnings encountered so far in [lenient](Decompressor::set_lenient) mode.
+    pub fn warnings(&self) -> &[DecompressionWarning] {
+        &self.warnings
+    }
+
+    /// Returns the performance counters collected so far.
+    #[cfg(feature = "perf-stats")]
+    pub fn perf_stats(&self) -> &PerfStats {
+        &self.stats
+    }
+
+    /// Attribute `n` bytes of output to the current block type.
+    fn count_block_bytes(&mut self, n: usize) {
+        if let Some(block_type) = self.block_type {
+            self.summary.get_mut(block_type).uncompressed_bytes += n as u64;
+            #[cfg(feature = "perf-stats")]
+            match block_type {
+                BlockType::Stored => self.stats.stored_bytes += n as u64,
+                BlockType::Fixed => self.stats.fixed_bytes += n as u64,
+                BlockType::Dynamic => self.stats.dynamic_bytes += n as u64,
+            }
+        }
+    }
+
+    /// Add the compressed size of the block that just ended to the summary.
+    fn end_block(&mut self) {
+        if let Some((block_type, start)) = self.open_block.take() {
+            self.summary.get_mut(block_type).compressed_bits += self.bit_position() - start;
+        }
+    }
+
+    /// Returns the number and sizes of the blocks of each type decoded so far.
+    ///
+    /// The block that is currently being decoded is included, with the compressed and
+    /// uncompressed sizes of the part decoded so far.
+    pub fn block_summary(&self) -> BlockSummary {
+        let mut summary = self.summary;
+        if let Some((block_type, start)) = self.open_block {
+            summary.get_mut(block_type).compressed_bits += self.bit_position() - start;
+        }
+        summary
+    }
+
+    /// Register a callback that reports the progress of decompression.
+    ///
+    /// The callback is passed the total number of input bytes consumed and output bytes produced
+    /// so far. It is invoked at the start of every block and whenever roughly `interval` more
+    /// bytes of output have been produced, even in the middle of a single large call to
+    /// [`Decompressor::read`].
+    pub fn set_progress_callback<F>(&mut self, interval: u64, callback: F)
+    where
+        F: FnMut(u64, u64) + Send + 'static,
+    {
+        self.progress = Some(ProgressCallback {
+            callback: Box::new(callback),
+            interval,
+            next_report: 0,
+        });
+    }
+
+    /// Feed all decompressed data to `hasher` as it is produced.
+    ///
+    /// This lets a digest of the output, like a SHA-256 hash, be computed in the same pass as
+    /// decompression and the Adler-32 checksum, while the data is still in cache. Output that is
+    /// already in the buffer before `output_position` when [`Decompressor::read`] is called isn't
+    /// included. Use [`Decompressor::take_output_hasher`] to get the hasher back.
+    pub fn set_output_hasher<H: Update + Send + 'static>(&mut self, hasher: H) {
+        self.output_hasher = Some(Box::new(hasher));
+    }
+
+    /// Remove the hasher registered with [`Decompressor::set_output_hasher`] and return it.
+    ///
+    /// Returns `None`, leaving the hasher in place, if there is no hasher or it isn't of type `H`.
+    pub fn take_output_hasher<H: Update + 'static>(&mut self) -> Option<H> {
+        if !self.output_hasher.as_ref()?.as_any().is::<H>() {
+            return None;
+        }
+        let hasher = self.output_hasher.take()?.into_any().downcast().ok()?;
+        Some(*hasher)
+    }
+
+    /// Abort decompression once `flag` is set.
+    ///
+    /// The flag is checked at the start of every block and after every few kilobytes of output,
+    /// so another thread can stop even a single large call to [`Decompressor::read`] promptly.
+    /// Once cancelled, `read` returns [`DecompressionError::Cancelled`] and the decompressor can't
+    /// be used any further.
+    pub fn set_cancellation_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.cancellation_flag = Some(flag);
+    }
+
+    fn check_cancelled(&self) -> Result<(), DecompressionError> {
+        match &self.cancellation_flag {
+            Some(flag) if flag.load(Ordering::Relaxed) => Err(DecompressionError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Invoke the progress callback if it is due (or `force` is set).
+    fn report_progress(&mut self, output_index: usize, force: bool) {
+        if let Some(progress) = &mut self.progress {
+            let total_output = self.output_base.wrapping_add(output_index as u64);
+            if force || total_output >= progress.next_report {
+                (progress.callback)(self.bytes_read, total_output);
+                progress.next_report = total_output.saturating_add(progress.interval.max(1));
+            }
+        }
+    }
+
+    fn add_warning(&mut self, warning: DecompressionWarning) {
+        if self.lenient && !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    fn add_trailing_data(&mut self) {
+        self.trailing_data = true;
+        self.add_warning(DecompressionWarning::TrailingData);
+    }
+
+    /// Limit the number of bytes that a single call to [`Decompressor::read`] may produce.
+    ///
+    /// Any output beyond `output_position + max_output` is left untouched, even if the output
+    /// buffer is larger. The limit must be at least 1.
+    pub fn set_max_output_per_call(&mut self, max_output: usize) {
+        assert!(max_output >= 1);
+        self.max_output_per_call = max_output;
+    }
+
+    /// Limit the maximum distance of back-references.
+    ///
+    /// Streams containing back-references further than `max_distance` bytes produce a
+    /// [`DecompressionError::DistanceTooFarBack`] error, regardless of the window size specified
+    /// in the zlib header. This bounds how much prior output must be retained while decoding.
+    pub fn set_max_distance(&mut self, max_distance: usize) {
+        self.max_distance = max_distance;
+    }
+
+    /// Limit the total number of bytes the stream may decompress to.
+    ///
+    /// Once the stream produces more than `limit` bytes of output, [`Decompressor::read`] returns
+    /// [`DecompressionError::OutputLimitExceeded`]. This guards against maliciously crafted
+    /// streams with extreme compression ratios.
+    pub fn set_output_limit(&mut self, limit: u64) {
+        self.output_limit = limit;
+    }
+
+    /// Limit the ratio between the size of the output and the size of the compressed input.
+    ///
+    /// Once at least `min_output` bytes have been produced, [`Decompressor::read`] returns
+    /// [`DecompressionError::ExpansionRatioExceeded`] whenever the output is more than `ratio`
+    /// times larger than the input consumed so far. Unlike an absolute output limit, this also
+    /// catches streams that expand to many times their size without any single one being large.
+    pub fn set_max_expansion_ratio(&mut self, ratio: u64, min_output: u64) {
+        self.max_expansion_ratio = Some((ratio, min_output));
+    }
+
+    /// Leave the contents of stored blocks in the input so they can be borrowed.
+    ///
+    /// When enabled, [`Decompressor::read`] stops at the start of the payload of each stored
+    /// block instead of copying it into the output buffer. The payload must then be retrieved with
+    /// [`Decompressor::read_stored`], which returns it as a subslice of the input.
+    pub fn set_borrow_stored_blocks(&mut self, borrow: bool) {
+        self.borrow_stored = borrow;
+    }
+
+    /// Return from [`Decompressor::read`] whenever a deflate block boundary is reached.
+    ///
+    /// This is the equivalent of zlib's `Z_BLOCK` flush mode. When enabled, `read` stops right
+    /// after the zlib header, at the end of every block, and at the end of the final block before
+    /// the checksum, so that callers can record the positions of blocks (for instance to build an
+    /// index for random access) or inspect each block before decoding it. Use
+    /// [`Decompressor::block_boundary`] to tell whether a call stopped at a boundary, since it may
+    /// do so without consuming or producing anything.
+    pub fn set_stop_at_block_boundaries(&mut self, stop: bool) {
+        self.stop_at_block_boundaries = stop;
+    }
+
+    /// Returns the bit offset of the block boundary at which the last call to
+    /// [`Decompressor::read`] stopped, or `None` if it didn't stop at one.
+    ///
+    /// See [`Decompressor::set_stop_at_block_boundaries`]. The offset is counted from the start of
+    /// the stream, like [`Decompressor::bit_position`], and is where the header of the next block
+    /// (or the checksum, once [`Decompressor::last_block_seen`] is true) begins.
+    pub fn block_boundary(&self) -> Option<u64> {
+        if self.at_block_boundary {
+            Some(self.bit_position())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the next part of a stored block as a subslice of `input`, without copying it.
+    ///
+    /// Returns the number of bytes consumed from `input` along with the stored data, which is
+    /// empty unless the decompressor is positioned within the payload of a stored block (see
+    /// [`Decompressor::set_borrow_stored_blocks`]); in that case [`Decompressor::read`] should
+    /// be called instead. The data is still included in the checksum and output limits.
+    ///
+    /// Later blocks may contain back-references into the returned bytes, so unless the stream is
+    /// known to consist only of stored blocks, they must also be placed in the output buffer
+    /// before the next call to `read`.
+    pub fn read_stored<'a>(
+        &mut self,
+        input: &'a [u8],
+    ) -> Result<(usize, &'a [u8]), DecompressionError> {
+        if self.state != State::UncompressedData || self.nbits > 0 {
+            return Ok((0, &[]));
+        }
+
+        let n = (self.uncompressed_bytes_left as usize).min(input.len());
+        let data = &input[..n];
+        self.hash_output(data);
+        self.bytes_read += n as u64;
+        self.total_output += n as u64;
+        self.uncompressed_bytes_left -= n as u16;
+        self.count_block_bytes(n);
+        if self.uncompressed_bytes_left == 0 {
+            self.state = if self.last_block {
+                State::Checksum
+            } else {
+                State::BlockHeader
+            };
+        }
+
+        if self.total_output > self.output_limit {
+            return Err(DecompressionError::OutputLimitExceeded);
+        }
+        Ok((n, data))
+    }
+
     fn fill_buffer(&mut self, input: &mut &[u8]) {
         if self.nbits == 64 {
             /* do nothing */
         } else if input.len() >= 8 {
             self.buffer |= u64::from_le_bytes(input[..8].try_into().unwrap()) << self.nbits;
-            *input = &mut &input[(63 - self.nbits as usize) / 8..];
+            let nbytes = (63 - self.nbits as usize) / 8;
+            self.bytes_read += nbytes as u64;
+            *input = &input[nbytes..];
             self.nbits |= 56;
         } else {
+            #[cfg(feature = "perf-stats")]
+            {
+                self.stats.refill_stalls += 1;
+            }
             let nbytes = input.len().min((64 - self.nbits as usize) / 8);
             let mut input_data = [0; 8];
             input_data[..nbytes].copy_from_slice(&input[..nbytes]);
             self.buffer |= u64::from_le_bytes(input_data) << self.nbits;
             self.nbits += nbytes as u8 * 8;
-            *input = &mut &input[nbytes..];
+            self.bytes_read += nbytes as u64;
+            *input = &input[nbytes..];
         }
     }
 
@@ -208,23 +1007,9 @@ impl Decompressor {
         self.nbits -= nbits;
     }
 
-    fn read_bits(&mut self, nbits: u8, input: &mut &[u8]) -> Option<u64> {
-        if self.nbits < nbits {
-            self.fill_buffer(input);
-            if self.nbits < nbits {
-                return None;
-            }
-        }
-
-        let result = self.peak_bits(nbits);
-        self.consume_bits(nbits);
-        Some(result)
-    }
-
-    fn read_block_header(
-        &mut self,
-        mut remaining_input: &mut &[u8],
-    ) -> Result<(), DecompressionError> {
+    fn read_block_header(&mut self, remaining_input: &mut &[u8]) -> Result<(), DecompressionError> {
+        self.end_block();
+        self.block_start = self.bit_position();
         self.fill_buffer(remaining_input);
         if self.nbits < 3 {
             return Ok(());
@@ -249,65 +1034,88 @@ impl Decompressor {
                 self.state = State::UncompressedData;
                 self.uncompressed_bytes_left = len;
                 self.consume_bits(header_bits);
-                return Ok(());
+                self.start_block(BlockType::Stored);
+                Ok(())
             }
             0b01 => {
                 self.consume_bits(3);
-                // TODO: Do this statically rather than every time.
-                Self::build_tables(288, &FIXED_CODE_LENGTHS, &mut self.compression, 6)?;
+                self.pending_tables = Some(BlockType::Fixed);
                 self.state = State::CompressedData;
-                return Ok(());
+                self.start_block(BlockType::Fixed);
+                Ok(())
             }
             0b10 => {
                 if self.nbits < 17 {
                     return Ok(());
                 }
-                let hclen = (self.peak_bits(17) >> 13) as usize + 4;
-                if self.nbits as usize + remaining_input.len() * 8 < 17 + 3 * hclen {
-                    return Ok(());
-                }
-
                 self.header.hlit = (self.peak_bits(8) >> 3) as usize + 257;
                 self.header.hdist = (self.peak_bits(13) >> 8) as usize + 1;
-                if self.header.hlit > 286 {
+                let (max_hlit, max_hdist) = if self.lenient { (288, 32) } else { (286, 30) };
+                if self.header.hlit > max_hlit {
                     return Err(DecompressionError::InvalidHlit);
                 }
-                if self.header.hdist > 30 {
+                if self.header.hdist > max_hdist {
                     return Err(DecompressionError::InvalidHdist);
                 }
 
+                // The code length code lengths are read as part of the CodeLengths state, since the
+                // whole header may not fit in the bit buffer at once.
+                self.header.hclen = (self.peak_bits(17) >> 13) as usize + 4;
+                self.header.code_lengths[..19].fill(0);
                 self.consume_bits(17);
-                let mut code_length_lengths = [0; 19];
-                for i in 0..hclen {
-                    code_length_lengths[CLCL_ORDER[i]] =
-                        self.read_bits(3, &mut remaining_input).unwrap() as u8;
-                }
-                let code_length_codes: [u16; 19] =
-                    crate::compute_codes(&code_length_lengths.try_into().unwrap())
-                        .ok_or(DecompressionError::BadCodeLengthHuffmanTree)?;
-
-                self.header.table = [255; 128];
-                for i in 0..19 {
-                    let length = code_length_lengths[i];
-                    if length > 0 {
-                        let mut j = code_length_codes[i];
-                        while j < 128 {
-                            self.header.table[j as usize] = ((i as u8) << 3) | length;
-                            j += 1 << length;
-                        }
-                    }
-                }
 
                 self.state = State::CodeLengths;
                 self.header.num_lengths_read = 0;
-                return Ok(());
+                self.start_block(BlockType::Dynamic);
+                Ok(())
             }
-            0b11 => return Err(DecompressionError::InvalidBlockType),
+            0b11 => Err(DecompressionError::InvalidBlockType),
             _ => unreachable!(),
         }
     }
 
+    fn start_block(&mut self, block_type: BlockType) {
+        self.block_type = Some(block_type);
+        self.block_count += 1;
+        self.summary.get_mut(block_type).count += 1;
+        self.open_block = Some((block_type, self.block_start));
+    }
+
     fn read_code_lengths(&mut self, remaining_input: &mut &[u8]) -> Result<(), DecompressionError> {
+        if self.header.hclen > 0 {
+            // The code length code lengths are stored at the start of `code_lengths` until the
+            // table is built, with `num_lengths_read` counting how many were read so far.
+            while self.header.num_lengths_read < self.header.hclen {
+                self.fill_buffer(remaining_input);
+                if self.nbits < 3 {
+                    return Ok(());
+                }
+                self.header.code_lengths[CLCL_ORDER[self.header.num_lengths_read]] =
+                    self.peak_bits(3) as u8;
+                self.header.num_lengths_read += 1;
+                self.consume_bits(3);
+            }
+
+            let mut code_length_lengths = [0; 19];
+            code_length_lengths.copy_from_slice(&self.header.code_lengths[..19]);
+            let code_length_codes: [u16; 19] = crate::compute_codes(&code_length_lengths)
+                .ok_or(DecompressionError::BadCodeLengthHuffmanTree)?;
+
+            self.header.table = [255; 128];
+            for i in 0..19 {
+                let length = code_length_lengths[i];
+                if length > 0 {
+                    let mut j = code_length_codes[i];
+                    while j < 128 {
+                        self.header.table[j as usize] = ((i as u8) << 3) | length;
+                        j += 1 << length;
+                    }
+                }
+            }
+            self.header.hclen = 0;
+            self.header.num_lengths_read = 0;
+        }
+
         let total_lengths = self.header.hlit + self.header.hdist;
         while self.header.num_lengths_read < total_lengths {
             self.fill_buffer(remaining_input);
@@ -327,7 +1135,7 @@ impl Decompressor {
                     self.header.num_lengths_read += 1;
                     self.consume_bits(length);
                 }
-                16 | 17 | 18 => {
+                16..=18 => {
                     let (base_repeat, extra_bits) = match symbol {
                         16 => (3, 2),
                         17 => (3, 3),
@@ -379,27 +1187,54 @@ impl Decompressor {
             self.header.code_lengths[i] = 0;
         }
 
-        if self.header.hdist == 1
+        self.pending_tables = Some(BlockType::Dynamic);
+        self.state = State::CompressedData;
+        Ok(())
+    }
+
+    /// Whether the code lengths of the current dynamic block are the ones written by fdeflate's
+    /// compressor: its fixed literal/length code, and a single distance code (for distance 1).
+    fn uses_fdeflate_codes(&self) -> bool {
+        self.header.hdist == 1
             && self.header.code_lengths[..286] == tables::HUFFMAN_LENGTHS
             && self.header.code_lengths[288] == 1
-        {
-            self.compression = FDEFLATE_COMPRESSED_BLOCK;
-        } else {
-            Self::build_tables(
-                self.header.hlit,
-                &self.header.code_lengths,
-                &mut self.compression,
-                6,
-            )?;
+    }
+
+    /// Build the decoding tables for the current block, if they haven't been built yet.
+    ///
+    /// This is deferred until the first symbol of the block is decoded, so that no work is spent
+    /// on tables that are never used. The tables for fixed Huffman codes are kept around so
+    /// consecutive fixed blocks don't rebuild them.
+    fn build_pending_tables(&mut self) -> Result<(), DecompressionError> {
+        match self.pending_tables.take() {
+            Some(BlockType::Fixed) => {
+                if !self.fixed_tables_built {
+                    Self::build_tables(288, &FIXED_CODE_LENGTHS, &mut self.compression, 6)?;
+                    self.fixed_tables_built = true;
+                }
+            }
+            Some(BlockType::Dynamic) => {
+                self.fixed_tables_built = false;
+                if LITLEN_TABLE_SIZE == 4096 && self.uses_fdeflate_codes() {
+                    self.compression.load_fdeflate();
+                } else {
+                    Self::build_tables(
+                        self.header.hlit,
+                        &self.header.code_lengths,
+                        &mut self.compression,
+                        6,
+                    )?;
+                }
+            }
+            Some(BlockType::Stored) | None => {}
         }
-        self.state = State::CompressedData;
         Ok(())
     }
 
     fn build_tables(
         hlit: usize,
         code_lengths: &[u8],
-        compression: &mut CompressedBlock,
+        compression: &mut CompressedBlock<LITLEN_TABLE_SIZE>,
         max_search_bits: u8,
     ) -> Result<(), DecompressionError> {
         // Build the literal/length code table.
@@ -407,25 +1242,44 @@ impl Decompressor {
         let codes: [u16; 288] = crate::compute_codes(&lengths.try_into().unwrap())
             .ok_or(DecompressionError::BadLiteralLengthHuffmanTree)?;
 
-        let table_bits = lengths.iter().cloned().max().unwrap().min(12).max(6);
+        let table_bits = lengths
+            .iter()
+            .cloned()
+            .max()
+            .unwrap()
+            .clamp(6, Self::TABLE_BITS);
         let table_size = 1 << table_bits;
 
-        for i in 0..256 {
+        // Only visit literals that are actually used. Small images and fdeflate's own output often
+        // use just a handful of them, which makes the pairwise search below much cheaper.
+        let mut used_literals = [0u8; 256];
+        let mut num_used_literals = 0;
+        for (i, &length) in lengths[..256].iter().enumerate() {
+            if length != 0 {
+                used_literals[num_used_literals] = i as u8;
+                num_used_literals += 1;
+            }
+        }
+        let used_literals = &used_literals[..num_used_literals];
+
+        for &i in used_literals {
+            let i = i as usize;
             let code = codes[i];
             let length = lengths[i];
             let mut j = code;
 
-            while j < table_size && length != 0 && length <= 12 {
+            while j < table_size && length <= Self::TABLE_BITS {
                 compression.litlen_table[j as usize] =
                     ((i as u32) << 16) | LITERAL_ENTRY | (1 << 8) | length as u32;
                 j += 1 << length;
             }
 
-            if length > 0 && length <= max_search_bits {
-                for ii in 0..256 {
+            if length <= max_search_bits {
+                for &ii in used_literals {
+                    let ii = ii as usize;
                     let code2 = codes[ii];
                     let length2 = lengths[ii];
-                    if length2 != 0 && length + length2 <= table_bits {
+                    if length + length2 <= table_bits {
                         let mut j = code | (code2 << length);
 
                         while j < table_size {
@@ -441,7 +1295,7 @@ impl Decompressor {
             }
         }
 
-        if lengths[256] != 0 && lengths[256] <= 12 {
+        if lengths[256] != 0 && lengths[256] <= Self::TABLE_BITS {
             let mut j = codes[256];
             while j < table_size {
                 compression.litlen_table[j as usize] = EXCEPTIONAL_ENTRY | lengths[256] as u32;
@@ -450,7 +1304,7 @@ impl Decompressor {
         }
 
         let table_size = table_size as usize;
-        for i in (table_size..4096).step_by(table_size) {
+        for i in (table_size..LITLEN_TABLE_SIZE).step_by(table_size) {
             compression.litlen_table.copy_within(0..table_size, i);
         }
 
@@ -461,12 +1315,12 @@ impl Decompressor {
         for i in 257..hlit {
             let code = codes[i];
             let length = lengths[i];
-            if length != 0 && length <= 12 {
+            if length != 0 && length <= Self::TABLE_BITS {
                 let mut j = code;
-                while j < 4096 {
+                while (j as usize) < LITLEN_TABLE_SIZE {
                     compression.litlen_table[j as usize] = if i < 286 {
-                        (LEN_SYM_TO_LEN_BASE[i as usize - 257] as u32) << 16
-                            | (LEN_SYM_TO_LEN_EXTRA[i as usize - 257] as u32) << 8
+                        (LEN_SYM_TO_LEN_BASE[i - 257] as u32) << 16
+                            | (LEN_SYM_TO_LEN_EXTRA[i - 257] as u32) << 8
                             | length as u32
                     } else {
                         EXCEPTIONAL_ENTRY
@@ -476,45 +1330,51 @@ impl Decompressor {
             }
         }
 
+        let table_mask = LITLEN_TABLE_SIZE as u16 - 1;
         for i in 0..hlit {
-            if lengths[i] > 12 {
-                compression.litlen_table[(codes[i] & 0xfff) as usize] = u32::MAX;
+            if lengths[i] > Self::TABLE_BITS {
+                compression.litlen_table[(codes[i] & table_mask) as usize] = u32::MAX;
             }
         }
 
         let mut secondary_table_len = 0;
         for i in 0..hlit {
-            if lengths[i] > 12 {
-                let j = (codes[i] & 0xfff) as usize;
+            if lengths[i] > Self::TABLE_BITS {
+                let j = (codes[i] & table_mask) as usize;
                 if compression.litlen_table[j] == u32::MAX {
                     compression.litlen_table[j] =
                         (secondary_table_len << 16) | EXCEPTIONAL_ENTRY | SECONDARY_TABLE_ENTRY;
-                    secondary_table_len += 8;
+                    secondary_table_len += 1 << Self::SECONDARY_BITS;
                 }
             }
         }
-        assert!(secondary_table_len <= 0x7ff);
-        compression.secondary_table = vec![0; secondary_table_len as usize];
+        assert!(secondary_table_len <= 0xffff);
+        debug_assert!(secondary_table_len as usize <= Self::MAX_SECONDARY_TABLE_LEN);
+        // Reuse the allocation from earlier blocks and streams.
+        compression.secondary_table.clear();
+        compression
+            .secondary_table
+            .resize(secondary_table_len as usize, 0);
         for i in 0..hlit {
             let code = codes[i];
             let length = lengths[i];
-            if length > 12 {
-                let j = (codes[i] & 0xfff) as usize;
+            if length > Self::TABLE_BITS {
+                let j = (codes[i] & table_mask) as usize;
                 let k = (compression.litlen_table[j] >> 16) as usize;
 
-                let mut s = code >> 12;
-                while s < 8 {
+                let mut s = code >> Self::TABLE_BITS;
+                while s < 1 << Self::SECONDARY_BITS {
                     debug_assert_eq!(compression.secondary_table[k + s as usize], 0);
                     compression.secondary_table[k + s as usize] =
                         ((i as u16) << 4) | (length as u16);
-                    s += 1 << (length - 12);
+                    s += 1 << (length - Self::TABLE_BITS);
                 }
             }
         }
         debug_assert!(compression
             .secondary_table
             .iter()
-            .all(|&x| x != 0 && (x & 0xf) > 12));
+            .all(|&x| x != 0 && (x & 0xf) as u8 > Self::TABLE_BITS));
 
         // Build the distance code table.
         let lengths = &code_lengths[288..320];
@@ -563,20 +1423,106 @@ impl Decompressor {
         Ok(())
     }
 
+    /// Fold `output[*checksum_position..output_index]` into the checksum.
+    fn update_checksum(
+        &mut self,
+        output: &[u8],
+        checksum_position: &mut usize,
+        output_index: usize,
+    ) {
+        self.hash_output(&output[*checksum_position..output_index]);
+        *checksum_position = output_index;
+    }
+
+    /// Add newly decompressed data to the checksum and the output hasher, if any.
+    fn hash_output(&mut self, data: &[u8]) {
+        if !self.ignore_adler32 {
+            self.checksum.write(data);
+        }
+        if let Some(hasher) = &mut self.output_hasher {
+            hasher.update(data);
+        }
+    }
+
     fn read_compressed(
         &mut self,
         remaining_input: &mut &[u8],
         output: &mut [u8],
-        mut output_index: usize,
+        output_index: usize,
+        checksum_position: &mut usize,
+        end_of_input: bool,
     ) -> Result<usize, DecompressionError> {
-        while let State::CompressedData = self.state {
-            self.fill_buffer(remaining_input);
-            if self.nbits < 33 || output_index == output.len() {
-                if self.nbits >= 15
-                    && self.peak_bits(15) as u16 & self.compression.eof_mask
-                        == self.compression.eof_code
+        let start_index = output_index;
+        let result = self.read_compressed_inner(
+            remaining_input,
+            output,
+            output_index,
+            checksum_position,
+            end_of_input,
+        );
+        if let Ok(end_index) = result {
+            self.count_block_bytes(end_index - start_index);
+        }
+        result
+    }
+
+    /// Returns whether the code of the next literal/length symbol is fully in the bit buffer.
+    ///
+    /// The decode loop relies on any code fitting in the 15 bits it normally keeps buffered. At the
+    /// end of a raw stream, which has no checksum after it, fewer bits may be left, so there this
+    /// is checked symbol by symbol. Back-references check for their extra bits anyway.
+    fn next_litlen_code_available(&self) -> bool {
+        let bits = self.buffer;
+        let litlen_entry =
+            self.compression.litlen_table[(bits & (LITLEN_TABLE_SIZE as u64 - 1)) as usize];
+        let code_bits = if litlen_entry & LITERAL_ENTRY == 0
+            && litlen_entry & EXCEPTIONAL_ENTRY != 0
+            && litlen_entry & SECONDARY_TABLE_ENTRY != 0
+        {
+            let secondary_entry = self.compression.secondary_table[(litlen_entry >> 16) as usize
+                + ((bits >> Self::TABLE_BITS) & ((1 << Self::SECONDARY_BITS) - 1)) as usize];
+            (secondary_entry & 0xf) as u8
+        } else {
+            litlen_entry as u8
+        };
+        code_bits != 0 && code_bits <= self.nbits
+    }
+
+    fn read_compressed_inner(
+        &mut self,
+        remaining_input: &mut &[u8],
+        output: &mut [u8],
+        mut output_index: usize,
+        checksum_position: &mut usize,
+        end_of_input: bool,
+    ) -> Result<usize, DecompressionError> {
+        while let State::CompressedData = self.state {
+            if output_index - *checksum_position >= CHECKSUM_CHUNK_SIZE {
+                self.update_checksum(output, checksum_position, output_index);
+                self.report_progress(output_index, false);
+                self.check_cancelled()?;
+            }
+
+            self.fill_buffer(remaining_input);
+            if self.nbits < 15 {
+                if !end_of_input {
+                    break;
+                }
+                self.build_pending_tables()?;
+                if !self.next_litlen_code_available() {
+                    break;
+                }
+            }
+            self.build_pending_tables()?;
+            if output_index == output.len() {
+                if self.nbits >= self.compression.eof_bits
+                    && self.buffer as u16 & self.compression.eof_mask == self.compression.eof_code
                 {
                     // println!("[{output_index}] EOF");
+                    #[cfg(feature = "perf-stats")]
+                    {
+                        self.stats.slow_path_symbols += 1;
+                    }
                     self.consume_bits(self.compression.eof_bits);
                     self.state = match self.last_block {
                         true => State::Checksum,
@@ -587,21 +1533,22 @@ impl Decompressor {
             }
 
             let mut bits = self.buffer;
-            let litlen_entry = self.compression.litlen_table[(bits & 0xfff) as usize];
+            let table_mask = LITLEN_TABLE_SIZE as u64 - 1;
+            let litlen_entry = self.compression.litlen_table[(bits & table_mask) as usize];
             let litlen_code_bits = litlen_entry as u8;
 
             if litlen_entry & LITERAL_ENTRY != 0 {
                 // Ultra-fast path: do 3 more consecutive table lookups and bail if any of them need the slow path.
-                if self.nbits >= 48 {
-                    let litlen_entry2 =
-                        self.compression.litlen_table[(bits >> litlen_code_bits & 0xfff) as usize];
+                if self.nbits >= 4 * Self::TABLE_BITS {
+                    let litlen_entry2 = self.compression.litlen_table
+                        [(bits >> litlen_code_bits & table_mask) as usize];
                     let litlen_code_bits2 = litlen_entry2 as u8;
                     let litlen_entry3 = self.compression.litlen_table
-                        [(bits >> (litlen_code_bits + litlen_code_bits2) & 0xfff) as usize];
+                        [(bits >> (litlen_code_bits + litlen_code_bits2) & table_mask) as usize];
                     let litlen_code_bits3 = litlen_entry3 as u8;
                     let litlen_entry4 = self.compression.litlen_table[(bits
                         >> (litlen_code_bits + litlen_code_bits2 + litlen_code_bits3)
-                        & 0xfff)
+                        & table_mask)
                         as usize];
                     let litlen_code_bits4 = litlen_entry4 as u8;
                     if litlen_entry2 & litlen_entry3 & litlen_entry4 & LITERAL_ENTRY != 0 {
@@ -635,6 +1582,10 @@ impl Decompressor {
                             output[output_index] = (litlen_entry4 >> 16) as u8;
                             output[output_index + 1] = (litlen_entry4 >> 24) as u8;
                             output_index += advance_output_bytes4;
+                            #[cfg(feature = "perf-stats")]
+                            {
+                                self.stats.fast_path_symbols += 4;
+                            }
                             continue;
                         }
                     }
@@ -664,6 +1615,10 @@ impl Decompressor {
                     output[output_index + 1] = (litlen_entry >> 24) as u8;
                     output_index += advance_output_bytes;
                     self.consume_bits(litlen_code_bits);
+                    #[cfg(feature = "perf-stats")]
+                    {
+                        self.stats.fast_path_symbols += 1;
+                    }
 
                     // if output_index > output.len() {
                     //     self.queued_rle = Some((0, output_index - output.len()));
@@ -672,67 +1627,95 @@ impl Decompressor {
                     // } else {
                     continue;
                     // }
-                } else if output_index + advance_output_bytes == output.len() {
-                    debug_assert_eq!(advance_output_bytes, 1);
+                } else if output_index < output.len() {
+                    // Only one byte of room is left. If the entry holds two literals, queue the
+                    // second one as a run of length one.
                     output[output_index] = (litlen_entry >> 16) as u8;
                     output_index += 1;
+                    if advance_output_bytes == 2 {
+                        self.queued_rle = Some(((litlen_entry >> 24) as u8, 1));
+                    }
                     self.consume_bits(litlen_code_bits);
+                    #[cfg(feature = "perf-stats")]
+                    {
+                        self.stats.fast_path_symbols += 1;
+                    }
                     break;
                 } else {
                     break;
                 }
             }
 
-            let (length_base, length_extra_bits, litlen_code_bits) =
-                if litlen_entry & EXCEPTIONAL_ENTRY == 0 {
-                    (
-                        litlen_entry >> 16,
-                        (litlen_entry >> 8) as u8,
-                        litlen_code_bits,
-                    )
-                } else if litlen_entry & SECONDARY_TABLE_ENTRY != 0 {
-                    let secondary_index = litlen_entry >> 16;
-                    let secondary_entry = self.compression.secondary_table
-                        [secondary_index as usize + ((bits >> 12) & 0x7) as usize];
-                    let litlen_symbol = secondary_entry >> 4;
-                    let litlen_code_bits = (secondary_entry & 0xf) as u8;
-
-                    if litlen_symbol < 256 {
-                        if output_index == output.len() {
-                            break;
-                        }
-                        // println!("[{output_index}] LIT1b {} (val={:04x})", litlen_symbol, self.peak_bits(15));
+            let (length_base, length_extra_bits, litlen_code_bits) = if litlen_entry
+                & EXCEPTIONAL_ENTRY
+                == 0
+            {
+                (
+                    litlen_entry >> 16,
+                    (litlen_entry >> 8) as u8,
+                    litlen_code_bits,
+                )
+            } else if litlen_entry & SECONDARY_TABLE_ENTRY != 0 {
+                let secondary_index = litlen_entry >> 16;
+                let secondary_entry = self.compression.secondary_table[secondary_index as usize
+                    + ((bits >> Self::TABLE_BITS) & ((1 << Self::SECONDARY_BITS) - 1)) as usize];
+                let litlen_symbol = secondary_entry >> 4;
+                let litlen_code_bits = (secondary_entry & 0xf) as u8;
+                #[cfg(feature = "perf-stats")]
+                {
+                    self.stats.secondary_table_hits += 1;
+                }
 
-                        self.consume_bits(litlen_code_bits);
-                        output[output_index] = litlen_symbol as u8;
-                        output_index += 1;
-                        continue;
-                    } else if litlen_symbol == 256 {
-                        // println!("[{output_index}] EOF");
-                        self.consume_bits(litlen_code_bits);
-                        self.state = match self.last_block {
-                            true => State::Checksum,
-                            false => State::BlockHeader,
-                        };
+                if litlen_symbol < 256 {
+                    if output_index == output.len() {
                         break;
                     }
+                    // println!("[{output_index}] LIT1b {} (val={:04x})", litlen_symbol, self.peak_bits(15));
 
-                    (
-                        LEN_SYM_TO_LEN_BASE[litlen_symbol as usize - 257] as u32,
-                        LEN_SYM_TO_LEN_EXTRA[litlen_symbol as usize - 257] as u8,
-                        litlen_code_bits,
-                    )
-                } else if litlen_code_bits == 0 {
-                    return Err(DecompressionError::InvalidLiteralLengthCode);
-                } else {
+                    self.consume_bits(litlen_code_bits);
+                    output[output_index] = litlen_symbol as u8;
+                    output_index += 1;
+                    #[cfg(feature = "perf-stats")]
+                    {
+                        self.stats.slow_path_symbols += 1;
+                    }
+                    continue;
+                } else if litlen_symbol == 256 {
                     // println!("[{output_index}] EOF");
+                    #[cfg(feature = "perf-stats")]
+                    {
+                        self.stats.slow_path_symbols += 1;
+                    }
                     self.consume_bits(litlen_code_bits);
                     self.state = match self.last_block {
                         true => State::Checksum,
                         false => State::BlockHeader,
                     };
                     break;
+                } else if litlen_symbol > 285 {
+                    return Err(DecompressionError::InvalidLiteralLengthCode);
+                }
+
+                (
+                    LEN_SYM_TO_LEN_BASE[litlen_symbol as usize - 257] as u32,
+                    LEN_SYM_TO_LEN_EXTRA[litlen_symbol as usize - 257],
+                    litlen_code_bits,
+                )
+            } else if litlen_code_bits == 0 {
+                return Err(DecompressionError::InvalidLiteralLengthCode);
+            } else {
+                // println!("[{output_index}] EOF");
+                #[cfg(feature = "perf-stats")]
+                {
+                    self.stats.slow_path_symbols += 1;
+                }
+                self.consume_bits(litlen_code_bits);
+                self.state = match self.last_block {
+                    true => State::Checksum,
+                    false => State::BlockHeader,
                 };
+                break;
+            };
             bits >>= litlen_code_bits;
 
             let length_extra_mask = (1 << length_extra_bits) - 1;
@@ -773,17 +1756,21 @@ impl Decompressor {
 
             if self.nbits < total_bits {
                 break;
-            } else if dist > output_index {
+            } else if dist > output_index || dist > self.max_distance {
                 return Err(DecompressionError::DistanceTooFarBack);
             }
 
             // println!("[{output_index}] BACKREF len={} dist={} {:x}", length, dist, dist_entry);
             self.consume_bits(total_bits);
+            #[cfg(feature = "perf-stats")]
+            {
+                self.stats.slow_path_symbols += 1;
+            }
 
             let copy_length = length.min(output.len() - output_index);
             if dist == 1 {
                 let last = output[output_index - 1];
-                output[output_index..][..copy_length].fill(last);
+                cpu::fill(&mut output[output_index..][..copy_length], last);
 
                 if copy_length < length {
                     self.queued_rle = Some((last, length - copy_length));
@@ -800,16 +1787,7 @@ impl Decompressor {
                     }
                 }
             } else {
-                if dist < copy_length {
-                    for i in 0..copy_length {
-                        output[output_index + i] = output[output_index + i - dist];
-                    }
-                } else {
-                    output.copy_within(
-                        output_index - dist..output_index + copy_length - dist,
-                        output_index,
-                    )
-                }
+                cpu::copy_match(output, output_index, dist, copy_length);
 
                 if copy_length < length {
                     self.queued_backref = Some((dist, length - copy_length));
@@ -829,6 +1807,20 @@ impl Decompressor {
     /// or an error if the deflate stream is not valid. `input` is the compressed data. `output`
     /// is the buffer to write the decompressed data to. `end_of_input` indicates whether more
     /// data may be available in the future.
+    ///
+    /// Decompressed data is written starting at `output_position`, one byte at a time if that's
+    /// all the room there is. Calls without any room still make progress through headers and the
+    /// checksum. The bytes of `output` from there on may hold anything, so recycled buffers can be
+    /// passed without clearing them. Since literals are written in pairs and matches in 16-byte
+    /// chunks, up to 15 bytes right after the produced data may be overwritten with scratch
+    /// values. Bytes further on are never written.
+    ///
+    /// The bytes before `output_position` are history: back-references are resolved against
+    /// them, up to the limit set by [`Decompressor::set_max_distance`]. They need not have been
+    /// written by this call or even by this decompressor, so callers can move the last 32 KiB of
+    /// output to the front of the buffer between calls, or decode into a rolling window over a
+    /// larger buffer that already holds the preceding plaintext. Only the newly written bytes are
+    /// included in the checksum.
     pub fn read(
         &mut self,
         input: &[u8],
@@ -837,33 +1829,84 @@ impl Decompressor {
         end_of_input: bool,
     ) -> Result<(usize, usize), DecompressionError> {
         if let State::Done = self.state {
+            if !input.is_empty() {
+                self.add_trailing_data();
+            }
             return Ok((0, 0));
         }
 
-        assert!(output.len() >= output_position + 2);
+        assert!(output.len() >= output_position);
+        if self.total_output > self.output_limit {
+            return Err(DecompressionError::OutputLimitExceeded);
+        }
+
+        // Leave room for at least one byte more than the output limit allows, so that exceeding it
+        // can be detected.
+        let output_allowance = (self.output_limit - self.total_output).saturating_add(2);
+        let output_end = output
+            .len()
+            .min(output_position.saturating_add(self.max_output_per_call))
+            .min(output_position.saturating_add(output_allowance.try_into().unwrap_or(usize::MAX)));
+        self.output_base = self.total_output.wrapping_sub(output_position as u64);
+        let (consumed, produced) = self.read_inner(
+            input,
+            &mut output[..output_end],
+            output_position,
+            end_of_input,
+        )?;
+        self.report_progress(output_position + produced, self.is_done());
+
+        self.total_output += produced as u64;
+        if self.total_output > self.output_limit {
+            return Err(DecompressionError::OutputLimitExceeded);
+        }
+        if let Some((ratio, min_output)) = self.max_expansion_ratio {
+            if self.total_output >= min_output
+                && self.total_output > self.bytes_read.saturating_mul(ratio)
+            {
+                return Err(DecompressionError::ExpansionRatioExceeded);
+            }
+        }
+        Ok((consumed, produced))
+    }
 
-        let mut remaining_input = &input[..];
+    fn read_inner(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        output_position: usize,
+        end_of_input: bool,
+    ) -> Result<(usize, usize), DecompressionError> {
+        let mut remaining_input = input;
         let mut output_index = output_position;
+        // Output before this position has already been added to the checksum.
+        let mut checksum_position = output_position;
+        self.at_block_boundary = false;
 
         if let Some((data, len)) = self.queued_rle.take() {
             let n = len.min(output.len() - output_index);
-            if data != 0 {
-                output[output_index..][..n].fill(data);
-            }
+            cpu::fill(&mut output[output_index..][..n], data);
             output_index += n;
+            self.count_block_bytes(n);
             if n < len {
                 self.queued_rle = Some((data, len - n));
+                self.hash_output(&output[output_position..output_index]);
                 return Ok((0, n));
             }
         }
         if let Some((dist, len)) = self.queued_backref.take() {
-            let n = len.min(output.len() - output_index);
-            for i in 0..n {
-                output[output_index + i] = output[output_index + i - dist];
+            // The output buffer may have changed since the back-reference was queued, for
+            // instance if the decompressor was deserialized.
+            if dist > output_index {
+                return Err(DecompressionError::DistanceTooFarBack);
             }
+            let n = len.min(output.len() - output_index);
+            cpu::copy_match(output, output_index, dist, n);
             output_index += n;
+            self.count_block_bytes(n);
             if n < len {
                 self.queued_backref = Some((dist, len - n));
+                self.hash_output(&output[output_position..output_index]);
                 return Ok((0, n));
             }
         }
@@ -871,37 +1914,48 @@ impl Decompressor {
         // Main decoding state machine.
         let mut last_state = None;
         while last_state != Some(self.state) {
+            // Any state change here means that this call got to the current state, so it is a new
+            // boundary rather than the one the previous call stopped at.
+            if self.stop_at_block_boundaries
+                && last_state.is_some()
+                && matches!(self.state, State::BlockHeader | State::Checksum)
+            {
+                self.at_block_boundary = true;
+                break;
+            }
             last_state = Some(self.state);
             match self.state {
                 State::ZlibHeader => {
-                    if input.len() < 2 && !end_of_input {
-                        return Ok((0, 0));
-                    } else if input.len() < 2 {
-                        return Err(DecompressionError::InsufficientInput);
-                    }
-
-                    if input[0] & 0x0f != 0x08
-                        || (input[0] & 0xf0) > 0x70
-                        || input[1] & 0x20 != 0
-                        || u16::from_be_bytes(input[..2].try_into().unwrap()) % 31 != 0
-                    {
-                        return Err(DecompressionError::BadZlibHeader);
+                    // Go through the bit buffer, so that the header may be split across calls.
+                    self.fill_buffer(&mut remaining_input);
+                    if self.nbits >= 16 {
+                        check_zlib_header(&(self.peak_bits(16) as u16).to_le_bytes())?;
+                        self.consume_bits(16);
+                        self.state = State::BlockHeader;
                     }
-
-                    remaining_input = &remaining_input[2..];
-                    self.state = State::BlockHeader;
                 }
                 State::BlockHeader => {
+                    self.check_cancelled()?;
                     self.read_block_header(&mut remaining_input)?;
+                    if self.state != State::BlockHeader {
+                        self.report_progress(output_index, true);
+                    }
                 }
                 State::CodeLengths => {
                     self.read_code_lengths(&mut remaining_input)?;
                 }
                 State::CompressedData => {
-                    output_index =
-                        self.read_compressed(&mut remaining_input, output, output_index)?
+                    output_index = self.read_compressed(
+                        &mut remaining_input,
+                        output,
+                        output_index,
+                        &mut checksum_position,
+                        end_of_input,
+                    )?
                 }
                 State::UncompressedData => {
+                    let stored_start = output_index;
+
                     // Drain any bytes from our buffer.
                     debug_assert_eq!(self.nbits % 8, 0);
                     while self.nbits > 0
@@ -918,15 +1972,25 @@ impl Decompressor {
                         self.buffer = 0;
                     }
 
-                    // Copy subsequent bytes directly from the input.
-                    let copy_bytes = (self.uncompressed_bytes_left as usize)
-                        .min(remaining_input.len())
-                        .min(output.len() - output_index);
+                    // Copy subsequent bytes directly from the input, unless the caller wants to
+                    // borrow them.
+                    let copy_bytes = if self.borrow_stored {
+                        0
+                    } else {
+                        (self.uncompressed_bytes_left as usize)
+                            .min(remaining_input.len())
+                            .min(output.len() - output_index)
+                    };
                     output[output_index..][..copy_bytes]
                         .copy_from_slice(&remaining_input[..copy_bytes]);
                     remaining_input = &remaining_input[copy_bytes..];
+                    self.bytes_read += copy_bytes as u64;
                     output_index += copy_bytes;
                     self.uncompressed_bytes_left -= copy_bytes as u16;
+                    self.update_checksum(output, &mut checksum_position, output_index);
+                    self.report_progress(output_index, false);
+                    self.check_cancelled()?;
+                    self.count_block_bytes(output_index - stored_start);
 
                     if self.uncompressed_bytes_left == 0 {
                         self.state = if self.last_block {
@@ -937,11 +2001,20 @@ impl Decompressor {
                     }
                 }
                 State::Checksum => {
+                    self.end_block();
+                    if self.raw {
+                        self.update_checksum(output, &mut checksum_position, output_index);
+                        self.state = State::Done;
+                        if self.read_ahead_len() > 0 || !remaining_input.is_empty() {
+                            self.add_trailing_data();
+                        }
+                        break;
+                    }
                     self.fill_buffer(&mut remaining_input);
 
                     let align_bits = self.nbits % 8;
                     if self.nbits >= 32 + align_bits {
-                        self.checksum.write(&output[output_position..output_index]);
+                        self.update_checksum(output, &mut checksum_position, output_index);
                         if align_bits != 0 {
                             self.consume_bits(align_bits);
                         }
@@ -949,10 +2022,16 @@ impl Decompressor {
                         if !self.ignore_adler32
                             && (self.peak_bits(32) as u32).swap_bytes() != self.checksum.finish()
                         {
-                            return Err(DecompressionError::WrongChecksum);
+                            if !self.lenient {
+                                return Err(DecompressionError::WrongChecksum);
+                            }
+                            self.add_warning(DecompressionWarning::WrongChecksum);
                         }
                         self.state = State::Done;
                         self.consume_bits(32);
+                        if self.nbits > 0 || !remaining_input.is_empty() {
+                            self.add_trailing_data();
+                        }
                         break;
                     }
                 }
@@ -960,11 +2039,18 @@ impl Decompressor {
             }
         }
 
-        if !self.ignore_adler32 && self.state != State::Done {
-            self.checksum.write(&output[output_position..output_index]);
+        if self.state != State::Done {
+            self.update_checksum(output, &mut checksum_position, output_index);
         }
 
-        if self.state == State::Done || !end_of_input || output_index >= output.len() - 1 {
+        let awaiting_borrow =
+            self.borrow_stored && self.state == State::UncompressedData && self.nbits == 0;
+        if self.state == State::Done
+            || !end_of_input
+            || awaiting_borrow
+            || self.at_block_boundary
+            || output_index + 1 >= output.len()
+        {
             let input_left = remaining_input.len();
             Ok((input.len() - input_left, output_index - output_position))
         } else {
@@ -972,177 +2058,3848 @@ impl Decompressor {
         }
     }
 
-    /// Returns true if the decompressor has finished decompressing the input.
-    pub fn is_done(&self) -> bool {
-        self.state == State::Done
+    /// Decompress until the output buffer is full, the input runs out, or the stream ends.
+    ///
+    /// This calls [`Decompressor::read`] repeatedly, starting at `*output_position` and advancing
+    /// both it and `input` past the data that was produced and consumed. The same requirements
+    /// as for `read` apply to `output`: the bytes before the output position hold the preceding
+    /// output, and the rest may hold anything.
+    ///
+    /// ```
+    /// # #[cfg(feature = "compress")] {
+    /// # use fdeflate::{Decompressor, ReadStatus};
+    /// # let compressed = fdeflate::compress_to_vec(&[7; 1000]);
+    /// let mut decompressor = Decompressor::new();
+    /// let mut input = &compressed[..];
+    /// let mut output = vec![0; 1000];
+    /// let mut position = 0;
+    /// let status = decompressor
+    ///     .read_exact(&mut input, &mut output, &mut position, true)
+    ///     .unwrap();
+    /// assert_eq!(status, ReadStatus::StreamEnd);
+    /// assert_eq!(&output[..position], &[7; 1000][..]);
+    /// # }
+    /// ```
+    pub fn read_exact(
+        &mut self,
+        input: &mut &[u8],
+        output: &mut [u8],
+        output_position: &mut usize,
+        end_of_input: bool,
+    ) -> Result<ReadStatus, DecompressionError> {
+        loop {
+            if self.is_done() {
+                return Ok(ReadStatus::StreamEnd);
+            }
+            // Even without room for output, headers and the checksum can still be read.
+            let (consumed, produced) = self.read(input, output, *output_position, end_of_input)?;
+            *input = &input[consumed..];
+            *output_position += produced;
+            if consumed == 0 && produced == 0 && !self.is_done() {
+                if *output_position == output.len() {
+                    return Ok(ReadStatus::OutputFull);
+                }
+                return Ok(ReadStatus::NeedsInput);
+            }
+        }
     }
-}
 
-/// Decompress the given data.
-pub fn decompress_to_vec(input: &[u8]) -> Result<Vec<u8>, DecompressionError> {
-    let mut decoder = Decompressor::new();
-    let mut output = vec![0; 1024];
-    let mut input_index = 0;
-    let mut output_index = 0;
-    while !decoder.is_done() {
-        let (consumed, produced) =
-            decoder.read(&input[input_index..], &mut output, output_index, true)?;
-        input_index += consumed;
-        output_index += produced;
-        output.resize(output_index + 32 * 1024, 0);
+    /// Decompress into `output` while keeping the history in an internal window.
+    ///
+    /// Unlike [`Decompressor::read`], this doesn't need the preceding output to stay in `output`:
+    /// the decompressor keeps the last 32 KiB itself, so huge streams can be decoded into a small
+    /// buffer that is reused for every call. The cost is an extra copy of the output, and the
+    /// window is allocated on first use. Decompressed data is written to the start of `output`,
+    /// which may have any length.
+    ///
+    /// Returns the number of bytes read from `input` and written to `output`. Once called, the
+    /// decompressor must only be driven through this method, [`Decompressor::read_uninit`] and
+    /// [`Decompressor::read_with`], which share the window, until it is [reset](Self::reset).
+    ///
+    /// ```
+    /// # #[cfg(feature = "compress")] {
+    /// # use fdeflate::Decompressor;
+    /// # let compressed = fdeflate::compress_to_vec(&[7; 100_000]);
+    /// let mut decompressor = Decompressor::new();
+    /// let mut input = &compressed[..];
+    /// let mut chunk = [0; 1000];
+    /// let mut total = 0;
+    /// while !decompressor.is_done() {
+    ///     let (consumed, produced) = decompressor.read_windowed(input, &mut chunk, true).unwrap();
+    ///     input = &input[consumed..];
+    ///     total += produced;
+    /// }
+    /// assert_eq!(total, 100_000);
+    /// # }
+    /// ```
+    pub fn read_windowed(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        end_of_input: bool,
+    ) -> Result<(usize, usize), DecompressionError> {
+        self.read_windowed_inner(input, output.len(), end_of_input, |offset, chunk| {
+            output[offset..][..chunk.len()].copy_from_slice(chunk)
+        })
     }
-    output.resize(output_index, 0);
 
-    // if input_index != input.len() {
-    //     println!("extra input: {} bytes", input.len() - input_index);
-    //     Err(DecompressionError::ExtraInput)
-    // } else {
-    Ok(output)
-    // }
-}
+    /// Decompress into uninitialized memory while keeping the history in an internal window.
+    ///
+    /// This works like [`Decompressor::read_windowed`], and shares its window, but accepts output
+    /// that hasn't been initialized, like freshly reserved capacity of a `Vec`, so it doesn't have
+    /// to be zeroed first. Returns the number of bytes read from `input` and written to `output`;
+    /// that many elements at the start of `output` are initialized afterwards.
+    pub fn read_uninit(
+        &mut self,
+        input: &[u8],
+        output: &mut [MaybeUninit<u8>],
+        end_of_input: bool,
+    ) -> Result<(usize, usize), DecompressionError> {
+        self.read_windowed_inner(input, output.len(), end_of_input, |offset, chunk| {
+            for (dst, &src) in output[offset..].iter_mut().zip(chunk) {
+                dst.write(src);
+            }
+        })
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::tables::{self, LENGTH_TO_LEN_EXTRA, LENGTH_TO_SYMBOL};
+    /// Decode up to `output_len` bytes via the internal window, passing each chunk of output to
+    /// `copy_out` along with its offset in the output.
+    fn read_windowed_inner<F: FnMut(usize, &[u8])>(
+        &mut self,
+        input: &[u8],
+        output_len: usize,
+        end_of_input: bool,
+        mut copy_out: F,
+    ) -> Result<(usize, usize), DecompressionError> {
+        let mut window = std::mem::take(&mut self.internal_window);
+        let mut consumed = 0;
+        let mut produced = 0;
+        let result = loop {
+            if self.is_done() {
+                break Ok(());
+            }
+            let end = self.prepare_internal_window(&mut window);
 
-    use super::*;
-    use rand::Rng;
+            // Only decode as much as fits into `output`, so nothing is left over for later.
+            let room = (output_len - produced).min(window.len() - end);
+            let (n, m) = match self.read(
+                &input[consumed..],
+                &mut window[..end + room],
+                end,
+                end_of_input,
+            ) {
+                Ok(result) => result,
+                Err(err) => break Err(err),
+            };
+            copy_out(produced, &window[end..][..m]);
+            self.internal_window_end = end + m;
+            consumed += n;
+            produced += m;
+            if (n == 0 && m == 0) || produced == output_len {
+                break Ok(());
+            }
+        };
+        self.internal_window = window;
+        result.map(|()| (consumed, produced))
+    }
 
-    fn roundtrip(data: &[u8]) {
-        let compressed = crate::compress_to_vec(data);
-        let decompressed = decompress_to_vec(&compressed).unwrap();
-        assert_eq!(&decompressed, data);
+    /// Decompress from `input`, passing each chunk of output to `sink` as it is produced.
+    ///
+    /// Like [`Decompressor::read_windowed`], this keeps the history in an internal window, so the
+    /// caller doesn't need to own an output buffer at all. The chunks are passed straight from the
+    /// window without copying, which makes it easy to feed the output into hashers, parsers or
+    /// files. An error returned by `sink` stops decompression and is passed through.
+    ///
+    /// Returns the number of bytes read from `input`.
+    ///
+    /// ```
+    /// # #[cfg(feature = "compress")] {
+    /// # use fdeflate::{Decompressor, DecompressionError};
+    /// # let compressed = fdeflate::compress_to_vec(&[7; 100_000]);
+    /// let mut decompressor = Decompressor::new();
+    /// let mut total = 0;
+    /// decompressor
+    ///     .read_with(&compressed, true, |chunk| {
+    ///         total += chunk.len();
+    ///         Ok::<_, DecompressionError>(())
+    ///     })
+    ///     .unwrap();
+    /// assert!(decompressor.is_done());
+    /// assert_eq!(total, 100_000);
+    /// # }
+    /// ```
+    pub fn read_with<F, E>(
+        &mut self,
+        input: &[u8],
+        end_of_input: bool,
+        mut sink: F,
+    ) -> Result<usize, E>
+    where
+        F: FnMut(&[u8]) -> Result<(), E>,
+        E: From<DecompressionError>,
+    {
+        let mut window = std::mem::take(&mut self.internal_window);
+        let mut consumed = 0;
+        let result = loop {
+            if self.is_done() {
+                break Ok(());
+            }
+            let end = self.prepare_internal_window(&mut window);
+            let (n, m) = match self.read(&input[consumed..], &mut window, end, end_of_input) {
+                Ok(result) => result,
+                Err(err) => break Err(err.into()),
+            };
+            self.internal_window_end = end + m;
+            consumed += n;
+            if m > 0 {
+                if let Err(err) = sink(&window[end..][..m]) {
+                    break Err(err);
+                }
+            } else if n == 0 {
+                break Ok(());
+            }
+        };
+        self.internal_window = window;
+        result.map(|()| consumed)
     }
 
-    fn roundtrip_miniz_oxide(data: &[u8]) {
-        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(data, 3);
-        let decompressed = decompress_to_vec(&compressed).unwrap();
-        assert_eq!(decompressed.len(), data.len());
-        for (i, (a, b)) in decompressed.chunks(1).zip(data.chunks(1)).enumerate() {
-            assert_eq!(a, b, "chunk {}..{}", i * 1, i * 1 + 1);
+    /// Allocate the internal window if needed, and make room in it for more output.
+    ///
+    /// Returns the position in the window at which to continue decoding.
+    fn prepare_internal_window(&mut self, window: &mut Vec<u8>) -> usize {
+        if window.is_empty() {
+            *window = vec![0; WINDOW_SIZE + 2 * RECOMMENDED_OUTPUT_BUFFER_SIZE];
         }
-        assert_eq!(&decompressed, data);
+        let end = self.internal_window_end;
+        if window.len() - end < RECOMMENDED_OUTPUT_BUFFER_SIZE {
+            window.copy_within(end - WINDOW_SIZE..end, 0);
+            self.internal_window_end = WINDOW_SIZE;
+        }
+        self.internal_window_end
     }
 
-    #[allow(unused)]
-    fn compare_decompression(data: &[u8]) {
-        // let decompressed0 = flate2::read::ZlibDecoder::new(std::io::Cursor::new(&data))
-        //     .bytes()
-        //     .collect::<Result<Vec<_>, _>>()
-        //     .unwrap();
-        let decompressed = decompress_to_vec(&data).unwrap();
-        let decompressed2 = miniz_oxide::inflate::decompress_to_vec_zlib(&data).unwrap();
-        for i in 0..decompressed.len().min(decompressed2.len()) {
-            if decompressed[i] != decompressed2[i] {
-                panic!(
-                    "mismatch at index {} {:?} {:?}",
-                    i,
-                    &decompressed[i.saturating_sub(1)..(i + 16).min(decompressed.len())],
-                    &decompressed2[i.saturating_sub(1)..(i + 16).min(decompressed2.len())]
-                );
+    /// Decompress from `input`, appending the output to `output`.
+    ///
+    /// The vector is grown as needed, so this takes care of the bookkeeping that driving
+    /// [`Decompressor::read`] with a growing buffer involves. Back-references are resolved against
+    /// the end of `output`, so between calls for the same stream the vector must only be changed
+    /// by this method. It may start out holding other data, which is left in place.
+    ///
+    /// Returns the number of bytes read from `input`. `end_of_input` indicates whether more data
+    /// may be passed to later calls. If an error occurs, `output` keeps what was decoded before it.
+    ///
+    /// ```
+    /// # #[cfg(feature = "compress")] {
+    /// # use fdeflate::Decompressor;
+    /// # let compressed = fdeflate::compress_to_vec(b"Hello world!");
+    /// let mut decompressor = Decompressor::new();
+    /// let mut output = Vec::new();
+    /// let packets: Vec<&[u8]> = compressed.chunks(5).collect();
+    /// for (i, packet) in packets.iter().enumerate() {
+    ///     let end_of_input = i == packets.len() - 1;
+    ///     let consumed = decompressor.decompress_into(packet, &mut output, end_of_input).unwrap();
+    ///     assert_eq!(consumed, packet.len());
+    /// }
+    /// assert_eq!(output, b"Hello world!");
+    /// # }
+    /// ```
+    pub fn decompress_into(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        end_of_input: bool,
+    ) -> Result<usize, DecompressionError> {
+        let mut consumed = 0;
+        let mut position = output.len();
+        let result = loop {
+            if self.is_done() {
+                break Ok(());
             }
-        }
-        if decompressed != decompressed2 {
-            panic!(
-                "length mismatch {} {} {:x?}",
-                decompressed.len(),
-                decompressed2.len(),
-                &decompressed2[decompressed.len()..][..16]
-            );
-        }
-        //assert_eq!(decompressed, decompressed2);
+            if output.len() - position < 2 {
+                // Growing geometrically keeps the total cost of zero-filling and copying linear.
+                let len = output.len() + output.len().max(RECOMMENDED_OUTPUT_BUFFER_SIZE);
+                output.resize(len, 0);
+            }
+            let (n, m) = match self.read(&input[consumed..], output, position, end_of_input) {
+                Ok(result) => result,
+                Err(err) => break Err(err),
+            };
+            consumed += n;
+            position += m;
+            if n == 0 && m == 0 {
+                break Ok(());
+            }
+        };
+        output.truncate(position);
+        result.map(|()| consumed)
     }
 
-    #[test]
-    fn tables() {
-        for (i, &bits) in LEN_SYM_TO_LEN_EXTRA.iter().enumerate() {
-            let len_base = LEN_SYM_TO_LEN_BASE[i];
-            for j in 0..(1 << bits) {
-                if i == 27 && j == 31 {
-                    continue;
+    /// Decompress from a buffered reader, which may deliver the input in fragments of any size.
+    ///
+    /// This avoids concatenating input that arrives in many pieces, like the IDAT chunks of a
+    /// PNG image: each fragment is decoded in place as returned by [`io::BufRead::fill_buf`], and
+    /// a [`chain`](io::Read::chain) of slices or a custom reader can stitch them together. `output`
+    /// and `output_position` work as for [`Decompressor::read`].
+    ///
+    /// Decompresses until the stream ends, the output is full, or the reader reaches its end.
+    /// Returns the number of bytes written to `output`. Reaching the end of the reader before the
+    /// end of the stream is an error.
+    ///
+    /// ```
+    /// # #[cfg(feature = "compress")] {
+    /// # use std::io::Read;
+    /// # use fdeflate::Decompressor;
+    /// # let compressed = fdeflate::compress_to_vec(b"Hello world!");
+    /// let (first, second) = compressed.split_at(5);
+    /// let mut decompressor = Decompressor::new();
+    /// let mut output = [0; 100];
+    /// let n = decompressor
+    ///     .read_buf(&mut first.chain(second), &mut output, 0)
+    ///     .unwrap();
+    /// assert_eq!(&output[..n], b"Hello world!");
+    /// # }
+    /// ```
+    pub fn read_buf<R: io::BufRead>(
+        &mut self,
+        input: &mut R,
+        output: &mut [u8],
+        output_position: usize,
+    ) -> io::Result<usize> {
+        let mut position = output_position;
+        while !self.is_done() {
+            let buf = input.fill_buf()?;
+            let end_of_input = buf.is_empty();
+            let bit_position = self.bit_position();
+            let (consumed, produced) = self.read(buf, output, position, end_of_input)?;
+            input.consume(consumed);
+            position += produced;
+            if consumed == 0 && produced == 0 && self.bit_position() == bit_position {
+                if end_of_input && position < output.len() {
+                    return Err(DecompressionError::InsufficientInput.into());
                 }
-                assert_eq!(LENGTH_TO_LEN_EXTRA[len_base + j - 3], bits, "{} {}", i, j);
-                assert_eq!(
-                    LENGTH_TO_SYMBOL[len_base + j - 3],
-                    i as u16 + 257,
-                    "{} {}",
-                    i,
-                    j
-                );
+                break;
             }
         }
+        Ok(position - output_position)
     }
 
-    #[test]
-    fn fdeflate_table() {
-        let mut compression = CompressedBlock {
-            litlen_table: [0; 4096],
-            dist_table: [0; 512],
-            dist_symbol_lengths: [0; 30],
-            dist_symbol_masks: [0; 30],
-            dist_symbol_codes: [0; 30],
-            secondary_table: Vec::new(),
-            eof_code: 0,
-            eof_mask: 0,
-            eof_bits: 0,
-        };
-        let mut lengths = tables::HUFFMAN_LENGTHS.to_vec();
-        lengths.resize(288, 0);
-        lengths.push(1);
-        lengths.resize(320, 0);
-        Decompressor::build_tables(286, &lengths, &mut compression, 11).unwrap();
+    /// Decode the next `n` bytes of output without returning them.
+    ///
+    /// This is used to fast forward through a stream, for instance to reach a position after
+    /// resuming from a [`ResumePoint`]. `output` and `output_position` work as for
+    /// [`Decompressor::read`], except that only the last 32 KiB of output are retained: whenever
+    /// `output` fills up, they are moved to its start and `*output_position` is moved back
+    /// accordingly. So a buffer that is somewhat larger than 32 KiB is enough to skip any amount
+    /// of data, and afterwards decoding can continue with `read` at `*output_position`. The
+    /// skipped data is still included in the checksum.
+    ///
+    /// Returns the number of bytes consumed from `input` and the number of bytes skipped, which is
+    /// less than `n` only if the input runs out or the stream ends.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `output` isn't longer than 32 KiB.
+    pub fn skip_output(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        output_position: &mut usize,
+        n: u64,
+        end_of_input: bool,
+    ) -> Result<(usize, u64), DecompressionError> {
+        assert!(output.len() > WINDOW_SIZE);
+
+        // Temporarily lower the output limit per call so that no more than `n` bytes are decoded.
+        let max_output_per_call = self.max_output_per_call;
+        let mut consumed = 0;
+        let mut skipped = 0;
+        let result = loop {
+            if skipped == n || self.is_done() {
+                break Ok(());
+            }
+            if *output_position > WINDOW_SIZE
+                && output.len() - *output_position < RECOMMENDED_OUTPUT_BUFFER_SIZE
+            {
+                output.copy_within(*output_position - WINDOW_SIZE..*output_position, 0);
+                *output_position = WINDOW_SIZE;
+            }
+
+            self.max_output_per_call =
+                max_output_per_call.min((n - skipped).try_into().unwrap_or(usize::MAX));
+            match self.read(&input[consumed..], output, *output_position, end_of_input) {
+                Ok((0, 0)) if !self.at_block_boundary => break Ok(()),
+                Ok((c, p)) => {
+                    consumed += c;
+                    skipped += p as u64;
+                    *output_position += p;
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        self.max_output_per_call = max_output_per_call;
+        result.map(|()| (consumed, skipped))
+    }
+
+    /// Returns the total number of input bytes consumed by all calls to [`Decompressor::read`].
+    ///
+    /// This is the sum of the first elements of the values returned by `read`. It may include
+    /// bytes past the end of the stream that were read ahead; see [`Decompressor::byte_position`]
+    /// for the exact position within the stream.
+    pub fn total_input(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the total number of bytes of output produced by all calls to
+    /// [`Decompressor::read`].
+    pub fn total_output(&self) -> u64 {
+        self.total_output
+    }
+
+    /// Returns the number of bits of the compressed stream that have been decoded so far.
+    ///
+    /// This includes the zlib header and, once decoding is done, the checksum. Bits that have
+    /// been read from the input but not yet decoded are not counted, so after the stream ends
+    /// this is exactly the length of the stream regardless of how much input was provided.
+    pub fn bit_position(&self) -> u64 {
+        self.bytes_read * 8 - self.nbits as u64
+    }
+
+    /// Returns the number of whole bytes of the compressed stream that have been decoded so far.
+    ///
+    /// If the decompressor isn't byte aligned, the partially decoded byte is not included.
+    pub fn byte_position(&self) -> u64 {
+        self.bit_position() / 8
+    }
+
+    /// Returns the length of the compressed stream once its end has been reached.
+    ///
+    /// This includes the zlib header and checksum, and for raw deflate streams the partial byte
+    /// the last block ends in. The input counts returned by [`Decompressor::read`] may include
+    /// bytes that were read ahead past the end, but this is exact, so any data following the
+    /// stream starts at this offset in the input. Returns `None` until the stream is done.
+    pub fn compressed_len(&self) -> Option<u64> {
+        if self.is_done() {
+            Some((self.bit_position() + 7) / 8)
+        } else {
+            None
+        }
+    }
+
+    /// Returns how many bytes past the end of the stream were read ahead.
+    ///
+    /// These bytes are included in the input counts returned by [`Decompressor::read`], but don't
+    /// belong to the stream, so they should be handed on to whatever parses the data following
+    /// it. Returns zero until the stream is done.
+    pub fn read_ahead_len(&self) -> u64 {
+        match self.compressed_len() {
+            Some(len) => self.bytes_read - len,
+            None => 0,
+        }
+    }
+
+    /// Returns whether the current position in the compressed stream is on a byte boundary.
+    pub fn is_byte_aligned(&self) -> bool {
+        self.nbits % 8 == 0
+    }
+
+    /// Returns the number of blocks encountered so far, including the one currently being decoded.
+    pub fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    /// Returns the type of the most recent block, or `None` if no block header has been read yet.
+    pub fn current_block_type(&self) -> Option<BlockType> {
+        self.block_type
+    }
+
+    /// Returns whether the header of the final block of the stream has been read.
+    pub fn last_block_seen(&self) -> bool {
+        self.last_block && !matches!(self.state, State::ZlibHeader | State::BlockHeader)
+    }
+
+    /// Returns how many bytes of output the decompressor can produce without any more input.
+    ///
+    /// This counts the rest of a pending run or back-reference that didn't fit into the output
+    /// buffer, and stored block data already read into the bit buffer. Further symbols that could
+    /// be decoded from buffered bits are not counted, so the actual amount may be higher.
+    pub fn output_available_hint(&self) -> usize {
+        let queued = self.queued_rle.map_or(0, |(_, len)| len)
+            + self.queued_backref.map_or(0, |(_, len)| len);
+        let buffered = match self.state {
+            State::UncompressedData => {
+                (self.uncompressed_bytes_left as usize).min(self.nbits as usize / 8)
+            }
+            _ => 0,
+        };
+        queued + buffered
+    }
+
+    /// Returns the minimum number of additional input bytes needed to make progress.
+    ///
+    /// A call to [`Decompressor::read`] with fewer bytes than this (and `end_of_input` unset)
+    /// won't produce any output or advance the stream. Returns zero if the decompressor may be
+    /// able to make progress with the input it already buffered, or once the stream is done.
+    /// Since symbol lengths aren't known until they are decoded, this is a lower bound: after
+    /// providing the bytes, more may still be needed.
+    pub fn input_needed_hint(&self) -> usize {
+        if self.output_available_hint() > 0 {
+            return 0;
+        }
+        let needed_bits = match self.state {
+            State::ZlibHeader => 16,
+            State::BlockHeader if self.nbits < 3 => 3,
+            State::BlockHeader => match self.buffer >> 1 & 0b11 {
+                0b00 => 3 + 32 + (self.nbits - 3) % 8,
+                0b10 => 17,
+                _ => 0,
+            },
+            State::CodeLengths if self.header.hclen > 0 => 3,
+            State::CodeLengths => 7,
+            State::CompressedData if self.nbits < 15 || self.pending_tables.is_some() => 15,
+            State::CompressedData => {
+                let eof =
+                    self.buffer as u16 & self.compression.eof_mask == self.compression.eof_code;
+                if eof {
+                    0
+                } else {
+                    33
+                }
+            }
+            State::UncompressedData if self.uncompressed_bytes_left > 0 => 8,
+            State::UncompressedData => 0,
+            State::Checksum if self.raw => 0,
+            State::Checksum => 32 + self.nbits % 8,
+            State::Done => 0,
+        };
+        (needed_bits.saturating_sub(self.nbits) as usize + 7) / 8
+    }
+
+    /// Returns the window needed to hand the rest of the stream off to another inflater.
+    ///
+    /// This is the last 32 KiB of output (or all of it, if there is less), which is exactly the
+    /// dictionary zlib's `inflateSetDictionary` expects for a raw inflate stream
+    /// (`inflateInit2` with negative `windowBits`) that continues from
+    /// [`byte_position`](Decompressor::byte_position) in the compressed input. `output` and
+    /// `output_end` are the buffer passed to the last call to [`Decompressor::read`] and the
+    /// position just after the data it produced.
+    ///
+    /// Handing off is only possible between blocks on a byte boundary, as after a full or sync
+    /// flush. Returns `None` elsewhere, or if `output` doesn't hold enough prior output.
+    pub fn window<'a>(&self, output: &'a [u8], output_end: usize) -> Option<&'a [u8]> {
+        if self.state != State::BlockHeader || !self.is_byte_aligned() {
+            return None;
+        }
+        let len = self.total_output.min(WINDOW_SIZE as u64) as usize;
+        if output_end < len {
+            return None;
+        }
+        Some(&output[output_end - len..output_end])
+    }
+
+    /// Returns a position from which decoding can later be resumed.
+    ///
+    /// Returns `None` if the decompressor is in the middle of a zlib header, block header, or
+    /// back-reference, or if the stream has already ended. Combined with the (up to) 32 KiB of
+    /// output preceding it, a resume point allows decoding to restart via
+    /// [`Decompressor::resume`] without decoding the stream from the start.
+    pub fn resume_point(&self) -> Option<ResumePoint> {
+        if self.queued_rle.is_some() || self.queued_backref.is_some() {
+            return None;
+        }
+        match self.state {
+            State::BlockHeader => Some(ResumePoint {
+                block_start: self.bit_position(),
+                position: self.bit_position(),
+            }),
+            State::CompressedData | State::UncompressedData => Some(ResumePoint {
+                block_start: self.block_start,
+                position: self.bit_position(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Creates a decompressor for a raw deflate stream that continues from earlier data.
+    ///
+    /// The stream must not have a zlib header or checksum, and its back-references may reach up
+    /// to 32 KiB into `window`: for instance the previously decoded data of a context takeover
+    /// protocol, or a window exported by zlib or by [`Decompressor::window`]. The end of `window`
+    /// is copied to the start of `output`, and the returned position must be passed as
+    /// `output_position` to the first call to [`Decompressor::read`] with that buffer. The stream
+    /// ends after its final block; nothing is read after it.
+    ///
+    /// Panics if `output` is too small to hold the window.
+    pub fn with_window(window: &[u8], output: &mut [u8]) -> (Self, usize) {
+        let len = window.len().min(WINDOW_SIZE);
+        output[..len].copy_from_slice(&window[window.len() - len..]);
+        (Self::new_raw(), len)
+    }
+
+    /// Creates a decompressor that continues decoding from a [`ResumePoint`].
+    ///
+    /// `input` must hold the compressed stream starting from byte `resume_point.block_start / 8`.
+    /// Returns the decompressor along with the number of bytes of `input` it consumed. Decoding
+    /// then continues by calling [`Decompressor::read`] on the rest of the input, with the output
+    /// preceding the resume point (up to 32 KiB of it) placed before `output_position`.
+    ///
+    /// Checksum verification is disabled for the returned decompressor, since the checksum of
+    /// the data before the resume point isn't known.
+    pub fn resume(
+        resume_point: &ResumePoint,
+        input: &[u8],
+    ) -> Result<(Self, usize), DecompressionError> {
+        let mut decompressor = Self::new();
+        decompressor.ignore_adler32 = true;
+        decompressor.state = State::BlockHeader;
+        decompressor.bytes_read = resume_point.block_start / 8;
+
+        let mut remaining_input = input;
+        decompressor.fill_buffer(&mut remaining_input);
+        let header_skip = (resume_point.block_start % 8) as u8;
+        if decompressor.nbits < header_skip {
+            return Err(DecompressionError::InsufficientInput);
+        }
+        decompressor.consume_bits(header_skip);
+
+        // Resume points between blocks are before the header of the next block.
+        if resume_point.position == resume_point.block_start {
+            return Ok((decompressor, input.len() - remaining_input.len()));
+        }
+
+        loop {
+            let state = decompressor.state;
+            match state {
+                State::BlockHeader => decompressor.read_block_header(&mut remaining_input)?,
+                State::CodeLengths => decompressor.read_code_lengths(&mut remaining_input)?,
+                _ => break,
+            }
+            if decompressor.state == state {
+                return Err(DecompressionError::InsufficientInput);
+            }
+        }
+
+        let mut skip = resume_point
+            .position
+            .checked_sub(decompressor.bit_position())
+            .ok_or(DecompressionError::InvalidResumePoint)?;
+        if decompressor.state == State::UncompressedData {
+            if skip % 8 != 0 || skip / 8 > decompressor.uncompressed_bytes_left as u64 {
+                return Err(DecompressionError::InvalidResumePoint);
+            }
+            decompressor.uncompressed_bytes_left -= (skip / 8) as u16;
+        }
+        while skip > 0 {
+            decompressor.fill_buffer(&mut remaining_input);
+            if decompressor.nbits == 0 {
+                return Err(DecompressionError::InsufficientInput);
+            }
+            let nbits = skip.min(decompressor.nbits.min(56) as u64) as u8;
+            decompressor.consume_bits(nbits);
+            skip -= nbits as u64;
+        }
+        if decompressor.nbits == 0 {
+            decompressor.buffer = 0;
+        }
+
+        Ok((decompressor, input.len() - remaining_input.len()))
+    }
+
+    /// Skips past damaged data after [`Decompressor::read`] returned an error.
+    ///
+    /// `input` must be the compressed data starting at [`Decompressor::byte_position`]. It is
+    /// searched for the next plausible block boundary, which is the end of a stored block header
+    /// (including the empty stored blocks emitted by sync flushes). If one is found, the
+    /// decompressor is prepared to continue decoding from there and the number of bytes of `input`
+    /// that should be skipped before the next call to `read` is returned. Otherwise, `None` is
+    /// returned and the decompressor is left unchanged.
+    ///
+    /// Recovery disables checksum verification, and back-references in later blocks may refer to
+    /// data that was lost. Since the located boundary is only a candidate, decoding may fail
+    /// again, in which case this method can be called repeatedly.
+    pub fn recover(&mut self, input: &[u8]) -> Option<usize> {
+        let position = input
+            .windows(4)
+            .position(|w| u16::from_le_bytes([w[0], w[1]]) == !u16::from_le_bytes([w[2], w[3]]))?;
+        let len = u16::from_le_bytes([input[position], input[position + 1]]);
+
+        self.buffer = 0;
+        self.nbits = 0;
+        self.bytes_read = self.byte_position() + position as u64 + 4;
+        self.block_start = self.bit_position();
+        self.queued_rle = None;
+        self.queued_backref = None;
+        self.last_block = false;
+        self.ignore_adler32 = true;
+        self.uncompressed_bytes_left = len;
+        self.start_block(BlockType::Stored);
+        self.state = if len == 0 {
+            State::BlockHeader
+        } else {
+            State::UncompressedData
+        };
+        Some(position + 4)
+    }
+
+    /// Returns information about the next block header without consuming any input.
+    ///
+    /// `input` should be the data that would be passed to the next call to [`Decompressor::read`].
+    /// Returns `None` if the decompressor isn't positioned at the start of a block, or if there
+    /// isn't enough input to decode the header.
+    pub fn peek_block_header(
+        &self,
+        input: &[u8],
+    ) -> Result<Option<BlockHeaderInfo>, DecompressionError> {
+        if !matches!(self.state, State::ZlibHeader | State::BlockHeader) {
+            return Ok(None);
+        }
+
+        // Some of the header may already be in the bit buffer, so peek at the bits that follow it
+        // instead of at `input` directly. A u128 holds the zlib header along with the longest
+        // block header and a full bit buffer.
+        let mut buffer = self.buffer as u128;
+        let mut nbits = self.nbits;
+        for &byte in input.iter().take((128 - nbits as usize) / 8) {
+            buffer |= (byte as u128) << nbits;
+            nbits += 8;
+        }
+        if self.state == State::ZlibHeader {
+            if nbits < 16 {
+                return Ok(None);
+            }
+            check_zlib_header(&(buffer as u16).to_le_bytes())?;
+            buffer >>= 16;
+            nbits -= 16;
+        }
+        if nbits < 3 {
+            return Ok(None);
+        }
+
+        let last_block = buffer & 1 != 0;
+        let block_type = match (buffer >> 1) & 0b11 {
+            0b00 => {
+                let align_bits = (nbits - 3) % 8;
+                if nbits < 3 + 32 + align_bits {
+                    return Ok(None);
+                }
+                let len = (buffer >> (align_bits + 3)) as u16;
+                let nlen = (buffer >> (align_bits + 19)) as u16;
+                if nlen != !len {
+                    return Err(DecompressionError::InvalidUncompressedBlockLength);
+                }
+                return Ok(Some(BlockHeaderInfo {
+                    block_type: BlockType::Stored,
+                    last_block,
+                    stored_len: Some(len),
+                }));
+            }
+            0b01 => BlockType::Fixed,
+            0b10 => BlockType::Dynamic,
+            _ => return Err(DecompressionError::InvalidBlockType),
+        };
+        Ok(Some(BlockHeaderInfo {
+            block_type,
+            last_block,
+            stored_len: None,
+        }))
+    }
+
+    /// Returns true if the decompressor has finished decompressing the input.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Check that the stream is complete and wasn't followed by any other data.
+    ///
+    /// Returns [`DecompressionError::InsufficientInput`] if the end of the stream hasn't been
+    /// reached yet, and [`DecompressionError::ExtraInput`] if any input passed to
+    /// [`Decompressor::read`] extends past it, whether it was read ahead or passed after the end.
+    /// Unlike the [`TrailingData`](DecompressionWarning::TrailingData) warning, this is reported
+    /// in [lenient](Decompressor::set_lenient) mode too. Data that was never passed to the
+    /// decompressor can't be detected, so the remaining input should be passed to `read` until
+    /// the stream is done, as a typical decoding loop does.
+    ///
+    /// ```
+    /// # #[cfg(feature = "compress")] {
+    /// # use fdeflate::{DecompressionError, Decompressor};
+    /// let mut input = fdeflate::compress_to_vec(b"Hello world!");
+    /// input.extend_from_slice(b"smuggled");
+    ///
+    /// let mut decompressor = Decompressor::new();
+    /// let mut output = [0; 100];
+    /// decompressor.read(&input, &mut output, 0, true).unwrap();
+    /// assert!(matches!(decompressor.finish(), Err(DecompressionError::ExtraInput)));
+    /// # }
+    /// ```
+    pub fn finish(&self) -> Result<(), DecompressionError> {
+        if !self.is_done() {
+            Err(DecompressionError::InsufficientInput)
+        } else if self.trailing_data || self.read_ahead_len() > 0 {
+            Err(DecompressionError::ExtraInput)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Scans compressed data for sync flush markers.
+///
+/// A sync flush ends with an empty stored block whose LEN and NLEN fields are the bytes
+/// `00 00 FF FF`. Each returned offset points just past such a marker, which is where the next
+/// block header would start if the marker is genuine. Since the same byte sequence can also
+/// occur by chance inside compressed data, the offsets are only candidates.
+pub fn find_sync_markers(data: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    data.windows(4)
+        .enumerate()
+        .filter(|(_, window)| *window == [0x00, 0x00, 0xff, 0xff])
+        .map(|(i, _)| i + 4)
+}
+
+fn check_zlib_header(input: &[u8]) -> Result<(), DecompressionError> {
+    if input[1] & 0x20 != 0 {
+        return Err(DecompressionError::BadZlibHeader);
+    }
+    check_zlib_header_allowing_dictionary(input)
+}
+
+/// Like `check_zlib_header`, but accepts headers with the FDICT flag set.
+fn check_zlib_header_allowing_dictionary(input: &[u8]) -> Result<(), DecompressionError> {
+    if input[0] & 0x0f != 0x08
+        || (input[0] & 0xf0) > 0x70
+        || u16::from_be_bytes(input[..2].try_into().unwrap()) % 31 != 0
+    {
+        return Err(DecompressionError::BadZlibHeader);
+    }
+    Ok(())
+}
+
+// Decompressors must remain `Send` so they can be moved between the threads of a pool.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Decompressor>();
+    assert_send::<SmallDecompressor>();
+    assert_send::<AnyDecompressor>();
+};
+
+impl<const LITLEN_TABLE_SIZE: usize> Default for CustomDecompressor<LITLEN_TABLE_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const LITLEN_TABLE_SIZE: usize> Clone for CustomDecompressor<LITLEN_TABLE_SIZE> {
+    fn clone(&self) -> Self {
+        // Allocate the full capacity, so that decoding with the copy doesn't allocate either.
+        let mut secondary_table = Vec::with_capacity(Self::MAX_SECONDARY_TABLE_LEN);
+        secondary_table.extend_from_slice(&self.compression.secondary_table);
+        Self {
+            compression: CompressedBlock {
+                secondary_table,
+                ..self.compression
+            },
+            header: self.header.clone(),
+            uncompressed_bytes_left: self.uncompressed_bytes_left,
+            buffer: self.buffer,
+            nbits: self.nbits,
+            bytes_read: self.bytes_read,
+            block_start: self.block_start,
+            block_type: self.block_type,
+            pending_tables: self.pending_tables,
+            fixed_tables_built: self.fixed_tables_built,
+            block_count: self.block_count,
+            queued_rle: self.queued_rle,
+            queued_backref: self.queued_backref,
+            last_block: self.last_block,
+            state: self.state,
+            checksum: self.checksum.clone(),
+            ignore_adler32: self.ignore_adler32,
+            raw: self.raw,
+            lenient: self.lenient,
+            borrow_stored: self.borrow_stored,
+            stop_at_block_boundaries: self.stop_at_block_boundaries,
+            at_block_boundary: self.at_block_boundary,
+            trailing_data: self.trailing_data,
+            warnings: self.warnings.clone(),
+            max_output_per_call: self.max_output_per_call,
+            max_distance: self.max_distance,
+            output_limit: self.output_limit,
+            max_expansion_ratio: self.max_expansion_ratio,
+            total_output: self.total_output,
+            #[cfg(feature = "perf-stats")]
+            stats: self.stats,
+            summary: self.summary,
+            open_block: self.open_block,
+            progress: None,
+            cancellation_flag: self.cancellation_flag.clone(),
+            output_hasher: None,
+            output_base: self.output_base,
+            internal_window: self.internal_window.clone(),
+            internal_window_end: self.internal_window_end,
+        }
+    }
+}
+
+/// Serialized form of a [`CustomDecompressor`], holding everything needed to continue decoding.
+///
+/// The decoding tables aren't included, since they can be rebuilt from the code lengths.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedDecompressor {
+    state: State,
+    buffer: u64,
+    nbits: u8,
+    bytes_read: u64,
+    block_start: u64,
+    block_type: Option<BlockType>,
+    block_count: u64,
+    last_block: bool,
+    uncompressed_bytes_left: u16,
+    queued_rle: Option<(u8, usize)>,
+    queued_backref: Option<(usize, usize)>,
+    hlit: usize,
+    hdist: usize,
+    hclen: usize,
+    num_lengths_read: usize,
+    code_length_table: Vec<u8>,
+    code_lengths: Vec<u8>,
+    checksum: u32,
+    ignore_adler32: bool,
+    raw: bool,
+    lenient: bool,
+    borrow_stored: bool,
+    stop_at_block_boundaries: bool,
+    trailing_data: bool,
+    warnings: Vec<DecompressionWarning>,
+    max_output_per_call: usize,
+    max_distance: usize,
+    output_limit: u64,
+    max_expansion_ratio: Option<(u64, u64)>,
+    total_output: u64,
+    summary: BlockSummary,
+    open_block: Option<(BlockType, u64)>,
+    output_base: u64,
+    /// The last 32 KiB of output in the internal window, if it was used.
+    window: Vec<u8>,
+}
+
+#[cfg(feature = "serde")]
+impl SavedDecompressor {
+    /// Check that the state is consistent, so that decoding from it can't panic.
+    fn validate(&self) -> Result<(), &'static str> {
+        let in_code_lengths = self.state == State::CodeLengths;
+        let dynamic_header = in_code_lengths
+            || (self.state == State::CompressedData && self.block_type == Some(BlockType::Dynamic));
+        let bit_position = self
+            .bytes_read
+            .checked_mul(8)
+            .and_then(|bits| bits.checked_sub(self.nbits as u64));
+        let valid = self.nbits <= 64
+            && bit_position.map_or(false, |position| {
+                self.block_start <= position
+                    && self.open_block.map_or(true, |(_, start)| start <= position)
+            })
+            && self.code_lengths.len() == 320
+            && self.code_lengths.iter().all(|&length| length <= 15)
+            && self.code_length_table.len() == 128
+            && self.hlit <= 288
+            && self.hdist <= 32
+            && self.hclen <= 19
+            && (!dynamic_header || (self.hlit >= 257 && self.hdist >= 1))
+            && if in_code_lengths && self.hclen > 0 {
+                self.num_lengths_read <= self.hclen
+            } else {
+                self.num_lengths_read <= self.hlit + self.hdist
+            }
+            && (!in_code_lengths
+                || self.hclen > 0
+                || self
+                    .code_length_table
+                    .iter()
+                    .all(|&entry| entry >> 3 <= 18 && entry & 0x7 != 0))
+            && match self.state {
+                State::CompressedData => matches!(
+                    self.block_type,
+                    Some(BlockType::Fixed) | Some(BlockType::Dynamic)
+                ),
+                _ => self.queued_rle.is_none() && self.queued_backref.is_none(),
+            }
+            && self.queued_backref.map_or(true, |(dist, _)| dist <= 32768)
+            && self.window.len() <= WINDOW_SIZE;
+        if valid {
+            Ok(())
+        } else {
+            Err("inconsistent decompressor state")
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<const LITLEN_TABLE_SIZE: usize> serde::Serialize for CustomDecompressor<LITLEN_TABLE_SIZE> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let window_start = self.internal_window_end.saturating_sub(WINDOW_SIZE);
+        SavedDecompressor {
+            state: self.state,
+            buffer: self.buffer,
+            nbits: self.nbits,
+            bytes_read: self.bytes_read,
+            block_start: self.block_start,
+            block_type: self.block_type,
+            block_count: self.block_count,
+            last_block: self.last_block,
+            uncompressed_bytes_left: self.uncompressed_bytes_left,
+            queued_rle: self.queued_rle,
+            queued_backref: self.queued_backref,
+            hlit: self.header.hlit,
+            hdist: self.header.hdist,
+            hclen: self.header.hclen,
+            num_lengths_read: self.header.num_lengths_read,
+            code_length_table: self.header.table.to_vec(),
+            code_lengths: self.header.code_lengths.to_vec(),
+            checksum: self.checksum.finish(),
+            ignore_adler32: self.ignore_adler32,
+            raw: self.raw,
+            lenient: self.lenient,
+            borrow_stored: self.borrow_stored,
+            stop_at_block_boundaries: self.stop_at_block_boundaries,
+            trailing_data: self.trailing_data,
+            warnings: self.warnings.clone(),
+            max_output_per_call: self.max_output_per_call,
+            max_distance: self.max_distance,
+            output_limit: self.output_limit,
+            max_expansion_ratio: self.max_expansion_ratio,
+            total_output: self.total_output,
+            summary: self.summary,
+            open_block: self.open_block,
+            output_base: self.output_base,
+            window: self.internal_window[window_start..self.internal_window_end].to_vec(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const LITLEN_TABLE_SIZE: usize> serde::Deserialize<'de>
+    for CustomDecompressor<LITLEN_TABLE_SIZE>
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let saved = SavedDecompressor::deserialize(deserializer)?;
+        saved.validate().map_err(serde::de::Error::custom)?;
+
+        let mut decompressor = Self::new();
+        decompressor.state = saved.state;
+        decompressor.buffer = saved.buffer;
+        decompressor.nbits = saved.nbits;
+        decompressor.bytes_read = saved.bytes_read;
+        decompressor.block_start = saved.block_start;
+        decompressor.block_type = saved.block_type;
+        decompressor.block_count = saved.block_count;
+        decompressor.last_block = saved.last_block;
+        decompressor.uncompressed_bytes_left = saved.uncompressed_bytes_left;
+        decompressor.queued_rle = saved.queued_rle;
+        decompressor.queued_backref = saved.queued_backref;
+        decompressor.header.hlit = saved.hlit;
+        decompressor.header.hdist = saved.hdist;
+        decompressor.header.hclen = saved.hclen;
+        decompressor.header.num_lengths_read = saved.num_lengths_read;
+        decompressor
+            .header
+            .table
+            .copy_from_slice(&saved.code_length_table);
+        decompressor
+            .header
+            .code_lengths
+            .copy_from_slice(&saved.code_lengths);
+        decompressor.checksum = Adler32::from_checksum(saved.checksum);
+        decompressor.ignore_adler32 = saved.ignore_adler32;
+        decompressor.raw = saved.raw;
+        decompressor.lenient = saved.lenient;
+        decompressor.borrow_stored = saved.borrow_stored;
+        decompressor.stop_at_block_boundaries = saved.stop_at_block_boundaries;
+        decompressor.trailing_data = saved.trailing_data;
+        decompressor.warnings = saved.warnings;
+        decompressor.max_output_per_call = saved.max_output_per_call;
+        decompressor.max_distance = saved.max_distance;
+        decompressor.output_limit = saved.output_limit;
+        decompressor.max_expansion_ratio = saved.max_expansion_ratio;
+        decompressor.total_output = saved.total_output;
+        decompressor.summary = saved.summary;
+        decompressor.open_block = saved.open_block;
+        decompressor.output_base = saved.output_base;
+
+        // The tables of a compressed block are rebuilt before its next symbol is decoded.
+        if decompressor.state == State::CompressedData {
+            decompressor.pending_tables = decompressor.block_type;
+        }
+        if !saved.window.is_empty() {
+            let mut window = vec![0; WINDOW_SIZE + 2 * RECOMMENDED_OUTPUT_BUFFER_SIZE];
+            window[..saved.window.len()].copy_from_slice(&saved.window);
+            decompressor.internal_window = window;
+            decompressor.internal_window_end = saved.window.len();
+        }
+        Ok(decompressor)
+    }
+}
+
+/// Decompressor whose table size is selected at runtime.
+///
+/// The state is boxed, so only the tables of the selected variant are allocated.
+#[derive(Clone)]
+pub enum AnyDecompressor {
+    /// A [`Decompressor`] with the default table size.
+    Standard(Box<Decompressor>),
+    /// A [`SmallDecompressor`] for low-memory environments.
+    Small(Box<SmallDecompressor>),
+}
+
+impl AnyDecompressor {
+    /// Create a new decompressor, using the small tables if `low_memory` is set.
+    pub fn new(low_memory: bool) -> Self {
+        if low_memory {
+            Self::Small(Box::default())
+        } else {
+            Self::Standard(Box::default())
+        }
+    }
+
+    /// Ignore the checksum at the end of the stream.
+    pub fn ignore_adler32(&mut self) {
+        match self {
+            Self::Standard(d) => d.ignore_adler32(),
+            Self::Small(d) => d.ignore_adler32(),
+        }
+    }
+
+    /// Decompresses a chunk of data. See [`Decompressor::read`].
+    pub fn read(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        output_position: usize,
+        end_of_input: bool,
+    ) -> Result<(usize, usize), DecompressionError> {
+        match self {
+            Self::Standard(d) => d.read(input, output, output_position, end_of_input),
+            Self::Small(d) => d.read(input, output, output_position, end_of_input),
+        }
+    }
+
+    /// Returns true if the decompressor has finished decompressing the input.
+    pub fn is_done(&self) -> bool {
+        match self {
+            Self::Standard(d) => d.is_done(),
+            Self::Small(d) => d.is_done(),
+        }
+    }
+
+    /// Check that the stream is complete and wasn't followed by any other data.
+    ///
+    /// See [`Decompressor::finish`].
+    pub fn finish(&self) -> Result<(), DecompressionError> {
+        match self {
+            Self::Standard(d) => d.finish(),
+            Self::Small(d) => d.finish(),
+        }
+    }
+}
+
+/// Returns whether `input` looks like a stream produced by fdeflate's own `Compressor`.
+///
+/// Only the zlib header and the header of the first block are inspected: the stream must start
+/// with a dynamic block that uses fdeflate's literal/length code lengths and a single distance
+/// code. This is cheap, but doesn't validate the rest of the stream.
+pub fn is_fdeflate_stream(input: &[u8]) -> bool {
+    if input.len() < 2 || check_zlib_header(input).is_err() {
+        return false;
+    }
+
+    let mut decompressor = Decompressor::new();
+    decompressor.state = State::BlockHeader;
+    let mut remaining_input = &input[2..];
+    loop {
+        let state = decompressor.state;
+        let result = match state {
+            State::BlockHeader => decompressor.read_block_header(&mut remaining_input),
+            State::CodeLengths => decompressor.read_code_lengths(&mut remaining_input),
+            _ => break,
+        };
+        if result.is_err() || decompressor.state == state {
+            return false;
+        }
+    }
+
+    decompressor.block_type == Some(BlockType::Dynamic) && decompressor.uses_fdeflate_codes()
+}
+
+/// Decompress the given data.
+pub fn decompress_to_vec(input: &[u8]) -> Result<Vec<u8>, DecompressionError> {
+    decompress_to_vec_bounded(input, usize::MAX)
+}
+
+/// Decompress the given data, rejecting any bytes after the end of the stream.
+///
+/// [`decompress_to_vec`] ignores data following the stream, while this fails with
+/// [`DecompressionError::ExtraInput`] instead, for callers that must not accept anything smuggled
+/// in after it.
+pub fn decompress_to_vec_strict(input: &[u8]) -> Result<Vec<u8>, DecompressionError> {
+    let mut decoder = Decompressor::new();
+    let output =
+        decompress_to_vec_inner(&mut decoder, input, estimated_output_len(input), usize::MAX)?;
+    decoder.finish()?;
+    Ok(output)
+}
+
+/// Decompress the given data, producing at most `max_size` bytes of output.
+///
+/// Fails with [`DecompressionError::OutputLimitExceeded`] as soon as the stream would decompress
+/// to more than `max_size` bytes, without ever allocating much more than that. This makes it
+/// suitable for untrusted input, where [`decompress_to_vec`] may be made to allocate arbitrarily
+/// large amounts of memory.
+pub fn decompress_to_vec_bounded(
+    input: &[u8],
+    max_size: usize,
+) -> Result<Vec<u8>, DecompressionError> {
+    decompress_to_vec_inner(
+        &mut Decompressor::new(),
+        input,
+        estimated_output_len(input),
+        max_size,
+    )
+}
+
+/// Initial size of the output buffer for one-shot decompression of `input`.
+fn estimated_output_len(input: &[u8]) -> usize {
+    // Most streams expand by a factor of 2 to 4, so this usually avoids growing the output at
+    // all. The cap keeps a small stream that claims to be huge from reserving too much up front.
+    input.len().saturating_mul(4).clamp(1024, 64 << 20)
+}
+
+/// Decompress the given data into a vector of `size_hint` bytes, growing it only if the output
+/// turns out to be larger.
+///
+/// When the size of the decompressed data is known in advance, like for the image data of a PNG
+/// file, this avoids reallocating and copying the output as it grows. The whole `size_hint` is
+/// allocated up front, so it shouldn't be taken from untrusted input without a sanity check.
+///
+/// ```
+/// # #[cfg(feature = "compress")] {
+/// # use fdeflate::decompress_to_vec_with_capacity;
+/// # let compressed = fdeflate::compress_to_vec(&[7; 10_000]);
+/// let output = decompress_to_vec_with_capacity(&compressed, 10_000).unwrap();
+/// assert_eq!(output, [7; 10_000]);
+/// # }
+/// ```
+pub fn decompress_to_vec_with_capacity(
+    input: &[u8],
+    size_hint: usize,
+) -> Result<Vec<u8>, DecompressionError> {
+    decompress_to_vec_inner(&mut Decompressor::new(), input, size_hint, usize::MAX)
+}
+
+fn decompress_to_vec_inner(
+    decoder: &mut Decompressor,
+    input: &[u8],
+    initial_len: usize,
+    max_size: usize,
+) -> Result<Vec<u8>, DecompressionError> {
+    decoder.set_output_limit(max_size as u64);
+    // The decoder needs room for a byte past the limit to detect that it was exceeded.
+    let max_len = max_size.saturating_add(2);
+    let mut output = vec![0; initial_len.min(max_len)];
+    let mut input_index = 0;
+    let mut output_index = 0;
+    while !decoder.is_done() {
+        let (consumed, produced) =
+            decoder.read(&input[input_index..], &mut output, output_index, true)?;
+        input_index += consumed;
+        output_index += produced;
+        if consumed == 0 && produced == 0 && !decoder.is_done() {
+            // The output is full. Growing geometrically keeps the total cost of zero-filling and
+            // copying linear.
+            let new_len = output.len().saturating_mul(2).clamp(1024, max_len);
+            if new_len == output.len() {
+                return Err(DecompressionError::OutputLimitExceeded);
+            }
+            output.resize(new_len, 0);
+        }
+    }
+    output.truncate(output_index);
+    Ok(output)
+}
+
+/// Decompress a stream produced by [`compress_with_base`](crate::compress_with_base).
+///
+/// `base` must be the same data that was passed when compressing, since the stream's
+/// back-references may point into its last 32 KiB. Streams whose zlib header names a preset
+/// dictionary are only accepted if its Adler-32 checksum matches `base`, and fail with
+/// [`DecompressionError::BadZlibHeader`] otherwise. Plain zlib streams are accepted too.
+pub fn decompress_with_base(base: &[u8], input: &[u8]) -> Result<Vec<u8>, DecompressionError> {
+    if input.len() < 2 {
+        return Err(DecompressionError::InsufficientInput);
+    }
+    check_zlib_header_allowing_dictionary(input)?;
+    let mut input_index = 2;
+    if input[1] & 0x20 != 0 {
+        if input.len() < 6 {
+            return Err(DecompressionError::InsufficientInput);
+        }
+        let dictionary_id = u32::from_be_bytes(input[2..6].try_into().unwrap());
+        if dictionary_id != simd_adler32::adler32(&base) {
+            return Err(DecompressionError::BadZlibHeader);
+        }
+        input_index = 6;
+    }
+
+    let window = &base[base.len().saturating_sub(WINDOW_SIZE)..];
+    let mut output = vec![0; window.len() + 1024];
+    output[..window.len()].copy_from_slice(window);
+    let mut decoder = Decompressor::new();
+    decoder.state = State::BlockHeader;
+    let mut output_index = window.len();
+    while !decoder.is_done() {
+        let (consumed, produced) =
+            decoder.read(&input[input_index..], &mut output, output_index, true)?;
+        input_index += consumed;
+        output_index += produced;
+        output.resize(output_index + RECOMMENDED_OUTPUT_BUFFER_SIZE, 0);
+    }
+    output.truncate(output_index);
+    Ok(output.split_off(window.len()))
+}
+
+/// Output of [`decompress_exact`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExactOutput {
+    /// The decompressed data.
+    pub data: Vec<u8>,
+    /// Length of the zlib stream in bytes, including its header and checksum.
+    pub compressed_len: usize,
+    /// Whether the input continues past the end of the zlib stream.
+    pub trailing_data: bool,
+}
+
+/// Decompress a zlib stream whose decompressed size is known in advance.
+///
+/// The output is allocated up front, and decoding stops with
+/// [`DecompressionError::OutputSizeMismatch`] as soon as the stream produces more data, or if
+/// it ends with less. Bytes after the end of the stream are not an error; the returned
+/// [`ExactOutput`] reports where the stream ended so they can be handled by the caller.
+///
+/// To decompress from a reader instead, use
+/// [`DecompressReader::with_expected_len`](crate::DecompressReader::with_expected_len).
+pub fn decompress_exact(
+    input: &[u8],
+    expected_len: usize,
+) -> Result<ExactOutput, DecompressionError> {
+    let mut decoder = Decompressor::new();
+    decoder.set_output_limit(expected_len as u64);
+    // Two spare bytes let the decoder run past `expected_len` and report the mismatch.
+    let buffer_len = expected_len
+        .checked_add(2)
+        .ok_or(DecompressionError::OutputSizeMismatch)?;
+    let mut output = vec![0; buffer_len];
+    let mut input_index = 0;
+    let mut output_index = 0;
+    while !decoder.is_done() {
+        let (consumed, produced) =
+            match decoder.read(&input[input_index..], &mut output, output_index, true) {
+                Err(DecompressionError::OutputLimitExceeded) => {
+                    return Err(DecompressionError::OutputSizeMismatch)
+                }
+                result => result?,
+            };
+        input_index += consumed;
+        output_index += produced;
+    }
+    if output_index != expected_len {
+        return Err(DecompressionError::OutputSizeMismatch);
+    }
+    output.truncate(expected_len);
+
+    let compressed_len = decoder.byte_position() as usize;
+    Ok(ExactOutput {
+        data: output,
+        compressed_len,
+        trailing_data: compressed_len < input.len(),
+    })
+}
+
+/// Decompress a zlib stream of big-endian 16-bit values into native-endian `u16`s.
+///
+/// This is for data like 16-bit image samples, which are stored most significant byte first. The
+/// bytes are converted as they are decompressed, a chunk at a time while they are still in
+/// cache, instead of in a separate pass over the whole output afterwards.
+///
+/// Returns the number of values written to the start of `output`. Fails with
+/// [`DecompressionError::OutputSizeMismatch`] if the stream decompresses to more values than fit
+/// in `output` or to an odd number of bytes.
+pub fn decompress_u16_be(input: &[u8], output: &mut [u16]) -> Result<usize, DecompressionError> {
+    let mut decoder = Decompressor::new();
+    decoder.set_output_limit(output.len() as u64 * 2);
+    let mut buffer = vec![0; WINDOW_SIZE + 2 * RECOMMENDED_OUTPUT_BUFFER_SIZE];
+    let mut input_index = 0;
+    let mut buffer_index = 0;
+    // Start of the decompressed bytes in `buffer` that haven't been converted yet.
+    let mut convert_index = 0;
+    let mut values_written = 0;
+    while !decoder.is_done() {
+        if buffer.len() - buffer_index < RECOMMENDED_OUTPUT_BUFFER_SIZE {
+            buffer.copy_within(buffer_index - WINDOW_SIZE..buffer_index, 0);
+            convert_index -= buffer_index - WINDOW_SIZE;
+            buffer_index = WINDOW_SIZE;
+        }
+
+        let (consumed, produced) =
+            match decoder.read(&input[input_index..], &mut buffer, buffer_index, true) {
+                Err(DecompressionError::OutputLimitExceeded) => {
+                    return Err(DecompressionError::OutputSizeMismatch)
+                }
+                result => result?,
+            };
+        input_index += consumed;
+        buffer_index += produced;
+
+        let pairs = buffer[convert_index..buffer_index].chunks_exact(2);
+        let n = pairs.len();
+        for (value, pair) in output[values_written..][..n].iter_mut().zip(pairs) {
+            *value = u16::from_be_bytes([pair[0], pair[1]]);
+        }
+        values_written += n;
+        convert_index += n * 2;
+    }
+    if convert_index != buffer_index {
+        return Err(DecompressionError::OutputSizeMismatch);
+    }
+    Ok(values_written)
+}
+
+/// Rewrite the Adler-32 checksum of a zlib stream to match its decompressed contents.
+///
+/// This repairs streams whose compressed data is intact but whose checksum is wrong, which is a
+/// common form of corruption. The stream is fully decoded without verifying the checksum, and a
+/// copy of `input` with the correct checksum is returned. Any data after the end of the stream
+/// is copied unchanged.
+pub fn repair_zlib_trailer(input: &[u8]) -> Result<Vec<u8>, DecompressionError> {
+    let mut decoder = Decompressor::new();
+    decoder.ignore_adler32();
+    let mut output = vec![0; 1024];
+    let mut input_index = 0;
+    let mut output_index = 0;
+    while !decoder.is_done() {
+        let (consumed, produced) =
+            decoder.read(&input[input_index..], &mut output, output_index, true)?;
+        input_index += consumed;
+        output_index += produced;
+        output.resize(output_index + RECOMMENDED_OUTPUT_BUFFER_SIZE, 0);
+    }
+
+    let stream_end = decoder.byte_position() as usize;
+    let mut repaired = input.to_vec();
+    repaired[stream_end - 4..stream_end]
+        .copy_from_slice(&simd_adler32::adler32(&&output[..output_index]).to_be_bytes());
+    Ok(repaired)
+}
+
+/// Decompress a zlib stream that is split across several chunks, such as the IDAT chunks of a PNG
+/// image.
+///
+/// The chunks may be split at arbitrary positions, including within the zlib header or the
+/// checksum at the end of the stream. Any data after the end of the stream is ignored.
+pub fn decompress_chunks_to_vec<'a, I>(chunks: I) -> Result<Vec<u8>, DecompressionError>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut decoder = Decompressor::new();
+    let mut output = vec![0; RECOMMENDED_OUTPUT_BUFFER_SIZE];
+    let mut output_index = 0;
+    // Input that couldn't be consumed yet, like the first byte of a split zlib header.
+    let mut pending = Vec::new();
+
+    let mut chunks = chunks.into_iter().peekable();
+    while !decoder.is_done() {
+        let chunk = chunks.next().unwrap_or(&[]);
+        let end_of_input = chunks.peek().is_none();
+        let input = if pending.is_empty() {
+            chunk
+        } else {
+            pending.extend_from_slice(chunk);
+            &pending[..]
+        };
+
+        let mut input_index = 0;
+        loop {
+            let (consumed, produced) = decoder.read(
+                &input[input_index..],
+                &mut output,
+                output_index,
+                end_of_input,
+            )?;
+            input_index += consumed;
+            output_index += produced;
+            output.resize(output_index + RECOMMENDED_OUTPUT_BUFFER_SIZE, 0);
+            if decoder.is_done() || (consumed == 0 && produced == 0) {
+                break;
+            }
+        }
+        pending = input[input_index..].to_vec();
+    }
+
+    output.truncate(output_index);
+    Ok(output)
+}
+
+/// Decompress each of several independent zlib streams, passing the results to `f`.
+///
+/// A single decompressor and output buffer are reused for all of the streams, so that
+/// decompressing many small buffers doesn't pay for setting up a decompressor and allocating
+/// output for each one. `f` is called in order with the index of each stream and either its
+/// decompressed contents or the error that stopped it. The contents are only valid for the
+/// duration of the call. An error in one stream doesn't prevent the others from being
+/// decompressed.
+///
+/// ```
+/// # #[cfg(feature = "compress")] {
+/// # let inputs = [fdeflate::compress_to_vec(b"first"), fdeflate::compress_to_vec(b"second")];
+/// let mut total = 0;
+/// fdeflate::decompress_each(&inputs, |_index, result| total += result.unwrap().len());
+/// assert_eq!(total, 11);
+/// # }
+/// ```
+pub fn decompress_each<T, F>(inputs: &[T], mut f: F)
+where
+    T: AsRef<[u8]>,
+    F: FnMut(usize, Result<&[u8], DecompressionError>),
+{
+    let mut decoder = Decompressor::new();
+    let mut output = vec![0; RECOMMENDED_OUTPUT_BUFFER_SIZE];
+    for (index, input) in inputs.iter().enumerate() {
+        if index > 0 {
+            decoder.reset();
+        }
+
+        let input = input.as_ref();
+        let mut input_index = 0;
+        let mut output_index = 0;
+        let result = loop {
+            if decoder.is_done() {
+                break Ok(());
+            }
+            if output.len() < output_index + RECOMMENDED_OUTPUT_BUFFER_SIZE {
+                output.resize(output_index + RECOMMENDED_OUTPUT_BUFFER_SIZE, 0);
+            }
+            match decoder.read(&input[input_index..], &mut output, output_index, true) {
+                Ok((consumed, produced)) => {
+                    input_index += consumed;
+                    output_index += produced;
+                }
+                Err(err) => break Err(err),
+            }
+        };
+        f(index, result.map(|()| &output[..output_index]));
+    }
+}
+
+/// Decompress each of several independent zlib streams.
+///
+/// This is [`decompress_each`] collecting the results into a `Vec`, in the same order as
+/// `inputs`. Only one output buffer per stream is allocated, at its final size.
+pub fn decompress_all<T: AsRef<[u8]>>(inputs: &[T]) -> Vec<Result<Vec<u8>, DecompressionError>> {
+    let mut results = Vec::with_capacity(inputs.len());
+    decompress_each(inputs, |_, result| results.push(result.map(<[u8]>::to_vec)));
+    results
+}
+
+/// Decompress the given data, borrowing the output from `input` when possible.
+///
+/// If the stream consists of a single stored block, its payload is validated against the
+/// checksum and returned as a subslice of `input` without copying. Any other stream is
+/// decompressed with [`decompress_to_vec`].
+pub fn decompress_cow(input: &[u8]) -> Result<Cow<'_, [u8]>, DecompressionError> {
+    if input.len() >= 11 && check_zlib_header(input).is_ok() && input[2] & 0b111 == 0b001 {
+        let len = u16::from_le_bytes([input[3], input[4]]);
+        let nlen = u16::from_le_bytes([input[5], input[6]]);
+        if nlen == !len && input.len() >= 11 + len as usize {
+            let data = &input[7..][..len as usize];
+            let checksum = &input[7 + len as usize..][..4];
+            if simd_adler32::adler32(&data) != u32::from_be_bytes(checksum.try_into().unwrap()) {
+                return Err(DecompressionError::WrongChecksum);
+            }
+            return Ok(Cow::Borrowed(data));
+        }
+    }
+    decompress_to_vec(input).map(Cow::Owned)
+}
+
+/// An output region that can be grown in place, such as a memory-mapped file.
+///
+/// Used by [`decompress_to_growable`] to decompress directly into the final destination,
+/// extending it in stages as more space is needed.
+pub trait GrowableOutput {
+    /// Returns the current contents of the region.
+    fn as_mut_slice(&mut self) -> &mut [u8];
+
+    /// Grows the region to at least `min_len` bytes.
+    ///
+    /// Existing contents must be preserved, while the newly added bytes may hold anything.
+    fn grow(&mut self, min_len: usize) -> io::Result<()>;
+}
+
+impl GrowableOutput for Vec<u8> {
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        self
+    }
+
+    fn grow(&mut self, min_len: usize) -> io::Result<()> {
+        self.resize(min_len, 0);
+        Ok(())
+    }
+}
+
+/// Decompresses the given data into a growable output region.
+///
+/// The region is grown geometrically whenever it fills up. Returns the number of decompressed
+/// bytes, which may be less than the final size of the region.
+pub fn decompress_to_growable<O: GrowableOutput + ?Sized>(
+    input: &[u8],
+    output: &mut O,
+) -> io::Result<usize> {
+    let mut decoder = Decompressor::new();
+    let mut input_index = 0;
+    let mut output_index = 0;
+    while !decoder.is_done() {
+        let output_len = output.as_mut_slice().len();
+        if output_len < output_index + RECOMMENDED_OUTPUT_BUFFER_SIZE {
+            output.grow((output_len * 2).max(output_index + RECOMMENDED_OUTPUT_BUFFER_SIZE))?;
+        }
+
+        let (consumed, produced) = decoder.read(
+            &input[input_index..],
+            output.as_mut_slice(),
+            output_index,
+            true,
+        )?;
+        input_index += consumed;
+        output_index += produced;
+    }
+    Ok(output_index)
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use crate::tables::{self, LENGTH_TO_LEN_EXTRA, LENGTH_TO_SYMBOL};
+
+    use super::*;
+    use rand::Rng;
+
+    fn roundtrip(data: &[u8]) {
+        let compressed = crate::compress_to_vec(data);
+        let decompressed = decompress_to_vec(&compressed).unwrap();
+        assert_eq!(&decompressed, data);
+    }
+
+    fn roundtrip_miniz_oxide(data: &[u8]) {
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(data, 3);
+        let decompressed = decompress_to_vec(&compressed).unwrap();
+        assert_eq!(decompressed.len(), data.len());
+        for (i, (a, b)) in decompressed.chunks(1).zip(data.chunks(1)).enumerate() {
+            assert_eq!(a, b, "chunk {}..{}", i, i + 1);
+        }
+        assert_eq!(&decompressed, data);
+    }
+
+    #[allow(unused)]
+    fn compare_decompression(data: &[u8]) {
+        // let decompressed0 = flate2::read::ZlibDecoder::new(std::io::Cursor::new(&data))
+        //     .bytes()
+        //     .collect::<Result<Vec<_>, _>>()
+        //     .unwrap();
+        let decompressed = decompress_to_vec(data).unwrap();
+        let decompressed2 = miniz_oxide::inflate::decompress_to_vec_zlib(data).unwrap();
+        for i in 0..decompressed.len().min(decompressed2.len()) {
+            if decompressed[i] != decompressed2[i] {
+                panic!(
+                    "mismatch at index {} {:?} {:?}",
+                    i,
+                    &decompressed[i.saturating_sub(1)..(i + 16).min(decompressed.len())],
+                    &decompressed2[i.saturating_sub(1)..(i + 16).min(decompressed2.len())]
+                );
+            }
+        }
+        if decompressed != decompressed2 {
+            panic!(
+                "length mismatch {} {} {:x?}",
+                decompressed.len(),
+                decompressed2.len(),
+                &decompressed2[decompressed.len()..][..16]
+            );
+        }
+        //assert_eq!(decompressed, decompressed2);
+    }
+
+    #[test]
+    fn tables() {
+        for (i, &bits) in LEN_SYM_TO_LEN_EXTRA.iter().enumerate() {
+            let len_base = LEN_SYM_TO_LEN_BASE[i];
+            for j in 0..(1 << bits) {
+                if i == 27 && j == 31 {
+                    continue;
+                }
+                assert_eq!(LENGTH_TO_LEN_EXTRA[len_base + j - 3], bits, "{} {}", i, j);
+                assert_eq!(
+                    LENGTH_TO_SYMBOL[len_base + j - 3],
+                    i as u16 + 257,
+                    "{} {}",
+                    i,
+                    j
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fdeflate_table() {
+        let mut compression = CompressedBlock {
+            litlen_table: [0; 4096],
+            dist_table: [0; 512],
+            dist_symbol_lengths: [0; 30],
+            dist_symbol_masks: [0; 30],
+            dist_symbol_codes: [0; 30],
+            secondary_table: Vec::new(),
+            eof_code: 0,
+            eof_mask: 0,
+            eof_bits: 0,
+        };
+        let mut lengths = tables::HUFFMAN_LENGTHS.to_vec();
+        lengths.resize(288, 0);
+        lengths.push(1);
+        lengths.resize(320, 0);
+        Decompressor::build_tables(286, &lengths, &mut compression, 11).unwrap();
+
+        assert_eq!(
+            compression, FDEFLATE_COMPRESSED_BLOCK,
+            "{:#x?}",
+            compression
+        );
+    }
+
+    #[test]
+    fn bit_buffer_byte_order() {
+        let mut decompressor = Decompressor::new();
+        let mut input: &[u8] = &[0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0xff];
+        decompressor.fill_buffer(&mut input);
+        assert_eq!(decompressor.nbits, 56);
+        assert_eq!(decompressor.peak_bits(56), 0xcd_ab89_6745_2301);
+        assert_eq!(decompressor.peak_bits(12), 0x301);
+        decompressor.consume_bits(12);
+        assert_eq!(decompressor.peak_bits(8), 0x52);
+
+        let mut input: &[u8] = &[0xaa, 0xbb];
+        let mut decompressor = Decompressor::new();
+        decompressor.fill_buffer(&mut input);
+        assert_eq!(decompressor.nbits, 16);
+        assert_eq!(decompressor.buffer, 0xbbaa);
+    }
+
+    #[test]
+    fn table_entry_encoding() {
+        let mut decompressor = Decompressor::new();
+        Decompressor::build_tables(288, &FIXED_CODE_LENGTHS, &mut decompressor.compression, 6)
+            .unwrap();
+        let litlen_table = &decompressor.compression.litlen_table;
+
+        // 'A' has the 8-bit fixed code 0b01110001, stored bit-reversed in the stream.
+        let code = 0b10001110;
+        let entry = litlen_table[code];
+        assert_ne!(entry & LITERAL_ENTRY, 0);
+        assert_eq!(entry as u8, 8);
+        assert_eq!((entry >> 16) as u8, b'A');
+
+        // Length symbol 265 (lengths 11-12, one extra bit) has the 7-bit code 0b0001001.
+        let code = 0b1001000;
+        let entry = litlen_table[code];
+        assert_eq!(entry & (LITERAL_ENTRY | EXCEPTIONAL_ENTRY), 0);
+        assert_eq!(entry as u8, 7);
+        assert_eq!((entry >> 8) as u8, 1);
+        assert_eq!(entry >> 16, 11);
+
+        // End of block has the 7-bit code 0b0000000.
+        let entry = litlen_table[0];
+        assert_eq!(entry, EXCEPTIONAL_ENTRY | 7);
+    }
+
+    #[test]
+    fn it_works() {
+        roundtrip(b"Hello world!");
+    }
+
+    #[test]
+    fn constant() {
+        roundtrip_miniz_oxide(&[0; 50]);
+        roundtrip_miniz_oxide(&vec![5; 2048]);
+        roundtrip_miniz_oxide(&vec![128; 2048]);
+        roundtrip_miniz_oxide(&vec![254; 2048]);
+    }
+
+    #[test]
+    fn random() {
+        let mut rng = rand::thread_rng();
+        let mut data = vec![0; 50000];
+        for _ in 0..10 {
+            for byte in &mut data {
+                *byte = rng.gen::<u8>() % 5;
+            }
+            println!("Random data: {:?}", data);
+            roundtrip_miniz_oxide(&data);
+        }
+    }
+
+    #[test]
+    fn ignore_adler32() {
+        let mut compressed = crate::compress_to_vec(b"Hello world!");
+        let last_byte = compressed.len() - 1;
+        compressed[last_byte] = compressed[last_byte].wrapping_add(1);
+
+        match decompress_to_vec(&compressed) {
+            Err(DecompressionError::WrongChecksum) => {}
+            r => panic!("expected WrongChecksum, got {:?}", r),
+        }
+
+        let mut decompressor = Decompressor::new();
+        decompressor.ignore_adler32();
+        let mut decompressed = vec![0; 1024];
+        let decompressed_len = decompressor
+            .read(&compressed, &mut decompressed, 0, true)
+            .unwrap()
+            .1;
+        assert_eq!(&decompressed[..decompressed_len], b"Hello world!");
+    }
+
+    /// Compresses each chunk followed by a sync flush (except the last), and returns the
+    /// compressed data along with the offsets just past each sync flush marker.
+    fn compress_with_sync_flushes(chunks: &[&[u8]]) -> (Vec<u8>, Vec<usize>) {
+        use miniz_oxide::deflate::core::{create_comp_flags_from_zip_params, CompressorOxide};
+        use miniz_oxide::deflate::stream::deflate;
+        use miniz_oxide::MZFlush;
+
+        let mut compressor = CompressorOxide::new(create_comp_flags_from_zip_params(6, 15, 0));
+        let total_len: usize = chunks.iter().map(|chunk| chunk.len()).sum();
+        let mut compressed = vec![0; 1024 + total_len * 2];
+        let mut compressed_len = 0;
+        let mut flush_offsets = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let flush = if i == chunks.len() - 1 {
+                MZFlush::Finish
+            } else {
+                MZFlush::Sync
+            };
+            let result = deflate(
+                &mut compressor,
+                chunk,
+                &mut compressed[compressed_len..],
+                flush,
+            );
+            assert_eq!(result.bytes_consumed, chunk.len());
+            compressed_len += result.bytes_written;
+            if flush == MZFlush::Sync {
+                flush_offsets.push(compressed_len);
+            }
+        }
+        compressed.truncate(compressed_len);
+        (compressed, flush_offsets)
+    }
+
+    #[test]
+    fn sync_markers() {
+        let chunks: [&[u8]; 3] = [b"Hello world! ", b"Hello again, world! ", b"Goodbye!"];
+        let (compressed, flush_offsets) = compress_with_sync_flushes(&chunks);
+        assert_eq!(
+            find_sync_markers(&compressed).collect::<Vec<_>>(),
+            flush_offsets
+        );
+        assert_eq!(decompress_to_vec(&compressed).unwrap(), chunks.concat());
+    }
+
+    #[test]
+    fn recover() {
+        let chunks: [&[u8]; 3] = [b"Hello world! ", b"Hello again, world! ", b"Goodbye!"];
+        let (mut compressed, flush_offsets) = compress_with_sync_flushes(&chunks);
+        compressed[flush_offsets[0]] |= 0b110; // Invalid block type
+
+        let mut decompressor = Decompressor::new();
+        let mut output = vec![0; 1024];
+        let (consumed, produced) = decompressor
+            .read(&compressed[..flush_offsets[0]], &mut output, 0, false)
+            .unwrap();
+        assert_eq!(consumed, flush_offsets[0]);
+        assert_eq!(&output[..produced], chunks[0]);
+        match decompressor.read(&compressed[consumed..], &mut output, produced, true) {
+            Err(DecompressionError::InvalidBlockType) => {}
+            r => panic!("expected InvalidBlockType, got {:?}", r),
+        }
+
+        let position = decompressor.byte_position() as usize;
+        let skipped = decompressor.recover(&compressed[position..]).unwrap();
+        assert_eq!(position + skipped, flush_offsets[1]);
+        let (_, produced2) = decompressor
+            .read(
+                &compressed[position + skipped..],
+                &mut output,
+                produced,
+                true,
+            )
+            .unwrap();
+        assert!(decompressor.is_done());
+        assert_eq!(&output[produced..][..produced2], chunks[2]);
+    }
+
+    #[test]
+    fn resume() {
+        let mut rng = rand::thread_rng();
+        let mut data = vec![0; 100_000];
+        for byte in &mut data {
+            *byte = rng.gen::<u8>() % 5;
+        }
+        for level in [0, 1, 6] {
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, level);
+
+            // Decode part of the stream, feeding input in small pieces.
+            let mut decompressor = Decompressor::new();
+            let mut output = vec![0; data.len() + 2];
+            let mut input_position = 0;
+            let mut output_position = 0;
+            let mut resume_points = Vec::new();
+            while !decompressor.is_done() {
+                let input_end = (input_position + 97).min(compressed.len());
+                let (consumed, produced) = decompressor
+                    .read(
+                        &compressed[input_position..input_end],
+                        &mut output,
+                        output_position,
+                        input_end == compressed.len(),
+                    )
+                    .unwrap();
+                input_position += consumed;
+                output_position += produced;
+                if let Some(resume_point) = decompressor.resume_point() {
+                    resume_points.push((resume_point, output_position));
+                }
+            }
+            assert_eq!(output[..data.len()], data);
+            assert!(resume_points.len() > 10);
+
+            for &(resume_point, output_position) in resume_points.iter().step_by(7) {
+                let input_start = (resume_point.block_start / 8) as usize;
+                let (mut decompressor, consumed) =
+                    Decompressor::resume(&resume_point, &compressed[input_start..]).unwrap();
+                let window_start = output_position.saturating_sub(32 * 1024);
+                let mut output = data[window_start..output_position].to_vec();
+                let window_len = output.len();
+                output.resize(window_len + data.len() - output_position + 2, 0);
+
+                let (_, produced) = decompressor
+                    .read(
+                        &compressed[input_start + consumed..],
+                        &mut output,
+                        window_len,
+                        true,
+                    )
+                    .unwrap();
+                assert!(decompressor.is_done());
+                assert_eq!(produced, data.len() - output_position);
+                assert!(output[window_len..][..produced] == data[output_position..]);
+            }
+        }
+    }
+
+    #[test]
+    fn max_output_per_call() {
+        let mut data = vec![0; 5000];
+        data[2000..3000].fill(7);
+        data[4000..].copy_from_slice(&[1, 2, 3, 4, 5].repeat(200));
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        for max_output in [1, 100] {
+            let mut decompressor = Decompressor::new();
+            decompressor.set_max_output_per_call(max_output);
+            let mut output = vec![0; data.len() + 2];
+            let mut input_position = 0;
+            let mut output_position = 0;
+            while !decompressor.is_done() {
+                let (consumed, produced) = decompressor
+                    .read(
+                        &compressed[input_position..],
+                        &mut output,
+                        output_position,
+                        true,
+                    )
+                    .unwrap();
+                assert!(produced <= max_output);
+                input_position += consumed;
+                output_position += produced;
+            }
+            assert_eq!(output_position, data.len());
+            assert_eq!(&output[..data.len()], &data[..]);
+        }
+    }
+
+    #[test]
+    fn output_limit() {
+        let compressed = crate::compress_to_vec(&[7; 10_000]);
+
+        let mut decompressor = Decompressor::new();
+        decompressor.set_output_limit(10_000);
+        let mut output = vec![0; 20_000];
+        let (_, produced) = decompressor
+            .read(&compressed, &mut output, 0, true)
+            .unwrap();
+        assert!(decompressor.is_done());
+        assert_eq!(produced, 10_000);
+
+        let mut decompressor = Decompressor::new();
+        decompressor.set_output_limit(9_999);
+        match decompressor.read(&compressed, &mut output, 0, true) {
+            Err(DecompressionError::OutputLimitExceeded) => {}
+            r => panic!("expected OutputLimitExceeded, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn max_expansion_ratio() {
+        let zeros = miniz_oxide::deflate::compress_to_vec_zlib(&[0; 100_000], 9);
+        let mut decompressor = Decompressor::new();
+        decompressor.set_max_expansion_ratio(100, 10_000);
+        let mut output = vec![0; 200_000];
+        match decompressor.read(&zeros, &mut output, 0, true) {
+            Err(DecompressionError::ExpansionRatioExceeded) => {}
+            r => panic!("expected ExpansionRatioExceeded, got {:?}", r),
+        }
+
+        let mut decompressor = Decompressor::new();
+        decompressor.set_max_expansion_ratio(100, 200_000);
+        decompressor.read(&zeros, &mut output, 0, true).unwrap();
+        assert!(decompressor.is_done());
+
+        let text = miniz_oxide::deflate::compress_to_vec_zlib(&b"Hello world! ".repeat(100), 9);
+        let mut decompressor = Decompressor::new();
+        decompressor.set_max_expansion_ratio(100, 0);
+        decompressor.read(&text, &mut output, 0, true).unwrap();
+        assert!(decompressor.is_done());
+    }
+
+    #[test]
+    fn max_distance() {
+        let mut rng = rand::thread_rng();
+        let mut data = vec![0; 10_000];
+        for byte in &mut data {
+            *byte = rng.gen();
+        }
+        let data = data.repeat(2);
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+        let mut output = vec![0; data.len() + 2];
+
+        let mut decompressor = Decompressor::new();
+        decompressor.set_max_distance(4096);
+        match decompressor.read(&compressed, &mut output, 0, true) {
+            Err(DecompressionError::DistanceTooFarBack) => {}
+            r => panic!("expected DistanceTooFarBack, got {:?}", r),
+        }
+
+        let mut decompressor = Decompressor::new();
+        decompressor.set_max_distance(10_000);
+        decompressor
+            .read(&compressed, &mut output, 0, true)
+            .unwrap();
+        assert!(decompressor.is_done());
+        assert_eq!(&output[..data.len()], &data[..]);
+    }
+
+    /// Builds a zlib stream with a dynamic block that declares 288 literal/length codes and 32
+    /// distance codes, followed by the given literal/length symbols.
+    fn stream_with_max_hlit_hdist(symbols: &[u16]) -> Vec<u8> {
+        let mut out = vec![0x78, 0x01];
+        let mut buffer = 0u64;
+        let mut nbits = 0;
+        let mut write_bits = |bits: u64, n: u8, out: &mut Vec<u8>| {
+            buffer |= bits << nbits;
+            nbits += n;
+            while nbits >= 8 {
+                out.push(buffer as u8);
+                buffer >>= 8;
+                nbits -= 8;
+            }
+        };
+
+        write_bits(0b101, 3, &mut out); // BFINAL, dynamic block
+        write_bits(31, 5, &mut out); // HLIT = 288
+        write_bits(31, 5, &mut out); // HDIST = 32
+        write_bits(6, 4, &mut out); // HCLEN = 10
+
+        // Code lengths 5, 7, 8, and 9 each get a 2-bit code.
+        let mut code_length_lengths = [0; 19];
+        for &i in &[5, 7, 8, 9] {
+            code_length_lengths[i] = 2;
+        }
+        for &i in &CLCL_ORDER[..10] {
+            write_bits(code_length_lengths[i] as u64, 3, &mut out);
+        }
+        let code_length_codes = crate::compute_codes(&code_length_lengths).unwrap();
+
+        // Use the fixed literal/length code lengths and a 5-bit code for every distance.
+        let mut lengths = [5; 320];
+        lengths[..288].copy_from_slice(&FIXED_CODE_LENGTHS[..288]);
+        for &length in &lengths {
+            write_bits(code_length_codes[length as usize] as u64, 2, &mut out);
+        }
+
+        let litlen_lengths: [u8; 288] = lengths[..288].try_into().unwrap();
+        let litlen_codes = crate::compute_codes(&litlen_lengths).unwrap();
+        for &symbol in symbols.iter().chain(&[256]) {
+            let symbol = symbol as usize;
+            write_bits(
+                litlen_codes[symbol] as u64,
+                litlen_lengths[symbol],
+                &mut out,
+            );
+        }
+        write_bits(0, 7, &mut out);
+
+        let literals: Vec<u8> = symbols.iter().map(|&s| s as u8).collect();
+        let checksum = simd_adler32::adler32(&literals.as_slice());
+        out.extend_from_slice(&checksum.to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn lenient_hlit_hdist() {
+        let compressed = stream_with_max_hlit_hdist(&[b'H' as u16, b'i' as u16]);
+        let mut output = vec![0; 1024];
+
+        match decompress_to_vec(&compressed) {
+            Err(DecompressionError::InvalidHlit) => {}
+            r => panic!("expected InvalidHlit, got {:?}", r),
+        }
+
+        let mut decompressor = Decompressor::new();
+        decompressor.set_lenient(true);
+        let (_, produced) = decompressor
+            .read(&compressed, &mut output, 0, true)
+            .unwrap();
+        assert!(decompressor.is_done());
+        assert_eq!(&output[..produced], b"Hi");
+
+        let compressed = stream_with_max_hlit_hdist(&[b'H' as u16, 286]);
+        let mut decompressor = Decompressor::new();
+        decompressor.set_lenient(true);
+        match decompressor.read(&compressed, &mut output, 0, true) {
+            Err(DecompressionError::InvalidLiteralLengthCode) => {}
+            r => panic!("expected InvalidLiteralLengthCode, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn warnings() {
+        let mut compressed = crate::compress_to_vec(b"Hello world!");
+        let last_byte = compressed.len() - 1;
+        compressed[last_byte] = compressed[last_byte].wrapping_add(1);
+        compressed.extend_from_slice(b"trailing data");
+
+        let mut decompressor = Decompressor::new();
+        let mut output = vec![0; 1024];
+        match decompressor.read(&compressed, &mut output, 0, true) {
+            Err(e) => assert!(e.is_metadata_error()),
+            r => panic!("expected WrongChecksum, got {:?}", r),
+        }
+
+        let mut decompressor = Decompressor::new();
+        decompressor.set_lenient(true);
+        let (_, produced) = decompressor
+            .read(&compressed, &mut output, 0, true)
+            .unwrap();
+        assert!(decompressor.is_done());
+        assert_eq!(&output[..produced], b"Hello world!");
+        assert_eq!(
+            decompressor.warnings(),
+            &[
+                DecompressionWarning::WrongChecksum,
+                DecompressionWarning::TrailingData
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_stream() {
+        let data = b"Hello raw deflate!".repeat(100);
+        let mut input = miniz_oxide::deflate::compress_to_vec(&data, 6);
+        let raw_len = input.len();
+        // Data may follow the stream, like the trailer of a gzip member.
+        input.extend_from_slice(&[0xff; 8]);
+
+        let mut decompressor = Decompressor::new_raw();
+        let mut output = vec![0; data.len()];
+        let (_, produced) = decompressor.read(&input, &mut output, 0, true).unwrap();
+        assert!(decompressor.is_done());
+        assert_eq!(decompressor.compressed_len(), Some(raw_len as u64));
+        assert_eq!(output[..produced], data[..]);
+    }
+
+    #[test]
+    fn raw_stream_end() {
+        let data: Vec<u8> = (0..1000u32)
+            .map(|i| (i % 17) as u8 * (i / 100) as u8)
+            .collect();
+        for level in [1, 6, 9] {
+            for len in [0, 1, 12, 100, 1000] {
+                let raw = miniz_oxide::deflate::compress_to_vec(&data[..len], level);
+                let mut output = vec![0; 2048];
+                let (mut decompressor, _) = Decompressor::with_window(&[], &mut output);
+                let (consumed, produced) = decompressor.read(&raw, &mut output, 0, true).unwrap();
+                assert!(decompressor.is_done());
+                assert_eq!(consumed, raw.len());
+                assert!(output[..produced] == data[..len]);
+            }
+        }
+    }
+
+    #[test]
+    fn finish() {
+        let compressed = crate::compress_to_vec(b"Hello world!");
+        let mut output = vec![0; 1024];
+
+        let mut decompressor = Decompressor::new();
+        let (consumed, _) = decompressor
+            .read(&compressed[..5], &mut output, 0, false)
+            .unwrap();
+        assert!(matches!(
+            decompressor.finish(),
+            Err(DecompressionError::InsufficientInput)
+        ));
+        decompressor
+            .read(&compressed[consumed..], &mut output, 0, true)
+            .unwrap();
+        assert!(decompressor.finish().is_ok());
+
+        // Trailing data is detected whether it arrives along with the end of the stream or later,
+        // and in lenient mode too.
+        let mut trailing = compressed.clone();
+        trailing.push(0);
+        for lenient in [false, true] {
+            let mut decompressor = Decompressor::new();
+            decompressor.set_lenient(lenient);
+            decompressor.read(&trailing, &mut output, 0, true).unwrap();
+            assert!(matches!(
+                decompressor.finish(),
+                Err(DecompressionError::ExtraInput)
+            ));
+
+            let mut decompressor = Decompressor::new();
+            decompressor.set_lenient(lenient);
+            decompressor
+                .read(&compressed, &mut output, 0, true)
+                .unwrap();
+            decompressor.read(b"x", &mut output, 0, true).unwrap();
+            assert!(matches!(
+                decompressor.finish(),
+                Err(DecompressionError::ExtraInput)
+            ));
+        }
+
+        // Raw streams end after their final block.
+        let mut raw = miniz_oxide::deflate::compress_to_vec(b"Hello world!", 6);
+        let (mut decompressor, _) = Decompressor::with_window(&[], &mut output);
+        decompressor.read(&raw, &mut output, 0, true).unwrap();
+        assert!(decompressor.finish().is_ok());
+        raw.push(0);
+        let (mut decompressor, _) = Decompressor::with_window(&[], &mut output);
+        decompressor.read(&raw, &mut output, 0, true).unwrap();
+        assert!(matches!(
+            decompressor.finish(),
+            Err(DecompressionError::ExtraInput)
+        ));
+
+        assert_eq!(
+            decompress_to_vec_strict(&compressed).unwrap(),
+            b"Hello world!"
+        );
+        assert!(matches!(
+            decompress_to_vec_strict(&trailing),
+            Err(DecompressionError::ExtraInput)
+        ));
+    }
+
+    #[test]
+    fn u16_be() {
+        let values: Vec<u16> = (0..200_000u32).map(|i| (i * 7 % 65_521) as u16).collect();
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&bytes, 6);
+
+        let mut output = vec![0; values.len() + 10];
+        let n = decompress_u16_be(&compressed, &mut output).unwrap();
+        assert_eq!(n, values.len());
+        assert!(output[..n] == values[..]);
+
+        assert!(matches!(
+            decompress_u16_be(&compressed, &mut output[..values.len() - 1]),
+            Err(DecompressionError::OutputSizeMismatch)
+        ));
+        let odd = crate::compress_to_vec(&bytes[..1001]);
+        assert!(matches!(
+            decompress_u16_be(&odd, &mut output),
+            Err(DecompressionError::OutputSizeMismatch)
+        ));
+    }
+
+    #[test]
+    fn skip_output() {
+        let data = crate::test_utils::test_data(300_000);
+        for compressed in [
+            crate::compress_to_vec(&data),
+            miniz_oxide::deflate::compress_to_vec_zlib(&data, 6),
+        ] {
+            for (n, output_len) in [
+                (0, 40_000),
+                (1, 40_000),
+                (3, 32 * 1024 + 1),
+                (12_345, 40_000),
+                (100_001, 32 * 1024 + 1),
+                (299_999, 40_000),
+            ] {
+                let mut decompressor = Decompressor::new();
+                let mut output = vec![0; output_len];
+                let mut position = 0;
+                let (consumed, skipped) = decompressor
+                    .skip_output(&compressed, &mut output, &mut position, n, true)
+                    .unwrap();
+                assert_eq!(skipped, n);
+                assert_eq!(decompressor.total_output(), n);
+                assert_eq!(output[..position], data[n as usize - position..n as usize]);
+
+                // Decode the rest into a buffer that holds the retained window.
+                let mut rest = output[..position].to_vec();
+                rest.resize(position + data.len() - n as usize + 2, 0);
+                let (_, produced) = decompressor
+                    .read(&compressed[consumed..], &mut rest, position, true)
+                    .unwrap();
+                assert!(decompressor.is_done());
+                assert!(rest[position..][..produced] == data[n as usize..]);
+            }
+        }
+    }
+
+    #[test]
+    fn stop_at_block_boundaries() {
+        let chunks: [&[u8]; 3] = [b"Hello world! ", b"Hello again, world! ", b"Goodbye!"];
+        let (compressed, flush_offsets) = compress_with_sync_flushes(&chunks);
+
+        let mut decompressor = Decompressor::new();
+        decompressor.set_stop_at_block_boundaries(true);
+        let mut output = vec![0; 1024];
+        let mut input = &compressed[..];
+        let mut position = 0;
+        let mut boundaries = Vec::new();
+        while !decompressor.is_done() {
+            let (consumed, produced) = decompressor
+                .read(input, &mut output, position, true)
+                .unwrap();
+            input = &input[consumed..];
+            position += produced;
+            boundaries.extend(decompressor.block_boundary());
+        }
+        assert_eq!(&output[..position], chunks.concat());
+
+        // One boundary after the zlib header, and one after every block.
+        assert_eq!(boundaries.len() as u64, decompressor.block_count() + 1);
+        assert_eq!(boundaries[0], 16);
+        assert!(boundaries.windows(2).all(|w| w[0] < w[1]));
+        for offset in flush_offsets {
+            assert!(boundaries.contains(&(offset as u64 * 8)));
+        }
+        // The final block ends just before the padding that precedes the checksum.
+        let checksum_start = (compressed.len() as u64 - 4) * 8;
+        assert!((checksum_start - 7..=checksum_start).contains(boundaries.last().unwrap()));
+    }
+
+    #[test]
+    fn read_windowed() {
+        let data = crate::test_utils::test_data(300_000);
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        let mut decompressor = Decompressor::new();
+        for chunk_size in [1, 999, 40_000, 1 << 20] {
+            decompressor.reset();
+            let mut chunk = vec![0; chunk_size];
+            let mut output = Vec::new();
+            let mut input = &compressed[..];
+            while !decompressor.is_done() {
+                let end = input.len().min(5000);
+                let (consumed, produced) = decompressor
+                    .read_windowed(&input[..end], &mut chunk, end == input.len())
+                    .unwrap();
+                input = &input[consumed..];
+                output.extend_from_slice(&chunk[..produced]);
+            }
+            assert!(input.is_empty());
+            assert!(output == data);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn round_trip(decompressor: &Decompressor) -> Decompressor {
+        let saved = bincode::serialize(decompressor).unwrap();
+        bincode::deserialize(&saved).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_round_trip() {
+        let data: Vec<u8> = (0..20_000u32)
+            .map(|i| ((i * i) >> 7) as u8 ^ (i / 301) as u8)
+            .collect();
+        let streams = [
+            crate::compress_to_vec(&data),
+            miniz_oxide::deflate::compress_to_vec_zlib(&data, 0),
+            miniz_oxide::deflate::compress_to_vec_zlib(&data, 6),
+        ];
+
+        for compressed in &streams {
+            // Suspend the decompressor after every few bytes of input, including in the middle of
+            // headers, while keeping the output buffer.
+            let mut decompressor = Decompressor::new();
+            let mut output = vec![0; data.len()];
+            let mut output_position = 0;
+            let mut input = &compressed[..];
+            while !decompressor.is_done() {
+                let end = input.len().min(7);
+                let (consumed, produced) = decompressor
+                    .read(
+                        &input[..end],
+                        &mut output,
+                        output_position,
+                        end == input.len(),
+                    )
+                    .unwrap();
+                input = &input[consumed..];
+                output_position += produced;
+                decompressor = round_trip(&decompressor);
+            }
+            assert!(output == data);
+
+            // The checksum is still verified after resuming.
+            let mut corrupted = compressed.clone();
+            *corrupted.last_mut().unwrap() ^= 1;
+            let mut output = vec![0; data.len() + 1024];
+            let mut decompressor = Decompressor::new();
+            let (consumed, produced) = decompressor
+                .read(&corrupted[..corrupted.len() / 2], &mut output, 0, false)
+                .unwrap();
+            let mut decompressor = round_trip(&decompressor);
+            let result = decompressor.read(&corrupted[consumed..], &mut output, produced, true);
+            assert!(matches!(result, Err(DecompressionError::WrongChecksum)));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_window() {
+        let data = crate::test_utils::test_data(300_000);
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        // The internal window is saved along with the decompressor, so no output needs to be kept.
+        let mut decompressor = Decompressor::new();
+        let mut chunk = vec![0; 4096];
+        let mut output = Vec::new();
+        let mut input = &compressed[..];
+        while !decompressor.is_done() {
+            let end = input.len().min(5000);
+            let (consumed, produced) = decompressor
+                .read_windowed(&input[..end], &mut chunk, end == input.len())
+                .unwrap();
+            input = &input[consumed..];
+            output.extend_from_slice(&chunk[..produced]);
+            decompressor = round_trip(&decompressor);
+        }
+        assert!(output == data);
+
+        // The number of bits in the bit buffer follows the state and the bit buffer itself.
+        let mut saved = bincode::serialize(&Decompressor::new()).unwrap();
+        assert_eq!(saved[12], 0);
+        saved[12] = 65;
+        assert!(bincode::deserialize::<Decompressor>(&saved).is_err());
+    }
+
+    #[test]
+    fn decompress_into() {
+        let data = crate::test_utils::test_data(300_000);
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        for chunk_size in [1000, compressed.len()] {
+            let mut decompressor = Decompressor::new();
+            let mut output = b"prefix".to_vec();
+            for chunk in compressed.chunks(chunk_size) {
+                let end_of_input = chunk.as_ptr_range().end == compressed.as_ptr_range().end;
+                let consumed = decompressor
+                    .decompress_into(chunk, &mut output, end_of_input)
+                    .unwrap();
+                assert_eq!(consumed, chunk.len());
+            }
+            assert!(decompressor.is_done());
+            assert!(output[..6] == *b"prefix" && output[6..] == data);
+        }
+
+        let mut decompressor = Decompressor::new();
+        let mut output = Vec::new();
+        let result = decompressor.decompress_into(&compressed[..5000], &mut output, true);
+        assert!(matches!(result, Err(DecompressionError::InsufficientInput)));
+        assert!(!output.is_empty() && data.starts_with(&output));
+    }
+
+    #[test]
+    fn read_with() {
+        let data = crate::test_utils::test_data(300_000);
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        let mut decompressor = Decompressor::new();
+        let mut output = Vec::new();
+        let mut input = &compressed[..];
+        for i in 0.. {
+            // Mix in chunks of output decoded with `read_windowed`.
+            if i % 3 == 0 {
+                let mut chunk = [0; 4000];
+                let (consumed, produced) = decompressor
+                    .read_windowed(&input[..input.len().min(1000)], &mut chunk, false)
+                    .unwrap();
+                input = &input[consumed..];
+                output.extend_from_slice(&chunk[..produced]);
+            }
+            let consumed = decompressor
+                .read_with(&input[..input.len().min(1000)], false, |chunk| {
+                    output.extend_from_slice(chunk);
+                    Ok::<_, DecompressionError>(())
+                })
+                .unwrap();
+            input = &input[consumed..];
+            if decompressor.is_done() {
+                break;
+            }
+        }
+        assert!(output == data);
+
+        let result = Decompressor::new().read_with(&compressed, true, |_| {
+            Err(std::io::Error::new(std::io::ErrorKind::Other, "stop"))
+        });
+        assert_eq!(result.unwrap_err().to_string(), "stop");
+    }
+
+    /// A reader that returns the input in fragments of the given sizes.
+    struct Fragments<'a> {
+        data: &'a [u8],
+        sizes: std::iter::Cycle<std::slice::Iter<'a, usize>>,
+        fragment: &'a [u8],
+    }
+
+    impl io::Read for Fragments<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = io::BufRead::fill_buf(self)?.len().min(buf.len());
+            buf[..n].copy_from_slice(&self.fragment[..n]);
+            io::BufRead::consume(self, n);
+            Ok(n)
+        }
+    }
+
+    impl io::BufRead for Fragments<'_> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            if self.fragment.is_empty() {
+                let (fragment, rest) = self
+                    .data
+                    .split_at((*self.sizes.next().unwrap()).min(self.data.len()));
+                self.fragment = fragment;
+                self.data = rest;
+            }
+            Ok(self.fragment)
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.fragment = &self.fragment[amt..];
+        }
+    }
+
+    #[test]
+    fn read_buf() {
+        let data = crate::test_utils::test_data(100_000);
+        let streams = [
+            crate::compress_to_vec(&data),
+            miniz_oxide::deflate::compress_to_vec_zlib(&data, 0),
+            miniz_oxide::deflate::compress_to_vec_zlib(&data, 6),
+        ];
+        for compressed in &streams {
+            for sizes in [&[1][..], &[1, 2, 3, 5, 8, 13, 1000], &[8192]] {
+                let mut input = Fragments {
+                    data: compressed,
+                    sizes: sizes.iter().cycle(),
+                    fragment: &[],
+                };
+                let mut decompressor = Decompressor::new();
+                let mut output = vec![0; data.len()];
+                let n = decompressor.read_buf(&mut input, &mut output, 0).unwrap();
+                assert!(decompressor.is_done());
+                assert_eq!(n, data.len());
+                assert!(output == data);
+            }
+        }
+
+        let truncated = &streams[0][..streams[0].len() - 1];
+        let mut output = vec![0; data.len() + 1];
+        let result = Decompressor::new().read_buf(&mut &truncated[..], &mut output, 0);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn tiny_output() {
+        let data: Vec<u8> = (0..20_000u32)
+            .map(|i| {
+                if (i / 500) % 2 == 0 {
+                    0
+                } else {
+                    (i % 13) as u8
+                }
+            })
+            .collect();
+        let streams = [
+            crate::compress_to_vec(&data),
+            miniz_oxide::deflate::compress_to_vec_zlib(&data, 0),
+            miniz_oxide::deflate::compress_to_vec_zlib(&data, 1),
+            miniz_oxide::deflate::compress_to_vec_zlib(&data, 9),
+        ];
+        for compressed in streams {
+            let mut decompressor = Decompressor::new();
+            let mut output = vec![0; data.len()];
+            let mut position = 0;
+            let mut input = &compressed[..];
+            for room in [0, 1].iter().cycle() {
+                if decompressor.is_done() {
+                    break;
+                }
+                let end = (position + room).min(output.len());
+                let (consumed, produced) = decompressor
+                    .read(input, &mut output[..end], position, true)
+                    .unwrap();
+                assert!(produced <= *room);
+                input = &input[consumed..];
+                position += produced;
+            }
+            assert_eq!(position, data.len());
+            assert!(output == data);
+        }
+    }
+
+    #[test]
+    fn dirty_output() {
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|i| {
+                if (i / 500) % 2 == 0 {
+                    0
+                } else {
+                    (i % 13) as u8
+                }
+            })
+            .collect();
+        let streams = [
+            crate::compress_to_vec(&data),
+            miniz_oxide::deflate::compress_to_vec_zlib(&data, 6),
+        ];
+        for compressed in streams {
+            let mut decompressor = Decompressor::new();
+            let mut output = vec![0xaa; data.len() + 100];
+            let mut position = 0;
+            let mut input = &compressed[..];
+            // Small calls exercise the queued runs and back-references.
+            while !decompressor.is_done() {
+                let end = (position + 77).min(output.len());
+                let (consumed, produced) = decompressor
+                    .read(input, &mut output[..end], position, true)
+                    .unwrap();
+                input = &input[consumed..];
+                position += produced;
+                // At most 15 bytes after the produced data are overwritten.
+                assert!(output[(position + 15).min(end)..]
+                    .iter()
+                    .all(|&b| b == 0xaa));
+            }
+            assert!(output[..position] == data);
+
+            // The same holds for a single call with plenty of room.
+            let mut output = vec![0xaa; data.len() + 100];
+            decompressor.reset();
+            let (_, produced) = decompressor
+                .read(&compressed, &mut output, 0, true)
+                .unwrap();
+            assert!(output[..produced] == data);
+            assert!(output[produced + 15..].iter().all(|&b| b == 0xaa));
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let first = miniz_oxide::deflate::compress_to_vec_zlib(&[1; 100_000], 6);
+        let data: Vec<u8> = (0..50_000u32).map(|i| ((i * i) >> 7) as u8).collect();
+        let second = miniz_oxide::deflate::compress_to_vec_zlib(&data, 9);
+
+        let mut decompressor = Decompressor::new();
+        let mut output = vec![0; 200_000];
+        // Abandon the first stream halfway through.
+        decompressor
+            .read(&first[..first.len() / 2], &mut output, 0, false)
+            .unwrap();
+        let table = decompressor.compression.secondary_table.as_ptr();
+
+        for stream in [&first, &second, &second] {
+            decompressor.reset();
+            output.fill(0);
+            let (consumed, produced) = decompressor.read(stream, &mut output, 0, true).unwrap();
+            assert!(decompressor.is_done());
+            assert_eq!(consumed, stream.len());
+            assert_eq!(decompressor.total_output(), produced as u64);
+            let summary = decompressor.block_summary();
+            let summarized = summary.stored.uncompressed_bytes
+                + summary.fixed.uncompressed_bytes
+                + summary.dynamic.uncompressed_bytes;
+            assert_eq!(summarized, produced as u64);
+        }
+        assert!(output[..data.len()] == data);
+        assert_eq!(decompressor.compression.secondary_table.as_ptr(), table);
+    }
+
+    #[test]
+    fn clone() {
+        let data: Vec<u8> = (0..200_000u32)
+            .map(|i| (((i % 4099) * (i % 4099)) >> 7) as u8 ^ (i / 301) as u8)
+            .collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 9);
+
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut decompressor = Decompressor::new();
+        decompressor.set_cancellation_flag(flag.clone());
+        decompressor.set_progress_callback(1000, |_, _| {});
+        let mut output = vec![0; data.len() + 1024];
+        let (consumed, produced) = decompressor
+            .read(&compressed[..compressed.len() / 2], &mut output, 0, false)
+            .unwrap();
+
+        // Both copies continue independently from the fork, each with its own output.
+        let mut fork = decompressor.clone();
+        assert!(fork.progress.is_none());
+        assert!(
+            fork.compression.secondary_table.capacity() >= Decompressor::MAX_SECONDARY_TABLE_LEN
+        );
+        let mut fork_output = output.clone();
+        for (decompressor, output) in [
+            (&mut decompressor, &mut output),
+            (&mut fork, &mut fork_output),
+        ] {
+            decompressor
+                .read(&compressed[consumed..], output, produced, true)
+                .unwrap();
+            assert!(decompressor.is_done());
+            assert!(output[..data.len()] == data);
+        }
+
+        // The cancellation flag is shared.
+        let mut fork = Decompressor::new();
+        fork.set_cancellation_flag(flag.clone());
+        let mut fork = fork.clone();
+        flag.store(true, Ordering::Relaxed);
+        assert!(matches!(
+            fork.read(&compressed, &mut output, 0, true),
+            Err(DecompressionError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn bounded_output() {
+        let data = vec![5; 100_000];
+        let compressed = crate::compress_to_vec(&data);
+
+        for max_size in [100_000, 100_001, 1 << 30] {
+            assert!(decompress_to_vec_bounded(&compressed, max_size).unwrap() == data);
+        }
+        for max_size in [0, 1, 1000, 99_999] {
+            assert!(matches!(
+                decompress_to_vec_bounded(&compressed, max_size),
+                Err(DecompressionError::OutputLimitExceeded)
+            ));
+        }
+        assert_eq!(
+            decompress_to_vec_bounded(&crate::compress_to_vec(&[]), 0).unwrap(),
+            []
+        );
+    }
+
+    #[test]
+    fn output_capacity() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 13) as u8).collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        // An exact hint is used as is, without growing the output.
+        let output = decompress_to_vec_with_capacity(&compressed, data.len()).unwrap();
+        assert!(output == data);
+        assert_eq!(output.capacity(), data.len());
+
+        for size_hint in [0, 1, 1000, 99_999, 100_001, 1 << 20] {
+            assert!(decompress_to_vec_with_capacity(&compressed, size_hint).unwrap() == data);
+        }
+        assert!(matches!(
+            decompress_to_vec_with_capacity(&compressed[..compressed.len() - 1], data.len() + 1),
+            Err(DecompressionError::InsufficientInput)
+        ));
+    }
+
+    #[test]
+    fn progress_hints() {
+        let data: Vec<u8> = (0..20_000u32)
+            .map(|i| (i % 7) as u8 * (i / 3000) as u8)
+            .collect();
+        // Zero runs between literals, which fdeflate encodes as back-references with extra bits.
+        let mut runs = crate::test_utils::test_data(20_000);
+        for (i, byte) in runs.iter_mut().enumerate() {
+            if i / 500 % 2 == 0 {
+                *byte = 0;
+            }
+        }
+        let streams = [
+            (&data, crate::compress_to_vec(&data)),
+            (&runs, crate::compress_to_vec(&runs)),
+            (&data, miniz_oxide::deflate::compress_to_vec_zlib(&data, 6)),
+            (&data, miniz_oxide::deflate::compress_to_vec_zlib(&data, 0)),
+        ];
+
+        for (data, compressed) in streams {
+            let mut decompressor = Decompressor::new();
+            let mut output = vec![0; 32 * 1024];
+            let mut position = 0;
+            let mut input_position = 0;
+            while !decompressor.is_done() {
+                // Starve the decoder of both input and output space.
+                let end = (input_position + decompressor.input_needed_hint().max(1))
+                    .min(compressed.len());
+                let output_end = (position + 100).min(output.len());
+                let (consumed, produced) = decompressor
+                    .read(
+                        &compressed[input_position..end],
+                        &mut output[..output_end],
+                        position,
+                        end == compressed.len(),
+                    )
+                    .unwrap();
+                input_position += consumed;
+                position += produced;
+
+                let available = decompressor.output_available_hint();
+                let needed = decompressor.input_needed_hint();
+                assert!(available == 0 || needed == 0);
+                if needed > 0 && !decompressor.is_done() {
+                    let bit_position = decompressor.bit_position();
+                    let result = decompressor.read(&[], &mut output, position, false);
+                    assert_eq!(result.unwrap(), (0, 0));
+                    assert_eq!(decompressor.bit_position(), bit_position);
+                } else if available > 0 {
+                    let (_, produced) = decompressor
+                        .read(&[], &mut output, position, false)
+                        .unwrap();
+                    assert!(produced >= available);
+                    position += produced;
+                } else if matches!(decompressor.state, State::CompressedData)
+                    && position < output_end
+                {
+                    // Without more input, the buffered bits must be enough to decode a symbol.
+                    let bit_position = decompressor.bit_position();
+                    let (_, produced) = decompressor
+                        .read(&[], &mut output[..output_end], position, false)
+                        .unwrap();
+                    assert!(produced > 0 || decompressor.bit_position() != bit_position);
+                    position += produced;
+                }
+            }
+            assert_eq!(decompressor.input_needed_hint(), 0);
+            assert!(output.starts_with(&data[..position]));
+        }
+    }
+
+    #[test]
+    fn block_accessors() {
+        let chunks: [&[u8]; 3] = [b"Hello world! ", b"Hello again, world! ", b"Goodbye!"];
+        let (compressed, flush_offsets) = compress_with_sync_flushes(&chunks);
+
+        let mut decompressor = Decompressor::new();
+        assert_eq!(decompressor.block_count(), 0);
+        assert_eq!(decompressor.current_block_type(), None);
+
+        let mut output = vec![0; 1024];
+        let (consumed, produced) = decompressor
+            .read(&compressed[..flush_offsets[0]], &mut output, 0, false)
+            .unwrap();
+        assert_eq!(decompressor.block_count(), 2);
+        assert_eq!(decompressor.current_block_type(), Some(BlockType::Stored));
+        assert!(!decompressor.last_block_seen());
+
+        decompressor
+            .read(&compressed[consumed..], &mut output, produced, true)
+            .unwrap();
+        assert!(decompressor.is_done());
+        assert_eq!(decompressor.block_count(), 5);
+        assert!(decompressor.last_block_seen());
+    }
+
+    #[test]
+    fn counters_past_4gib() {
+        let compressed = crate::compress_to_vec(&[1; 1000]);
+        let mut decompressor = Decompressor::new();
+        decompressor.bytes_read = u32::MAX as u64;
+        decompressor.total_output = u32::MAX as u64;
+        decompressor.set_output_limit(u32::MAX as u64 + 1000);
+
+        let mut output = vec![0; 2048];
+        let (consumed, produced) = decompressor
+            .read(&compressed, &mut output, 0, true)
+            .unwrap();
+        assert!(decompressor.is_done());
+        assert_eq!(
+            decompressor.total_input(),
+            u32::MAX as u64 + consumed as u64
+        );
+        assert_eq!(
+            decompressor.total_output(),
+            u32::MAX as u64 + produced as u64
+        );
+        assert_eq!(
+            decompressor.byte_position(),
+            u32::MAX as u64 + compressed.len() as u64
+        );
+    }
+
+    /// Decodes a stream of more than 4 GiB. This takes a while, so it isn't run by default.
+    #[test]
+    #[ignore]
+    fn stream_over_4gib() {
+        let chunk = vec![0; 1 << 20];
+        let num_chunks = 4 * 1024 + 1;
+        let mut compressor = crate::Compressor::new(Vec::new()).unwrap();
+        for _ in 0..num_chunks {
+            compressor.write_data(&chunk).unwrap();
+        }
+        let compressed = compressor.finish().unwrap();
+        let total_size = num_chunks as u64 * chunk.len() as u64;
+
+        // The output is all zeros and fdeflate only uses back-references with a distance of one,
+        // so a single zero byte of history is enough.
+        let mut decompressor = Decompressor::new();
+        let mut output = vec![0; 1 << 20];
+        let mut input_position = 0;
+        while !decompressor.is_done() {
+            let (consumed, _) = decompressor
+                .read(&compressed[input_position..], &mut output, 1, true)
+                .unwrap();
+            input_position += consumed;
+        }
+        assert_eq!(decompressor.total_output(), total_size);
+        assert_eq!(decompressor.byte_position(), compressed.len() as u64);
+    }
+
+    #[test]
+    fn growable_output() {
+        struct StagedOutput {
+            data: Vec<u8>,
+            grow_calls: usize,
+        }
+        impl GrowableOutput for StagedOutput {
+            fn as_mut_slice(&mut self) -> &mut [u8] {
+                &mut self.data
+            }
+            fn grow(&mut self, min_len: usize) -> io::Result<()> {
+                assert!(min_len > self.data.len());
+                self.grow_calls += 1;
+                self.data.resize(min_len, 0);
+                Ok(())
+            }
+        }
+
+        let data: Vec<u8> = (0..500_000u32).map(|i| (i % 7 + i / 1000) as u8).collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        let mut output = StagedOutput {
+            data: Vec::new(),
+            grow_calls: 0,
+        };
+        let len = decompress_to_growable(&compressed, &mut output).unwrap();
+        assert_eq!(len, data.len());
+        assert!(output.data[..len] == data[..]);
+        assert!(output.grow_calls < 10);
+
+        let mut output = Vec::new();
+        let len = decompress_to_growable(&compressed, &mut output).unwrap();
+        assert!(output[..len] == data[..]);
+    }
+
+    #[test]
+    fn stream_position() {
+        let mut compressed = crate::compress_to_vec(b"Hello world!");
+        let stream_len = compressed.len() as u64;
+        compressed.extend_from_slice(b"trailing data");
+
+        let mut decompressor = Decompressor::new();
+        assert_eq!(decompressor.bit_position(), 0);
+
+        let mut output = vec![0; 1024];
+        let (consumed, _) = decompressor
+            .read(&compressed[..20], &mut output, 0, false)
+            .unwrap();
+        assert_eq!(consumed, 20);
+        assert!(decompressor.bit_position() <= 20 * 8);
+
+        let mut output_position = 0;
+        let mut input_position = consumed;
+        while !decompressor.is_done() {
+            let (consumed, produced) = decompressor
+                .read(
+                    &compressed[input_position..],
+                    &mut output,
+                    output_position,
+                    true,
+                )
+                .unwrap();
+            input_position += consumed;
+            output_position += produced;
+        }
+        assert_eq!(decompressor.bit_position(), stream_len * 8);
+        assert_eq!(decompressor.byte_position(), stream_len);
+        assert!(decompressor.is_byte_aligned());
+    }
+
+    #[test]
+    fn compressed_len() {
+        let data = b"Hello world! Hello world!";
+        let zlib = crate::compress_to_vec(data);
+        let raw = miniz_oxide::deflate::compress_to_vec(data, 6);
+        for (compressed, is_raw) in [(zlib, false), (raw, true)] {
+            let mut input = compressed.clone();
+            input.extend_from_slice(b"trailing data");
+
+            let mut decompressor = Decompressor::new();
+            let mut output = vec![0; 1024];
+            if is_raw {
+                decompressor = Decompressor::with_window(&[], &mut output).0;
+            }
+            assert_eq!(decompressor.compressed_len(), None);
+            let mut consumed = 0;
+            let mut output_position = 0;
+            while !decompressor.is_done() {
+                assert_eq!(decompressor.read_ahead_len(), 0);
+                let end = (consumed + 10).min(input.len());
+                let (n, produced) = decompressor
+                    .read(&input[consumed..end], &mut output, output_position, false)
+                    .unwrap();
+                consumed += n;
+                output_position += produced;
+            }
+            assert_eq!(&output[..output_position], data);
+            assert_eq!(decompressor.compressed_len(), Some(compressed.len() as u64));
+            assert_eq!(
+                consumed as u64 - decompressor.read_ahead_len(),
+                compressed.len() as u64
+            );
+        }
+    }
+
+    #[test]
+    fn peek_block_header() {
+        let decompressor = Decompressor::new();
+
+        let stored = miniz_oxide::deflate::compress_to_vec_zlib(b"Hello world!", 0);
+        let header = decompressor.peek_block_header(&stored).unwrap().unwrap();
+        assert_eq!(header.block_type, BlockType::Stored);
+        assert_eq!(header.stored_len, Some(12));
+        assert!(decompressor
+            .peek_block_header(&stored[..4])
+            .unwrap()
+            .is_none());
+
+        let fixed = [0x78, 0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01];
+        let header = decompressor.peek_block_header(&fixed).unwrap().unwrap();
+        assert_eq!(header.block_type, BlockType::Fixed);
+        assert!(header.last_block);
+
+        let dynamic = crate::compress_to_vec(b"Hello world!");
+        let header = decompressor.peek_block_header(&dynamic).unwrap().unwrap();
+        assert_eq!(header.block_type, BlockType::Dynamic);
+        assert_eq!(header.stored_len, None);
 
+        let mut decompressor = Decompressor::new();
+        let mut output = vec![0; 1024];
+        decompressor.read(&dynamic, &mut output, 0, true).unwrap();
+        assert!(decompressor.peek_block_header(&[]).unwrap().is_none());
+
+        // Part of the zlib header is already in the bit buffer.
+        let mut decompressor = Decompressor::new();
         assert_eq!(
-            compression, FDEFLATE_COMPRESSED_BLOCK,
-            "{:#x?}",
-            compression
+            decompressor
+                .read(&stored[..1], &mut output, 0, false)
+                .unwrap()
+                .0,
+            1
         );
+        let header = decompressor
+            .peek_block_header(&stored[1..])
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.block_type, BlockType::Stored);
+        assert_eq!(header.stored_len, Some(12));
     }
 
     #[test]
-    fn it_works() {
-        roundtrip(b"Hello world!");
+    fn borrow_stored_blocks() {
+        let mut rng = rand::thread_rng();
+        let mut data = vec![0; 200_000];
+        for byte in &mut data {
+            *byte = rng.gen();
+        }
+        let compressed = crate::compress_stored_vectored(&data, Vec::new()).unwrap();
+
+        let mut decompressor = Decompressor::new();
+        decompressor.set_borrow_stored_blocks(true);
+        let mut decompressed = Vec::new();
+        let mut output = vec![0; 1024];
+        let mut input = &compressed[..];
+        let mut borrowed = 0;
+        while !decompressor.is_done() {
+            let (consumed, stored) = decompressor.read_stored(input).unwrap();
+            if !stored.is_empty() {
+                assert!(compressed.as_ptr_range().contains(&stored.as_ptr()));
+                decompressed.extend_from_slice(stored);
+                borrowed += stored.len();
+                input = &input[consumed..];
+                continue;
+            }
+
+            let (consumed, produced) = decompressor.read(input, &mut output, 0, true).unwrap();
+            decompressed.extend_from_slice(&output[..produced]);
+            input = &input[consumed..];
+        }
+        assert_eq!(decompressed, data);
+        assert!(borrowed > data.len() - 100);
+        assert_eq!(decompressor.total_output(), data.len() as u64);
     }
 
     #[test]
-    fn constant() {
-        roundtrip_miniz_oxide(&vec![0; 50]);
-        roundtrip_miniz_oxide(&vec![5; 2048]);
-        roundtrip_miniz_oxide(&vec![128; 2048]);
-        roundtrip_miniz_oxide(&vec![254; 2048]);
+    fn cow() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+
+        let stored = crate::compress_stored_vectored(&data, Vec::new()).unwrap();
+        match decompress_cow(&stored).unwrap() {
+            Cow::Borrowed(output) => assert_eq!(output, &data[..]),
+            Cow::Owned(_) => panic!("expected a borrowed result"),
+        }
+
+        let mut corrupted = stored.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            decompress_cow(&corrupted),
+            Err(DecompressionError::WrongChecksum)
+        ));
+
+        let compressed = crate::compress_to_vec(&data);
+        match decompress_cow(&compressed).unwrap() {
+            Cow::Owned(output) => assert_eq!(output, data),
+            Cow::Borrowed(_) => panic!("expected an owned result"),
+        }
     }
 
     #[test]
-    fn random() {
+    fn custom_table_size() {
+        fn decompress<const N: usize>(input: &[u8]) -> Vec<u8> {
+            let mut decompressor = CustomDecompressor::<N>::new();
+            let mut output = vec![0; 1 << 20];
+            let (_, produced) = decompressor.read(input, &mut output, 0, true).unwrap();
+            assert!(decompressor.is_done());
+            output.truncate(produced);
+            output
+        }
+
         let mut rng = rand::thread_rng();
-        let mut data = vec![0; 50000];
-        for _ in 0..10 {
-            for byte in &mut data {
-                *byte = rng.gen::<u8>() % 5;
-            }
-            println!("Random data: {:?}", data);
-            roundtrip_miniz_oxide(&data);
+        let mut data = vec![0; 100_000];
+        for byte in &mut data {
+            // Skewed distribution, so that the Huffman codes include some long ones.
+            *byte = (rng.gen_range(0..256u32) * rng.gen_range(0..256u32) / 256) as u8;
+        }
+
+        for compressed in [
+            miniz_oxide::deflate::compress_to_vec_zlib(&data, 6),
+            crate::compress_to_vec(&data),
+        ] {
+            assert_eq!(decompress::<256>(&compressed), data);
+            assert_eq!(decompress::<1024>(&compressed), data);
+            assert_eq!(decompress::<4096>(&compressed), data);
+            assert_eq!(decompress::<8192>(&compressed), data);
         }
     }
 
     #[test]
-    fn ignore_adler32() {
-        let mut compressed = crate::compress_to_vec(b"Hello world!");
-        let last_byte = compressed.len() - 1;
-        compressed[last_byte] = compressed[last_byte].wrapping_add(1);
+    fn small_tables() {
+        assert!(std::mem::size_of::<SmallDecompressor>() < 6 * 1024);
 
-        match decompress_to_vec(&compressed) {
-            Err(DecompressionError::WrongChecksum) => {}
-            r => panic!("expected WrongChecksum, got {:?}", r),
+        let data = b"Hello world! Small tables for small chips. ".repeat(1000);
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 9);
+        for low_memory in [false, true] {
+            let mut decompressor = AnyDecompressor::new(low_memory);
+            let mut output = vec![0; data.len() + 2];
+            let (consumed, produced) = decompressor
+                .read(&compressed, &mut output, 0, true)
+                .unwrap();
+            assert!(decompressor.is_done());
+            assert_eq!(consumed, compressed.len());
+            assert_eq!(&output[..produced], &data[..]);
         }
+    }
 
+    #[test]
+    fn lazy_tables() {
+        // Stored blocks never need any tables.
+        let stored = miniz_oxide::deflate::compress_to_vec_zlib(&[1; 1000], 0);
         let mut decompressor = Decompressor::new();
-        decompressor.ignore_adler32();
-        let mut decompressed = vec![0; 1024];
-        let decompressed_len = decompressor
+        let mut output = vec![0; 1024];
+        decompressor.read(&stored, &mut output, 0, true).unwrap();
+        assert!(decompressor.is_done());
+        assert!(decompressor
+            .compression
+            .litlen_table
+            .iter()
+            .all(|&e| e == 0));
+
+        // Tables are only built once the first symbol is decoded.
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(b"Hello world!", 1);
+        let mut decompressor = Decompressor::new();
+        decompressor
+            .read(&compressed[..3], &mut output, 0, false)
+            .unwrap();
+        assert_eq!(decompressor.current_block_type(), Some(BlockType::Fixed));
+        assert_eq!(decompressor.pending_tables, Some(BlockType::Fixed));
+        let (_, produced) = decompressor
+            .read(&compressed[3..], &mut output, 0, true)
+            .unwrap();
+        assert_eq!(decompressor.pending_tables, None);
+        assert!(decompressor.fixed_tables_built);
+        assert_eq!(&output[..produced], b"Hello world!");
+    }
+
+    #[test]
+    fn sparse_alphabet() {
+        let mut rng = rand::thread_rng();
+        let mut data = vec![0; 10_000];
+        for byte in &mut data {
+            *byte = [0, 1, 2, 255][rng.gen_range(0..4)];
+        }
+        for level in [1, 6] {
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, level);
+            assert_eq!(decompress_to_vec(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn checksum_across_chunks() {
+        let mut rng = rand::thread_rng();
+        let mut data = vec![0; 3 * CHECKSUM_CHUNK_SIZE + 123];
+        for byte in &mut data {
+            *byte = rng.gen_range(0..16);
+        }
+        let mut compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+        assert_eq!(decompress_to_vec(&compressed).unwrap(), data);
+
+        let len = compressed.len();
+        compressed[len - 1] ^= 1;
+        assert!(matches!(
+            decompress_to_vec(&compressed),
+            Err(DecompressionError::WrongChecksum)
+        ));
+    }
+
+    #[cfg(feature = "perf-stats")]
+    #[test]
+    fn perf_stats() {
+        let mut data = b"Hello world! ".repeat(1000);
+        data.extend((0..5000u32).map(|i| (i * 7 % 256) as u8));
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        let mut decompressor = Decompressor::new();
+        let mut output = vec![0; data.len() + 2];
+        decompressor
+            .read(&compressed, &mut output, 0, true)
+            .unwrap();
+        assert!(decompressor.is_done());
+
+        let stats = decompressor.perf_stats();
+        assert!(stats.fast_path_symbols > 0);
+        assert!(stats.slow_path_symbols > 0);
+        assert!(stats.refill_stalls > 0);
+        assert_eq!(
+            stats.stored_bytes + stats.fixed_bytes + stats.dynamic_bytes,
+            data.len() as u64
+        );
+    }
+
+    #[test]
+    fn detect_fdeflate_stream() {
+        let data = b"Hello world! ".repeat(100);
+        assert!(is_fdeflate_stream(&crate::compress_to_vec(&data)));
+        assert!(is_fdeflate_stream(&crate::compress_to_vec(&[])));
+        assert!(!is_fdeflate_stream(&crate::compress_to_vec(&data)[..10]));
+        for level in [0, 1, 6] {
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, level);
+            assert!(!is_fdeflate_stream(&compressed));
+        }
+    }
+
+    #[test]
+    fn fdeflate_stream() {
+        let mut rng = rand::thread_rng();
+        let mut data = vec![0; 200_000];
+        for (i, byte) in data.iter_mut().enumerate() {
+            if (i / 1000) % 3 != 0 {
+                *byte = rng.gen_range(0..8);
+            } else if i % 700 < 300 {
+                *byte = 5;
+            }
+        }
+        let compressed = crate::compress_to_vec(&data);
+        assert!(is_fdeflate_stream(&compressed));
+        assert_eq!(decompress_to_vec(&compressed).unwrap(), data);
+
+        // Small output buffers split runs and pairs of literals between calls.
+        let mut decompressor = Decompressor::new();
+        decompressor.set_max_output_per_call(300);
+        let mut output = vec![0; data.len() + 2];
+        let mut input_index = 0;
+        let mut output_index = 0;
+        while !decompressor.is_done() {
+            let (consumed, produced) = decompressor
+                .read(&compressed[input_index..], &mut output, output_index, true)
+                .unwrap();
+            input_index += consumed;
+            output_index += produced;
+        }
+        assert_eq!(&output[..output_index], &data[..]);
+    }
+
+    #[test]
+    fn chunked_input() {
+        let mut rng = rand::thread_rng();
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i % 251 / 3) as u8).collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        for _ in 0..20 {
+            let mut chunks = vec![&compressed[..1]];
+            let mut start = 1;
+            while start < compressed.len() {
+                let end = (start + rng.gen_range(1..2000)).min(compressed.len());
+                chunks.push(&compressed[start..end]);
+                start = end;
+            }
+            assert_eq!(decompress_chunks_to_vec(chunks).unwrap(), data);
+        }
+
+        let bytes: Vec<&[u8]> = compressed.chunks(1).collect();
+        assert_eq!(decompress_chunks_to_vec(bytes).unwrap(), data);
+
+        assert!(matches!(
+            decompress_chunks_to_vec(vec![&compressed[..100]]),
+            Err(DecompressionError::InsufficientInput)
+        ));
+    }
+
+    #[test]
+    fn decompress_batch() {
+        let data: Vec<Vec<u8>> = (0..20u32)
+            .map(|i| (0..i * 997).map(|j| (j * i % 251) as u8).collect())
+            .collect();
+        let mut inputs: Vec<Vec<u8>> = data.iter().map(|d| crate::compress_to_vec(d)).collect();
+        inputs[5].pop();
+        inputs[7][2] = 0xff;
+
+        let results = decompress_all(&inputs);
+        assert_eq!(results.len(), inputs.len());
+        for (i, result) in results.iter().enumerate() {
+            match i {
+                5 | 7 => assert!(result.is_err()),
+                _ => assert_eq!(result.as_ref().unwrap(), &data[i]),
+            }
+        }
+
+        let mut indices = Vec::new();
+        decompress_each(&[&inputs[19][..], &inputs[1][..]], |i, result| {
+            indices.push(i);
+            assert_eq!(result.unwrap(), &data[[19, 1][i]][..]);
+        });
+        assert_eq!(indices, [0, 1]);
+    }
+
+    #[test]
+    fn scratch() {
+        // Long codes that need the secondary table of the small decompressor.
+        let mut rng = rand::thread_rng();
+        let data: Vec<u8> = (0..50_000)
+            .map(|_| rng.gen::<u8>() & rng.gen::<u8>() & rng.gen::<u8>())
+            .collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+        let mut decompressed = vec![0; data.len() + 2];
+
+        let scratch = DecompressorScratch::preallocated();
+        let allocated = scratch.allocated_bytes();
+        let mut decompressor = SmallDecompressor::with_scratch(scratch);
+        let (_, produced) = decompressor
             .read(&compressed, &mut decompressed, 0, true)
-            .unwrap()
-            .1;
-        assert_eq!(&decompressed[..decompressed_len], b"Hello world!");
+            .unwrap();
+        assert_eq!(&decompressed[..produced], &data[..]);
+        assert!(!decompressor.compression.secondary_table.is_empty());
+
+        let scratch = decompressor.take_scratch();
+        assert_eq!(scratch.allocated_bytes(), allocated);
+        assert!(decompressor.compression.secondary_table.is_empty());
+        assert_eq!(scratch.into_vec().capacity(), 1 << 15);
+    }
+
+    #[test]
+    fn read_exact() {
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|i| (i / 7 * (i % 13) % 256) as u8)
+            .collect();
+        for compressed in [
+            crate::compress_to_vec(&data),
+            miniz_oxide::deflate::compress_to_vec_zlib(&data, 6),
+        ] {
+            let mut decompressor = Decompressor::new();
+            let mut output = vec![0; data.len() + 2];
+            let mut position = 0;
+            let mut input_index = 0;
+            let mut chunk_end = 0;
+            let mut output_end = 0;
+            loop {
+                let end_of_input = chunk_end == compressed.len();
+                let mut chunk = &compressed[input_index..chunk_end];
+                let status = decompressor
+                    .read_exact(
+                        &mut chunk,
+                        &mut output[..output_end],
+                        &mut position,
+                        end_of_input,
+                    )
+                    .unwrap();
+                input_index = chunk_end - chunk.len();
+                match status {
+                    ReadStatus::StreamEnd => break,
+                    ReadStatus::OutputFull => {
+                        assert_eq!(position, output_end);
+                        output_end = (output_end + 3001).min(output.len());
+                    }
+                    ReadStatus::NeedsInput => {
+                        assert!(chunk.is_empty() && !end_of_input);
+                        chunk_end = (chunk_end + 777).min(compressed.len());
+                    }
+                }
+            }
+            assert_eq!(&output[..position], &data[..]);
+        }
+    }
+
+    #[test]
+    fn to_vec_growth() {
+        for data in [
+            vec![],
+            vec![42],
+            vec![0; 5_000_000],
+            (0..300_000u32).map(|i| (i * 31 % 253) as u8).collect(),
+        ] {
+            let compressed = crate::compress_to_vec(&data);
+            assert_eq!(decompress_to_vec(&compressed).unwrap(), data);
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 9);
+            assert_eq!(decompress_to_vec(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn progress_callback() {
+        use std::sync::{Arc, Mutex};
+
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 253 / 5) as u8).collect();
+        let (compressed, _) = compress_with_sync_flushes(&[&data[..100_000], &data[100_000..]]);
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let mut decompressor = Decompressor::new();
+        let reports2 = Arc::clone(&reports);
+        decompressor.set_progress_callback(50_000, move |input, output| {
+            reports2.lock().unwrap().push((input, output));
+        });
+        let mut output = vec![0; data.len() + 2];
+        decompressor
+            .read(&compressed, &mut output, 0, true)
+            .unwrap();
+        assert!(decompressor.is_done());
+
+        // A single call produces several reports, that never go backwards.
+        let reports = reports.lock().unwrap();
+        assert!(reports.len() >= 6);
+        assert!(reports
+            .windows(2)
+            .all(|w| w[0].0 <= w[1].0 && w[0].1 <= w[1].1));
+        assert_eq!(
+            *reports.last().unwrap(),
+            (compressed.len() as u64, data.len() as u64)
+        );
+    }
+
+    #[test]
+    fn cancellation() {
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 253 / 5) as u8).collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut decompressor = Decompressor::new();
+        decompressor.set_cancellation_flag(Arc::clone(&flag));
+        let flag2 = Arc::clone(&flag);
+        decompressor.set_progress_callback(100_000, move |_, output| {
+            if output >= 300_000 {
+                flag2.store(true, Ordering::Relaxed);
+            }
+        });
+
+        let mut output = vec![0; data.len() + 2];
+        assert!(matches!(
+            decompressor.read(&compressed, &mut output, 0, true),
+            Err(DecompressionError::Cancelled)
+        ));
+        assert!(output[400_000..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn move_between_threads() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+
+        let mut decompressor = Decompressor::new();
+        let mut output = vec![0; data.len()];
+        let (in1, out1) = decompressor.read(first, &mut output, 0, false).unwrap();
+        assert_eq!(in1, first.len());
+
+        let second = second.to_vec();
+        let (decompressor, output, out2) = std::thread::spawn(move || {
+            let (_, out2) = decompressor.read(&second, &mut output, out1, true).unwrap();
+            (decompressor, output, out2)
+        })
+        .join()
+        .unwrap();
+        assert_eq!(out1 + out2, data.len());
+        assert!(decompressor.is_done());
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn regression_corpus() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/regression");
+        let mut count = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().map_or(true, |ext| ext != "zlib") {
+                continue;
+            }
+            let compressed = std::fs::read(&path).unwrap();
+            let expected = std::fs::read(path.with_extension("raw")).unwrap();
+
+            let output = decompress_to_vec(&compressed).unwrap();
+            assert!(output == expected, "{}", path.display());
+
+            // Also feed the input in small pieces to exercise the slow paths.
+            let mut decompressor = Decompressor::new();
+            let mut output = vec![0; expected.len() + 2];
+            let mut position = 0;
+            let mut input = &compressed[..];
+            while !decompressor.is_done() {
+                let len = input.len().min(7);
+                let (consumed, produced) = decompressor
+                    .read(&input[..len], &mut output, position, len == input.len())
+                    .unwrap();
+                input = &input[consumed..];
+                position += produced;
+            }
+            assert!(output[..position] == expected[..], "{}", path.display());
+            count += 1;
+        }
+        assert!(count >= 30);
+    }
+
+    #[test]
+    fn exact_size() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 13) as u8).collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        let output = decompress_exact(&compressed, data.len()).unwrap();
+        assert_eq!(output.data, data);
+        assert_eq!(output.compressed_len, compressed.len());
+        assert!(!output.trailing_data);
+
+        let mut with_trailer = compressed.clone();
+        with_trailer.extend_from_slice(b"IEND");
+        let output = decompress_exact(&with_trailer, data.len()).unwrap();
+        assert_eq!(output.compressed_len, compressed.len());
+        assert!(output.trailing_data);
+
+        for len in [0, data.len() - 1, data.len() + 1, usize::MAX] {
+            assert!(matches!(
+                decompress_exact(&compressed, len),
+                Err(DecompressionError::OutputSizeMismatch)
+            ));
+        }
+    }
+
+    #[test]
+    fn block_summary() {
+        let mut compressed = vec![0x78, 0x01];
+        // Stored block with 5 bytes.
+        compressed.extend_from_slice(&[0b000, 5, 0, !5, !0]);
+        compressed.extend_from_slice(b"hello");
+        // Final fixed block with "a": BFINAL, BTYPE=01, literal 0x61 (8 bits), end of block (7 bits).
+        let mut bits: u32 = 0b011;
+        bits |= ((0x30 + 0x61u32).reverse_bits() >> 24) << 3;
+        compressed.extend_from_slice(&bits.to_le_bytes()[..3]);
+        compressed.extend_from_slice(&simd_adler32::adler32(&&b"helloa"[..]).to_be_bytes());
+
+        let mut decompressor = Decompressor::new();
+        let mut output = [0; 16];
+        let (_, produced) = decompressor
+            .read(&compressed, &mut output, 0, true)
+            .unwrap();
+        assert_eq!(&output[..produced], b"helloa");
+
+        let summary = decompressor.block_summary();
+        assert_eq!(
+            summary.stored,
+            BlockTypeSummary {
+                count: 1,
+                compressed_bits: 80,
+                uncompressed_bytes: 5,
+            }
+        );
+        assert_eq!(
+            summary.fixed,
+            BlockTypeSummary {
+                count: 1,
+                compressed_bits: 18,
+                uncompressed_bytes: 1,
+            }
+        );
+        assert_eq!(summary.dynamic, BlockTypeSummary::default());
+
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 7) as u8).collect();
+        let compressed = crate::compress_to_vec(&data);
+        let mut decompressor = Decompressor::new();
+        let mut output = vec![0; data.len() + 2];
+        decompressor
+            .read(&compressed, &mut output, 0, true)
+            .unwrap();
+        let summary = decompressor.block_summary();
+        assert_eq!(summary.dynamic.count, 1);
+        assert_eq!(summary.dynamic.uncompressed_bytes, data.len() as u64);
+        // The dynamic block is followed by the 10-bit final empty block, padding and checksum.
+        let total_bits = compressed.len() as u64 * 8;
+        assert!(summary.dynamic.compressed_bits <= total_bits - 16 - 10 - 32);
+        assert!(summary.dynamic.compressed_bits > total_bits - 16 - 10 - 32 - 8);
+        assert_eq!(summary.fixed.count, 1);
+        assert_eq!(summary.fixed.compressed_bits, 10);
+    }
+
+    #[test]
+    fn decompress_against_base() {
+        let base: Vec<u8> = (0..100_000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 7) as u8)
+            .collect();
+        let mut new = base[60_000..].to_vec();
+        new.extend_from_slice(&base[80_000..90_000]);
+        new.extend((0..50_000u32).map(|i| (i % 13) as u8));
+
+        for (base, new) in [
+            (&base[..], &new[..]),
+            (&[][..], &new[..]),
+            (&base[..], &[][..]),
+        ] {
+            let compressed = crate::compress_with_base(base, new);
+            assert_eq!(decompress_with_base(base, &compressed).unwrap(), new);
+        }
+
+        let compressed = crate::compress_with_base(&base, &new);
+        assert!(compressed.len() < new.len() / 2);
+        assert!(matches!(
+            decompress_with_base(&base[1..], &compressed),
+            Err(DecompressionError::BadZlibHeader)
+        ));
+        assert!(matches!(
+            decompress_to_vec(&compressed),
+            Err(DecompressionError::BadZlibHeader)
+        ));
+
+        let plain = crate::compress_to_vec(&new);
+        assert_eq!(decompress_with_base(&base, &plain).unwrap(), new);
+    }
+
+    #[test]
+    fn history_before_output_position() {
+        // A fixed Huffman block holding a single match of length 5 at distance 7, which reaches
+        // into history supplied by the caller rather than decoded from the stream.
+        let mut writer = crate::BlockWriter::new(Vec::new()).unwrap();
+        writer.write_bits(0b011, 3).unwrap();
+        writer.write_bits(0b110_0000, 7).unwrap(); // length symbol 259
+        writer.write_bits(0b1_0100, 5).unwrap(); // distance symbol 5
+        writer.write_bits(0, 1).unwrap(); // distance extra bit
+        writer.write_bits(0, 7).unwrap(); // end of block
+        writer.update_checksum(b"Hello");
+        let compressed = writer.finish().unwrap();
+
+        let mut output = b"Hello, \0\0\0\0\0\0\0".to_vec();
+        let (_, produced) = Decompressor::new()
+            .read(&compressed, &mut output, 7, true)
+            .unwrap();
+        assert_eq!(&output[..7 + produced], b"Hello, Hello");
+
+        // Decode into a rolling window, moving the last 32 KiB to the front whenever it fills up.
+        let data: Vec<u8> = (0..200_000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 5000) as u8)
+            .collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+        let mut decompressor = Decompressor::new();
+        let mut window = vec![0; 40_000];
+        let mut position = 0;
+        let mut input = &compressed[..];
+        let mut decompressed = Vec::new();
+        while !decompressor.is_done() {
+            if position + 2 > window.len() {
+                window.copy_within(position - 32768..position, 0);
+                window[32768..].fill(0);
+                position = 32768;
+            }
+            let (consumed, produced) = decompressor
+                .read(input, &mut window, position, true)
+                .unwrap();
+            decompressed.extend_from_slice(&window[position..position + produced]);
+            input = &input[consumed..];
+            position += produced;
+        }
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn window_at_flush_point() {
+        let data: Vec<u8> = (0..80_000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 3000) as u8)
+            .collect();
+        let (compressed, flush_offsets) =
+            compress_with_sync_flushes(&[&data[..50_000], &data[50_000..]]);
+
+        let mut decompressor = Decompressor::new();
+        let mut output = vec![0; data.len() + 2];
+        let (consumed, produced) = decompressor
+            .read(&compressed[..flush_offsets[0]], &mut output, 0, false)
+            .unwrap();
+        assert_eq!(consumed, flush_offsets[0]);
+        assert_eq!(produced, 50_000);
+        assert_eq!(decompressor.byte_position(), flush_offsets[0] as u64);
+        assert_eq!(
+            decompressor.window(&output, produced),
+            Some(&data[50_000 - 32768..50_000])
+        );
+        assert_eq!(
+            decompressor.window(&output[30_000..], produced - 30_000),
+            None
+        );
+
+        let mut decompressor = Decompressor::new();
+        decompressor
+            .read(&compressed[..flush_offsets[0] - 100], &mut output, 0, false)
+            .unwrap();
+        assert_eq!(decompressor.window(&output, 10_000), None);
+    }
+
+    #[test]
+    fn continue_from_window() {
+        let data: Vec<u8> = (0..80_000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 3000) as u8)
+            .collect();
+        let (compressed, flush_offsets) =
+            compress_with_sync_flushes(&[&data[..50_000], &data[50_000..]]);
+
+        let mut decompressor = Decompressor::new();
+        let mut output = vec![0; data.len() + 2];
+        let (consumed, produced) = decompressor
+            .read(&compressed[..flush_offsets[0]], &mut output, 0, false)
+            .unwrap();
+        let window = decompressor.window(&output, produced).unwrap();
+
+        let mut continued = vec![0; 32768 + 30_000 + 2];
+        let (mut decompressor, position) = Decompressor::with_window(window, &mut continued);
+        assert_eq!(position, 32768);
+        let (_, produced) = decompressor
+            .read(&compressed[consumed..], &mut continued, position, true)
+            .unwrap();
+        assert!(decompressor.is_done());
+        assert_eq!(&continued[position..][..produced], &data[50_000..]);
+    }
+
+    #[test]
+    fn repair_checksum() {
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i % 13) as u8).collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        let mut corrupted = compressed.clone();
+        let len = corrupted.len();
+        corrupted[len - 3] ^= 0x55;
+        corrupted.extend_from_slice(b"trailing");
+        assert!(matches!(
+            decompress_to_vec(&corrupted),
+            Err(DecompressionError::WrongChecksum)
+        ));
+
+        let repaired = repair_zlib_trailer(&corrupted).unwrap();
+        assert_eq!(&repaired[..len], &compressed[..]);
+        assert_eq!(&repaired[len..], b"trailing");
+    }
+
+    #[test]
+    fn output_hasher() {
+        #[derive(Default)]
+        struct Collect(Vec<u8>);
+        impl Update for Collect {
+            fn update(&mut self, data: &[u8]) {
+                self.0.extend_from_slice(data);
+            }
+        }
+        struct Count(usize);
+        impl Update for Count {
+            fn update(&mut self, data: &[u8]) {
+                self.0 += data.len();
+            }
+        }
+
+        let data = crate::test_utils::test_data(100_000);
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        // Use small output windows so that back-references get split across calls.
+        let mut decompressor = Decompressor::new();
+        decompressor.set_output_hasher(Collect::default());
+        let mut output = vec![0; data.len() + 2];
+        let mut input = &compressed[..];
+        let mut position = 0;
+        while !decompressor.is_done() {
+            let end = output.len().min(position + 3000);
+            let (consumed, produced) = decompressor
+                .read(input, &mut output[..end], position, true)
+                .unwrap();
+            input = &input[consumed..];
+            position += produced;
+        }
+
+        assert!(decompressor.take_output_hasher::<Count>().is_none());
+        let collected = decompressor.take_output_hasher::<Collect>().unwrap();
+        assert_eq!(collected.0, data);
     }
 }
@@ -0,0 +1,343 @@
+//! Path-based helpers for compressing and decompressing whole files.
+
+use std::fs::{self, File};
+#[cfg(feature = "compress")]
+use std::io::BufReader;
+#[cfg(feature = "decompress")]
+use std::io::Read;
+use std::io::{self, BufRead, BufWriter};
+use std::path::Path;
+
+use crate::GzipHeader;
+#[cfg(feature = "compress")]
+use crate::GzipWriter;
+#[cfg(feature = "decompress")]
+use crate::{Crc32, DecompressReader, DecompressionWarning, Decompressor};
+
+/// Write a file by writing a temporary file next to it and renaming it into place, so that
+/// `path` never holds partial output.
+fn write_atomically<T>(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<T>,
+) -> io::Result<T> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = File::create(&temp_path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        let value = write(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        Ok(value)
+    });
+    match result.and_then(|value| fs::rename(&temp_path, path).map(|()| value)) {
+        Ok(value) => Ok(value),
+        Err(err) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(err)
+        }
+    }
+}
+
+/// Compress the file at `input` into a gzip file at `output`.
+///
+/// The header records the name and modification time of the input file. The output is written
+/// to a temporary file in the same directory which replaces `output` once it is complete, so
+/// `output` is never left holding a partial file. Returns the number of bytes compressed.
+#[cfg(feature = "compress")]
+pub fn compress_file<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> io::Result<u64> {
+    let input = input.as_ref();
+    let file = File::open(input)?;
+    let mtime = file
+        .metadata()?
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_secs().min(u32::MAX as u64) as u32);
+    let header = GzipHeader {
+        mtime,
+        name: input
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.as_bytes().to_vec()),
+        comment: None,
+    };
+
+    let mut reader = BufReader::with_capacity(256 * 1024, file);
+    write_atomically(output.as_ref(), |writer| {
+        let mut gzip = GzipWriter::new(writer, &header)?;
+        let mut total = 0;
+        loop {
+            let data = reader.fill_buf()?;
+            if data.is_empty() {
+                break;
+            }
+            gzip.write_data(data)?;
+            let len = data.len();
+            reader.consume(len);
+            total += len as u64;
+        }
+        gzip.finish()?;
+        Ok(total)
+    })
+}
+
+/// Decompress the gzip or zlib file at `input` into a file at `output`.
+///
+/// The format is detected from the start of the file, as described for [`GzFile`]. Like
+/// [`compress_file`], the output replaces `output` only once it is complete. Returns the number
+/// of decompressed bytes.
+#[cfg(feature = "decompress")]
+pub fn decompress_file<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> io::Result<u64> {
+    let mut file = GzFile::open(input)?;
+    write_atomically(output.as_ref(), |writer| io::copy(&mut file, writer))
+}
+
+/// A gzip or zlib file opened for reading its decompressed contents.
+///
+/// Files starting with the gzip magic bytes are decoded as gzip, checking the CRC-32 and length
+/// of every member, and anything else as a zlib stream. As with the `gzip` tool, data after the
+/// last gzip member that isn't another member is ignored. In [lenient](GzFile::set_lenient)
+/// mode, a gzip trailer that doesn't match the data is reported as a warning instead.
+///
+/// ```no_run
+/// # use std::io::Read;
+/// let mut contents = String::new();
+/// fdeflate::GzFile::open("notes.txt.gz")?.read_to_string(&mut contents)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(feature = "decompress")]
+pub struct GzFile {
+    reader: DecompressReader<File>,
+    header: Option<GzipHeader>,
+    crc: Crc32,
+    size: u32,
+    done: bool,
+    warnings: Vec<DecompressionWarning>,
+}
+
+#[cfg(feature = "decompress")]
+impl GzFile {
+    /// Open the file at `path`, reading its header.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut reader = DecompressReader::new(File::open(path)?);
+        let mut input = reader.remaining_input();
+        let header = if input.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+            let (header, _) = GzipHeader::read_from(&mut input)?;
+            reader.restart(Box::new(Decompressor::new_raw()));
+            Some(header)
+        } else {
+            None
+        };
+        Ok(Self {
+            reader,
+            header,
+            crc: Crc32::new(),
+            size: 0,
+            done: false,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// Decode leniently, as described for [`Decompressor::set_lenient`].
+    ///
+    /// A wrong CRC-32 or length in the trailer of a gzip member is recorded as a
+    /// [warning](GzFile::warnings) rather than failing the read.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.reader.decompressor_mut().set_lenient(lenient);
+    }
+
+    /// Returns the warnings encountered so far in [lenient](GzFile::set_lenient) mode, across
+    /// all members of a gzip file.
+    pub fn warnings(&self) -> &[DecompressionWarning] {
+        match self.header {
+            Some(_) => &self.warnings,
+            None => self.reader.decompressor().warnings(),
+        }
+    }
+
+    /// Record `warning` in lenient mode, or fail with `message` otherwise.
+    fn soft_error(&mut self, warning: DecompressionWarning, message: &str) -> io::Result<()> {
+        if !self.reader.decompressor().is_lenient() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+        Ok(())
+    }
+
+    /// Returns the header of the first member, or `None` for a zlib file.
+    pub fn header(&self) -> Option<&GzipHeader> {
+        self.header.as_ref()
+    }
+
+    /// Check the trailer of the member that just ended, and start decoding the next one if
+    /// there is any.
+    fn next_member(&mut self) -> io::Result<()> {
+        let mut trailer = [0; 8];
+        self.reader.remaining_input().read_exact(&mut trailer)?;
+        if trailer[..4] != self.crc.finish().to_le_bytes() {
+            self.soft_error(
+                DecompressionWarning::WrongChecksum,
+                "gzip member has the wrong checksum",
+            )?;
+        }
+        if trailer[4..] != self.size.to_le_bytes() {
+            self.soft_error(
+                DecompressionWarning::WrongLength,
+                "gzip member has the wrong length",
+            )?;
+        }
+
+        let mut rest = self.reader.remaining_input();
+        let mut magic = [0; 2];
+        let another_member = rest.read(&mut magic[..1])? == 1
+            && rest.read(&mut magic[1..])? == 1
+            && magic == [0x1f, 0x8b];
+        if another_member {
+            GzipHeader::read_from(&mut (&magic[..]).chain(rest))?;
+        }
+
+        self.done = !another_member;
+        if another_member {
+            let mut decompressor = Decompressor::new_raw();
+            decompressor.set_lenient(self.reader.decompressor().is_lenient());
+            self.reader.restart(Box::new(decompressor));
+            self.crc = Crc32::new();
+            self.size = 0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "decompress")]
+impl Read for GzFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "decompress")]
+impl BufRead for GzFile {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // The decompressed data is checked as it is consumed, so a member's data has all been
+        // checked once its stream is done and nothing is buffered.
+        while !self.done && self.reader.fill_buf()?.is_empty() {
+            if self.header.is_none() {
+                self.done = true;
+            } else {
+                self.next_member()?;
+            }
+        }
+        Ok(self.reader.buffered())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let buffered = self.reader.buffered();
+        let consumed = &buffered[..amt.min(buffered.len())];
+        self.crc.write(consumed);
+        self.size = self.size.wrapping_add(consumed.len() as u32);
+        self.reader.consume(amt);
+    }
+}
+
+#[cfg(all(test, feature = "compress", feature = "decompress"))]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fdeflate-gzfile-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn compress_and_decompress_file() {
+        let data = crate::test_utils::test_data(300_000);
+        let (original, compressed, decompressed) = (
+            temp_path("data"),
+            temp_path("data.gz"),
+            temp_path("data.out"),
+        );
+        fs::write(&original, &data).unwrap();
+
+        assert_eq!(
+            compress_file(&original, &compressed).unwrap(),
+            data.len() as u64
+        );
+        let file = GzFile::open(&compressed).unwrap();
+        let name = file.header().unwrap().name.clone().unwrap();
+        assert!(original
+            .to_str()
+            .unwrap()
+            .ends_with(std::str::from_utf8(&name).unwrap()));
+
+        assert_eq!(
+            decompress_file(&compressed, &decompressed).unwrap(),
+            data.len() as u64
+        );
+        assert!(fs::read(&decompressed).unwrap() == data);
+
+        // Zlib files are detected too.
+        fs::write(&compressed, crate::compress_to_vec(&data)).unwrap();
+        let mut output = Vec::new();
+        GzFile::open(&compressed)
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert!(output == data);
+
+        for path in [original, compressed, decompressed] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn multiple_members() {
+        let path = temp_path("members.gz");
+        let mut file = Vec::new();
+        for part in [&b"first "[..], b"", b"second"] {
+            let mut writer = GzipWriter::new(&mut file, &GzipHeader::default()).unwrap();
+            writer.write_data(part).unwrap();
+            writer.finish().unwrap();
+        }
+        file.extend_from_slice(&[0; 16]);
+        fs::write(&path, &file).unwrap();
+
+        let mut output = Vec::new();
+        GzFile::open(&path)
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, b"first second");
+
+        // Corrupt the length in the trailer of the first member.
+        let first_trailer = file
+            .windows(4)
+            .position(|w| w == 6u32.to_le_bytes())
+            .unwrap();
+        file[first_trailer] = 7;
+        fs::write(&path, &file).unwrap();
+        let err = GzFile::open(&path)
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // In lenient mode, all of the data is still decoded and the wrong length is reported.
+        let mut gz = GzFile::open(&path).unwrap();
+        gz.set_lenient(true);
+        let mut output = Vec::new();
+        gz.read_to_end(&mut output).unwrap();
+        assert_eq!(output, b"first second");
+        assert_eq!(gz.warnings(), &[DecompressionWarning::WrongLength]);
+        fs::remove_file(path).unwrap();
+    }
+}
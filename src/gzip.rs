@@ -0,0 +1,364 @@
+//! Helpers for the gzip file format.
+//!
+//! A gzip file is a sequence of members, each made of a header, a raw deflate stream and a
+//! trailer holding the CRC-32 and length (modulo 2^32) of the decompressed data.
+
+#[cfg(feature = "compress")]
+use std::fs::{File, OpenOptions};
+#[cfg(feature = "compress")]
+use std::io::Write;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+#[cfg(feature = "compress")]
+use std::path::Path;
+
+#[cfg(feature = "compress")]
+use crate::Compressor;
+#[cfg(any(feature = "compress", feature = "decompress"))]
+use crate::Crc32;
+#[cfg(feature = "decompress")]
+use crate::{Decompressor, RECOMMENDED_OUTPUT_BUFFER_SIZE};
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// A complete gzip member with no contents.
+///
+/// It has a header without optional fields or modification time, a single final fixed Huffman
+/// block holding only the end of block symbol, and a trailer for the empty input.
+pub const EMPTY_GZIP_MEMBER: [u8; 20] = [
+    0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255, 0x03, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+/// Optional fields of a gzip member header.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GzipHeader {
+    /// Modification time of the original file, in seconds since the Unix epoch, or 0 if unknown.
+    pub mtime: u32,
+    /// Name of the original file, without the terminating zero byte.
+    pub name: Option<Vec<u8>>,
+    /// Free-form comment, without the terminating zero byte.
+    pub comment: Option<Vec<u8>>,
+}
+
+impl GzipHeader {
+    #[cfg(feature = "compress")]
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut flags = 0;
+        for (field, flag) in [(&self.name, FNAME), (&self.comment, FCOMMENT)] {
+            if let Some(field) = field {
+                if field.contains(&0) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "gzip header fields can't contain zero bytes",
+                    ));
+                }
+                flags |= flag;
+            }
+        }
+
+        let mut header = vec![0x1f, 0x8b, 8, flags];
+        header.extend_from_slice(&self.mtime.to_le_bytes());
+        header.extend_from_slice(&[0, 255]); // XFL, OS = unknown
+        for field in [&self.name, &self.comment].iter().copied().flatten() {
+            header.extend_from_slice(field);
+            header.push(0);
+        }
+        writer.write_all(&header)
+    }
+
+    /// Parse a member header, returning it along with its length in bytes.
+    pub(crate) fn read_from<R: BufRead>(reader: &mut R) -> io::Result<(Self, u64)> {
+        let mut fixed = [0; 10];
+        reader.read_exact(&mut fixed)?;
+        if fixed[..3] != [0x1f, 0x8b, 8] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a gzip member header",
+            ));
+        }
+        let flags = fixed[3];
+        let mut header = GzipHeader {
+            mtime: u32::from_le_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]),
+            name: None,
+            comment: None,
+        };
+        let mut len = fixed.len() as u64;
+
+        if flags & FEXTRA != 0 {
+            let mut xlen = [0; 2];
+            reader.read_exact(&mut xlen)?;
+            let xlen = u16::from_le_bytes(xlen) as u64;
+            if io::copy(&mut reader.take(xlen), &mut io::sink())? != xlen {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            len += 2 + xlen;
+        }
+        for (field, flag) in [(&mut header.name, FNAME), (&mut header.comment, FCOMMENT)] {
+            if flags & flag != 0 {
+                let mut value = Vec::new();
+                reader.read_until(0, &mut value)?;
+                if value.pop() != Some(0) {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                len += value.len() as u64 + 1;
+                *field = Some(value);
+            }
+        }
+        if flags & FHCRC != 0 {
+            reader.read_exact(&mut [0; 2])?;
+            len += 2;
+        }
+        Ok((header, len))
+    }
+}
+
+/// Metadata of a gzip file, as returned by [`probe_gzip`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzipInfo {
+    /// The header of the first member.
+    pub header: GzipHeader,
+    /// The CRC-32 of the decompressed data, from the trailer.
+    pub crc32: u32,
+    /// The length of the decompressed data modulo 2^32, from the trailer.
+    pub size: u32,
+    /// The length of the whole file.
+    pub compressed_size: u64,
+}
+
+/// Read the metadata of a gzip file without decompressing it.
+///
+/// Only the header at the start and the trailer at the end are read; the compressed data in
+/// between is skipped by seeking. Like `gzip --list`, this assumes the file holds a single
+/// member: for files with several members, the header is that of the first one and the trailer
+/// that of the last one.
+pub fn probe_gzip<R: Read + Seek>(reader: R) -> io::Result<GzipInfo> {
+    let mut reader = io::BufReader::with_capacity(512, reader);
+    let (header, header_len) = GzipHeader::read_from(&mut reader)?;
+
+    let compressed_size = reader.seek(SeekFrom::End(0))?;
+    if compressed_size < header_len + 8 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    reader.seek(SeekFrom::End(-8))?;
+    let mut trailer = [0; 8];
+    reader.read_exact(&mut trailer)?;
+    Ok(GzipInfo {
+        header,
+        crc32: u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]),
+        size: u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]),
+        compressed_size,
+    })
+}
+
+/// Writes a single gzip member.
+///
+/// Since a gzip file may contain any number of members, which decompress to the concatenation
+/// of their contents, writing a member to the end of an existing file extends it the same way
+/// `gzip >> file` does. See [`append_gzip_member`].
+#[cfg(feature = "compress")]
+pub struct GzipWriter<W: Write> {
+    compressor: Compressor<W>,
+    crc: Crc32,
+    size: u32,
+}
+
+#[cfg(feature = "compress")]
+impl<W: Write> GzipWriter<W> {
+    /// Start a new member, writing its header to `writer`.
+    pub fn new(mut writer: W, header: &GzipHeader) -> io::Result<Self> {
+        header.write_to(&mut writer)?;
+        let compressor = Compressor::new_raw(writer)?;
+        Ok(Self {
+            compressor,
+            crc: Crc32::new(),
+            size: 0,
+        })
+    }
+
+    /// Write data to the member.
+    pub fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        self.crc.write(data);
+        self.size = self.size.wrapping_add(data.len() as u32);
+        self.compressor.write_data(data)
+    }
+
+    /// Write the end of the deflate stream and the trailer, and return the writer.
+    pub fn finish(self) -> io::Result<W> {
+        let mut writer = self.compressor.finish()?;
+        writer.write_all(&self.crc.finish().to_le_bytes())?;
+        writer.write_all(&self.size.to_le_bytes())?;
+        Ok(writer)
+    }
+}
+
+/// Open the gzip file at `path` for appending a new member, creating it if it doesn't exist.
+#[cfg(feature = "compress")]
+pub fn append_gzip_member<P: AsRef<Path>>(
+    path: P,
+    header: &GzipHeader,
+) -> io::Result<GzipWriter<File>> {
+    let file = OpenOptions::new().append(true).create(true).open(path)?;
+    GzipWriter::new(file, header)
+}
+
+/// Rewrite the trailer of a gzip member to match its decompressed contents.
+///
+/// The CRC-32 and length in the trailer of the first member of `input` are recomputed by
+/// decoding its compressed data, which must be intact. A copy of `input` with the corrected
+/// trailer is returned; any following members or other data are copied unchanged.
+#[cfg(feature = "decompress")]
+pub fn repair_gzip_trailer(input: &[u8]) -> io::Result<Vec<u8>> {
+    let (_, header_len) = GzipHeader::read_from(&mut &input[..])?;
+    let body = &input[header_len as usize..];
+
+    let mut output = vec![0; 1024];
+    let mut decoder = Decompressor::new_raw();
+    let mut input_index = 0;
+    let mut output_index = 0;
+    while !decoder.is_done() {
+        let (consumed, produced) =
+            decoder.read(&body[input_index..], &mut output, output_index, true)?;
+        input_index += consumed;
+        output_index += produced;
+        output.resize(output_index + RECOMMENDED_OUTPUT_BUFFER_SIZE, 0);
+    }
+
+    let trailer_start = header_len as usize + ((decoder.bit_position() + 7) / 8) as usize;
+    if input.len() < trailer_start + 8 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let mut crc = Crc32::new();
+    crc.write(&output[..output_index]);
+    let mut repaired = input.to_vec();
+    repaired[trailer_start..][..4].copy_from_slice(&crc.finish().to_le_bytes());
+    repaired[trailer_start + 4..][..4].copy_from_slice(&(output_index as u32).to_le_bytes());
+    Ok(repaired)
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use super::*;
+    use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+    use miniz_oxide::inflate::TINFLStatus;
+
+    /// Splits a gzip file into the decompressed contents of its members, checking their trailers.
+    fn read_members(mut data: &[u8]) -> Vec<Vec<u8>> {
+        let mut members = Vec::new();
+        while !data.is_empty() {
+            assert_eq!(&data[..3], &[0x1f, 0x8b, 8]);
+            let flags = data[3];
+            let mut pos = 10;
+            for flag in [FNAME, FCOMMENT] {
+                if flags & flag != 0 {
+                    pos += data[pos..].iter().position(|&b| b == 0).unwrap() + 1;
+                }
+            }
+
+            let mut contents = vec![0; 1 << 20];
+            let (status, consumed, produced) = decompress(
+                &mut DecompressorOxide::new(),
+                &data[pos..],
+                &mut contents,
+                0,
+                inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+            );
+            assert_eq!(status, TINFLStatus::Done);
+            contents.truncate(produced);
+            pos += consumed;
+
+            let mut crc = Crc32::new();
+            crc.write(&contents);
+            assert_eq!(&data[pos..pos + 4], &crc.finish().to_le_bytes());
+            assert_eq!(
+                &data[pos + 4..pos + 8],
+                &(contents.len() as u32).to_le_bytes()
+            );
+            data = &data[pos + 8..];
+            members.push(contents);
+        }
+        members
+    }
+
+    #[test]
+    fn append_members() {
+        let path = std::env::temp_dir().join(format!("fdeflate-gzip-{}.gz", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let first: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let header = GzipHeader {
+            mtime: 1_700_000_000,
+            name: Some(b"log.txt".to_vec()),
+            comment: None,
+        };
+        let mut writer = append_gzip_member(&path, &header).unwrap();
+        writer.write_data(&first).unwrap();
+        writer.finish().unwrap();
+
+        let mut writer = append_gzip_member(&path, &GzipHeader::default()).unwrap();
+        writer.write_data(b"second member").unwrap();
+        writer.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&data[4..10], &[0x00, 0xf1, 0x53, 0x65, 0, 255]);
+        assert_eq!(&data[10..18], b"log.txt\0");
+        assert_eq!(read_members(&data), [first, b"second member".to_vec()]);
+    }
+
+    #[test]
+    fn empty_member() {
+        assert_eq!(read_members(&EMPTY_GZIP_MEMBER), [Vec::new()]);
+
+        let mut writer = GzipWriter::new(Vec::new(), &GzipHeader::default()).unwrap();
+        writer.write_data(&[]).unwrap();
+        assert_eq!(read_members(&writer.finish().unwrap()), [Vec::new()]);
+    }
+
+    #[test]
+    fn probe() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let header = GzipHeader {
+            mtime: 1_700_000_000,
+            name: Some(b"data.bin".to_vec()),
+            comment: Some(b"test file".to_vec()),
+        };
+        let mut writer = GzipWriter::new(Vec::new(), &header).unwrap();
+        writer.write_data(&data).unwrap();
+        let file = writer.finish().unwrap();
+
+        let mut crc = Crc32::new();
+        crc.write(&data);
+        let info = probe_gzip(io::Cursor::new(&file)).unwrap();
+        assert_eq!(
+            info,
+            GzipInfo {
+                header,
+                crc32: crc.finish(),
+                size: data.len() as u32,
+                compressed_size: file.len() as u64,
+            }
+        );
+
+        assert!(probe_gzip(io::Cursor::new(&file[..20])).is_err());
+        assert!(probe_gzip(io::Cursor::new(&crate::compress_to_vec(&data))).is_err());
+    }
+
+    #[cfg(feature = "decompress")]
+    #[test]
+    fn repair_trailer() {
+        let mut writer = GzipWriter::new(Vec::new(), &GzipHeader::default()).unwrap();
+        writer.write_data(&[42; 10_000]).unwrap();
+        let file = writer.finish().unwrap();
+
+        let mut corrupted = file.clone();
+        let len = corrupted.len();
+        corrupted[len - 8..].copy_from_slice(&[0xff; 8]);
+        corrupted.extend_from_slice(&file);
+        let repaired = repair_gzip_trailer(&corrupted).unwrap();
+        assert_eq!(repaired, [&file[..], &file[..]].concat());
+
+        assert!(repair_gzip_trailer(&file[..len - 4]).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! Huffman codes for the literal/length alphabet used by [`Compressor`](crate::Compressor).
+
+use std::convert::TryInto;
+use std::io;
+
+use crate::tables::{HUFFMAN_CODES, HUFFMAN_LENGTHS, LENGTH_TO_SYMBOL};
+
+/// Number of symbols in the literal/length alphabet, excluding the two reserved ones.
+const NUM_SYMBOLS: usize = 286;
+
+/// Code lengths of the literal/length alphabet, along with the codes derived from them.
+///
+/// The default table is the one fdeflate was tuned with, trained on a large corpus of filtered
+/// PNG images. [`HuffmanTable::from_data`] instead builds a table from the symbol frequencies of
+/// some sample data, with code lengths limited to a given maximum.
+///
+/// ```
+/// # use fdeflate::{Compressor, HuffmanTable};
+/// let data = b"some text that isn't a PNG image".repeat(100);
+/// let table = HuffmanTable::from_data(&data, 12);
+/// let mut compressor = Compressor::with_table(Vec::new(), &table).unwrap();
+/// compressor.write_data(&data).unwrap();
+/// let compressed = compressor.finish().unwrap();
+/// # #[cfg(feature = "decompress")]
+/// # assert_eq!(fdeflate::decompress_to_vec(&compressed).unwrap(), data);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HuffmanTable {
+    pub(crate) lengths: [u8; NUM_SYMBOLS],
+    pub(crate) codes: [u16; NUM_SYMBOLS],
+}
+
+impl HuffmanTable {
+    /// Build a table suited to `data`, with no code longer than `max_code_length` bits.
+    ///
+    /// Every symbol gets a code, even those that don't appear in `data`, so the table can be
+    /// used to compress any input. Limiting the code lengths costs a little compression, but
+    /// tables with codes of at most 12 bits let the decompressor in this crate decode every
+    /// symbol with a single table lookup.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_code_length` isn't between 9 and 15. At least 9 bits are needed to give
+    /// each of the 286 symbols a code, and the deflate format doesn't allow more than 15.
+    pub fn from_data(data: &[u8], max_code_length: u8) -> Self {
+        Self::from_samples(&[data], max_code_length)
+    }
+
+    /// Build a table suited to all of `samples` together, with no code longer than
+    /// `max_code_length` bits.
+    ///
+    /// This is the same as [`HuffmanTable::from_data`] on the concatenation of the samples,
+    /// except that runs of zeros aren't joined across them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_code_length` isn't between 9 and 15.
+    pub fn from_samples(samples: &[&[u8]], max_code_length: u8) -> Self {
+        assert!((9..=15).contains(&max_code_length));
+        let mut freqs = [0u64; NUM_SYMBOLS];
+        for sample in samples {
+            add_to_histogram(&mut freqs, sample);
+        }
+        Self::from_lengths(limited_code_lengths(&freqs, max_code_length))
+    }
+
+    fn from_lengths(lengths: [u8; NUM_SYMBOLS]) -> Self {
+        let codes = crate::compute_codes(&lengths).expect("incomplete Huffman code");
+        Self { lengths, codes }
+    }
+
+    /// Create a table from code lengths previously returned by [`HuffmanTable::code_lengths`].
+    ///
+    /// This skips the histogram pass of [`HuffmanTable::from_data`], so a table chosen once can
+    /// be reused for many similar inputs, or stored and loaded later. Returns an error of kind
+    /// [`io::ErrorKind::InvalidInput`] unless there are exactly 286 lengths, each between 1 and
+    /// 15, that form a complete prefix code.
+    pub fn from_code_lengths(code_lengths: &[u8]) -> io::Result<Self> {
+        let invalid = |msg| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        let lengths: [u8; NUM_SYMBOLS] = match code_lengths.try_into() {
+            Ok(lengths) => lengths,
+            Err(_) => return invalid("a Huffman table must have 286 code lengths"),
+        };
+        if !lengths.iter().all(|len| (1..=15).contains(len)) {
+            return invalid("Huffman code lengths must be between 1 and 15");
+        }
+        match crate::compute_codes(&lengths) {
+            Some(codes) => Ok(Self { lengths, codes }),
+            None => invalid("Huffman code lengths don't form a complete prefix code"),
+        }
+    }
+
+    /// Returns the code length of each symbol of the literal/length alphabet.
+    ///
+    /// Symbols 0 to 255 are the literal bytes, 256 is the end of block symbol and 257 to 285
+    /// are the length codes. The lengths can be passed to [`HuffmanTable::from_code_lengths`]
+    /// to recreate the table.
+    pub fn code_lengths(&self) -> &[u8] {
+        &self.lengths
+    }
+
+    /// Returns the length of the longest code in the table.
+    pub fn max_code_length(&self) -> u8 {
+        *self.lengths.iter().max().unwrap()
+    }
+}
+
+impl Default for HuffmanTable {
+    fn default() -> Self {
+        Self {
+            lengths: HUFFMAN_LENGTHS,
+            codes: HUFFMAN_CODES,
+        }
+    }
+}
+
+/// Count the symbols that the compressor would emit for `data`, adding them to `freqs`.
+///
+/// Runs of zeros are counted as a literal zero followed by length codes, as
+/// [`Compressor`](crate::Compressor) encodes them. The compressor only detects runs that span
+/// 8-byte boundaries, so this is an approximation.
+fn add_to_histogram(freqs: &mut [u64; NUM_SYMBOLS], data: &[u8]) {
+    fn count_run(freqs: &mut [u64; NUM_SYMBOLS], run: usize) {
+        if run > 0 {
+            freqs[0] += 1;
+            let rest = run - 1;
+            freqs[285] += (rest / 258) as u64;
+            match rest % 258 {
+                0 => {}
+                n @ 1..=4 => freqs[0] += n as u64,
+                n => freqs[LENGTH_TO_SYMBOL[n - 3] as usize] += 1,
+            }
+        }
+    }
+
+    let mut run = 0;
+    for &b in data {
+        if b == 0 {
+            run += 1;
+        } else {
+            count_run(freqs, run);
+            run = 0;
+            freqs[b as usize] += 1;
+        }
+    }
+    count_run(freqs, run);
+    freqs[256] += 1;
+}
+
+/// Compute Huffman code lengths for `freqs` that are at most `max_length` bits long.
+///
+/// Symbols with a frequency of zero are treated as having a frequency of one, so that all of them
+/// get a code. Lengths are first computed without a limit, then any that are too long are
+/// truncated and the tree is fixed up by lengthening the codes of the least frequent symbols.
+fn limited_code_lengths(freqs: &[u64; NUM_SYMBOLS], max_length: u8) -> [u8; NUM_SYMBOLS] {
+    let n = NUM_SYMBOLS;
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by_key(|&sym| (freqs[sym].max(1), sym));
+
+    // Build the Huffman tree with the two-queue method: leaves in order of increasing weight
+    // occupy nodes 0..n, and internal nodes are created in order of increasing weight after them.
+    let mut weights: Vec<u64> = order.iter().map(|&sym| freqs[sym].max(1)).collect();
+    weights.resize(2 * n - 1, 0);
+    let mut parents = vec![0; 2 * n - 1];
+    let mut next_leaf = 0;
+    let mut next_internal = n;
+    for node in n..2 * n - 1 {
+        for _ in 0..2 {
+            let child = if next_leaf < n
+                && (next_internal == node || weights[next_leaf] <= weights[next_internal])
+            {
+                next_leaf += 1;
+                next_leaf - 1
+            } else {
+                next_internal += 1;
+                next_internal - 1
+            };
+            weights[node] += weights[child];
+            parents[child] = node;
+        }
+    }
+
+    let mut depths = vec![0u32; 2 * n - 1];
+    for node in (0..2 * n - 2).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+
+    // Truncate the lengths, measuring the Kraft sum in units of 2^-max_length.
+    let mut lengths = [0u8; NUM_SYMBOLS];
+    for (i, &sym) in order.iter().enumerate() {
+        lengths[sym] = depths[i].min(max_length as u32) as u8;
+    }
+    let target = 1u32 << max_length;
+    let kraft = |lengths: &[u8; NUM_SYMBOLS]| -> u32 {
+        lengths.iter().map(|&len| 1 << (max_length - len)).sum()
+    };
+    let mut sum = kraft(&lengths);
+
+    // Lengthen the longest codes that can still grow, starting with the least frequent symbols.
+    while sum > target {
+        let sym = *order
+            .iter()
+            .rev()
+            .filter(|&&sym| lengths[sym] < max_length)
+            .max_by_key(|&&sym| lengths[sym])
+            .unwrap();
+        lengths[sym] += 1;
+        sum -= 1 << (max_length - lengths[sym]);
+    }
+
+    // Spend any remaining slack on shortening the codes of the most frequent symbols.
+    while sum < target {
+        let sym = *order
+            .iter()
+            .rev()
+            .find(|&&sym| lengths[sym] > 1 && sum + (1 << (max_length - lengths[sym])) <= target)
+            .unwrap();
+        sum += 1 << (max_length - lengths[sym]);
+        lengths[sym] -= 1;
+    }
+    debug_assert_eq!(kraft(&lengths), target);
+
+    lengths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_and_import() {
+        let data = b"abracadabra".repeat(100);
+        let table = HuffmanTable::from_data(&data, 12);
+        let imported = HuffmanTable::from_code_lengths(table.code_lengths()).unwrap();
+        assert_eq!(imported, table);
+        assert_eq!(
+            HuffmanTable::from_code_lengths(&HUFFMAN_LENGTHS).unwrap(),
+            HuffmanTable::default()
+        );
+
+        let mut lengths = table.code_lengths().to_vec();
+        assert!(HuffmanTable::from_code_lengths(&lengths[1..]).is_err());
+        lengths[b'z' as usize] += 1;
+        let err = HuffmanTable::from_code_lengths(&lengths).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        lengths[b'z' as usize] = 0;
+        assert!(HuffmanTable::from_code_lengths(&lengths).is_err());
+    }
+
+    #[test]
+    fn limited_lengths() {
+        let data: Vec<u8> = (0..100_000u32)
+            .map(|i| (i.wrapping_mul(i) >> 7) as u8 & (i % 5) as u8)
+            .collect();
+        for max_code_length in 9..=15 {
+            let table = HuffmanTable::from_data(&data, max_code_length);
+            assert!(table.max_code_length() <= max_code_length);
+        }
+
+        // Exponentially distributed frequencies give a tree deeper than 15 levels unless limited.
+        let mut freqs = [0u64; NUM_SYMBOLS];
+        for (i, freq) in freqs.iter_mut().enumerate().take(40) {
+            *freq = 1 << (40 - i);
+        }
+        let lengths = limited_code_lengths(&freqs, 12);
+        assert!(lengths.iter().all(|&len| (1..=12).contains(&len)));
+        assert!(lengths[0] <= lengths[39]);
+        assert!(crate::compute_codes(&lengths).is_some());
+    }
+}
@@ -0,0 +1,229 @@
+//! Helper for decompressing files read with io_uring.
+//!
+//! Reads of the next chunk of the file are submitted before decompressing the current one, so
+//! that I/O latency overlaps with decompression. Submitting reads into caller-owned buffers
+//! requires unsafe code, so with the `forbid-unsafe` feature the file is read with blocking
+//! reads instead.
+#![cfg_attr(not(feature = "forbid-unsafe"), allow(unsafe_code))]
+
+use std::fs::File;
+use std::io::{self, Write};
+#[cfg(not(feature = "forbid-unsafe"))]
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+#[cfg(not(feature = "forbid-unsafe"))]
+use io_uring::{opcode, types, IoUring};
+
+use crate::windowed::OutputWindow;
+use crate::Decompressor;
+
+const CHUNK_SIZE: usize = 256 * 1024;
+
+#[cfg(not(feature = "forbid-unsafe"))]
+struct Reader {
+    ring: IoUring,
+    file: File,
+    offset: u64,
+    in_flight: bool,
+}
+
+#[cfg(not(feature = "forbid-unsafe"))]
+impl Reader {
+    /// Submits a read of the next chunk of the file into `buffer`.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must not be accessed or dropped until the read is completed by `wait`.
+    unsafe fn submit(&mut self, buffer: &mut [u8], index: u64) -> io::Result<()> {
+        let entry = opcode::Read::new(
+            types::Fd(self.file.as_raw_fd()),
+            buffer.as_mut_ptr(),
+            buffer.len() as u32,
+        )
+        .offset(self.offset)
+        .build()
+        .user_data(index);
+        self.ring
+            .submission()
+            .push(&entry)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "io_uring submission queue full"))?;
+        self.ring.submit()?;
+        self.in_flight = true;
+        Ok(())
+    }
+
+    /// Waits for the in-flight read, returning the index of its buffer and the number of bytes read.
+    fn wait(&mut self) -> io::Result<(usize, usize)> {
+        debug_assert!(self.in_flight);
+        let entry = loop {
+            if let Some(entry) = self.ring.completion().next() {
+                break entry;
+            }
+            self.ring.submit_and_wait(1)?;
+        };
+        self.in_flight = false;
+        if entry.result() < 0 {
+            return Err(io::Error::from_raw_os_error(-entry.result()));
+        }
+        self.offset += entry.result() as u64;
+        Ok((entry.user_data() as usize, entry.result() as usize))
+    }
+}
+
+#[cfg(not(feature = "forbid-unsafe"))]
+impl Drop for Reader {
+    fn drop(&mut self) {
+        // The kernel may still be writing into one of the buffers, so wait for it to finish.
+        if self.in_flight {
+            let _ = self.wait();
+        }
+    }
+}
+
+/// Decompresses the zlib stream in the file at `path`, writing the output to `writer`.
+///
+/// The file is read in chunks using io_uring, with the read of each chunk overlapping the
+/// decompression of the previous one. Returns the number of decompressed bytes.
+#[cfg(not(feature = "forbid-unsafe"))]
+pub fn decompress_file_io_uring<P: AsRef<Path>, W: Write>(
+    path: P,
+    mut writer: W,
+) -> io::Result<u64> {
+    let mut buffers = [vec![0; CHUNK_SIZE], vec![0; CHUNK_SIZE]];
+    let mut reader = Reader {
+        ring: IoUring::new(2)?,
+        file: File::open(path)?,
+        offset: 0,
+        in_flight: false,
+    };
+    let mut decoder = ChunkDecoder::new();
+
+    // SAFETY: The buffer isn't touched until the read completes, and `reader` is declared after
+    // `buffers` so it is dropped (waiting for any in-flight read) before them.
+    unsafe { reader.submit(&mut buffers[0], 0)? };
+    loop {
+        let (index, len) = reader.wait()?;
+        let end_of_input = len == 0;
+        if !end_of_input {
+            let next = 1 - index;
+            // SAFETY: See above. The previous read into this buffer has already completed.
+            unsafe { reader.submit(&mut buffers[next], next as u64)? };
+        }
+
+        decoder.decode(&buffers[index][..len], end_of_input, &mut writer)?;
+        if decoder.decompressor.is_done() || end_of_input {
+            break;
+        }
+    }
+    decoder.finish()
+}
+
+/// Decompresses the zlib stream in the file at `path`, writing the output to `writer`.
+///
+/// Since the `forbid-unsafe` feature is enabled, the file is read in chunks with blocking reads
+/// rather than with io_uring. Returns the number of decompressed bytes.
+#[cfg(feature = "forbid-unsafe")]
+pub fn decompress_file_io_uring<P: AsRef<Path>, W: Write>(
+    path: P,
+    mut writer: W,
+) -> io::Result<u64> {
+    use std::io::Read;
+
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut file = File::open(path)?;
+    let mut decoder = ChunkDecoder::new();
+    loop {
+        let len = match file.read(&mut buffer) {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let end_of_input = len == 0;
+        decoder.decode(&buffer[..len], end_of_input, &mut writer)?;
+        if decoder.decompressor.is_done() || end_of_input {
+            break;
+        }
+    }
+    decoder.finish()
+}
+
+/// Feeds chunks of the file to a decompressor, holding on to any input it couldn't consume yet.
+struct ChunkDecoder {
+    decompressor: Decompressor,
+    output: OutputWindow,
+    pending: Vec<u8>,
+    total_output: u64,
+}
+
+impl ChunkDecoder {
+    fn new() -> Self {
+        Self {
+            decompressor: Decompressor::new(),
+            output: OutputWindow::new(),
+            pending: Vec::new(),
+            total_output: 0,
+        }
+    }
+
+    fn decode<W: Write>(
+        &mut self,
+        chunk: &[u8],
+        end_of_input: bool,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        // Decode directly from the chunk when possible, and only copy any input the decoder
+        // couldn't consume yet.
+        if self.pending.is_empty() {
+            let (consumed, produced) =
+                self.output
+                    .decode_to(&mut self.decompressor, chunk, end_of_input, writer)?;
+            self.pending.extend_from_slice(&chunk[consumed..]);
+            self.total_output += produced;
+        } else {
+            self.pending.extend_from_slice(chunk);
+            let (consumed, produced) = self.output.decode_to(
+                &mut self.decompressor,
+                &self.pending,
+                end_of_input,
+                writer,
+            )?;
+            self.pending.drain(..consumed);
+            self.total_output += produced;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<u64> {
+        if !self.decompressor.is_done() {
+            return Err(crate::DecompressionError::InsufficientInput.into());
+        }
+        Ok(self.total_output)
+    }
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let data = crate::test_utils::test_data(1_000_000);
+        let path =
+            std::env::temp_dir().join(format!("fdeflate-io-uring-{}.zlib", std::process::id()));
+        std::fs::write(&path, miniz_oxide::deflate::compress_to_vec_zlib(&data, 6)).unwrap();
+
+        let mut output = Vec::new();
+        let result = decompress_file_io_uring(&path, &mut output);
+        std::fs::remove_file(&path).unwrap();
+        match result {
+            Ok(written) => {
+                assert_eq!(written, data.len() as u64);
+                assert!(output == data);
+            }
+            // io_uring may be unavailable or disabled in the test environment.
+            Err(e) if e.raw_os_error() == Some(38) || e.raw_os_error() == Some(1) => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
@@ -4,7 +4,7 @@
 //! images. It is compatible with standard zlib, but make a bunch of simplifying assumptions that
 //! drastically improve encoding performance:
 //!
-//! - Exactly one block per deflate stream.
+//! - A single compressed block per deflate stream, followed by an empty final block.
 //! - No distance codes except for run length encoding of zeros.
 //! - A single fixed huffman tree trained on a large corpus of PNG images.
 //! - All huffman codes are 12 bits or less.
@@ -18,19 +18,121 @@
 //! * [fpnge](https://github.com/veluca93/fpnge)
 //! * [zune-inflate](https://github.com/etemesi254/zune-image/tree/main/zune-inflate)
 //! * [RealTime Data Compression blog](https://fastcompression.blogspot.com/2015/10/huffman-revisited-part-4-multi-bytes.html)
-#![forbid(unsafe_code)]
+#![cfg_attr(
+    any(
+        feature = "forbid-unsafe",
+        not(any(feature = "mmap", feature = "io-uring"))
+    ),
+    forbid(unsafe_code)
+)]
+#![cfg_attr(
+    all(
+        not(feature = "forbid-unsafe"),
+        any(feature = "mmap", feature = "io-uring")
+    ),
+    deny(unsafe_code)
+)]
 #![warn(missing_docs)]
 
+#[cfg(feature = "async-io")]
+mod async_io;
+#[cfg(feature = "decompress")]
+mod back;
+#[cfg(feature = "compress")]
 mod compress;
+#[cfg(feature = "decompress")]
+mod cpu;
+#[cfg(feature = "gzip")]
+mod crc32;
+#[cfg(feature = "decompress")]
 mod decompress;
-mod tables;
+#[cfg(all(feature = "gzip", any(feature = "compress", feature = "decompress")))]
+mod gzfile;
+#[cfg(feature = "gzip")]
+mod gzip;
+#[cfg(feature = "compress")]
+mod huffman;
+#[cfg(all(target_os = "linux", feature = "io-uring", feature = "decompress"))]
+mod io_uring;
+#[cfg(all(unix, feature = "mmap"))]
+mod mmap;
+#[cfg(feature = "decompress")]
+mod push;
+#[cfg(feature = "decompress")]
+mod raw;
+#[cfg(feature = "decompress")]
+mod reader;
+#[cfg(feature = "decompress")]
+mod seek;
+#[cfg(feature = "decompress")]
+mod streaming;
+#[cfg(any(feature = "compress", feature = "decompress"))]
+pub mod tables;
+#[cfg(all(test, feature = "compress"))]
+mod test_utils;
+#[cfg(feature = "decompress")]
+mod windowed;
+#[cfg(feature = "decompress")]
+mod writer;
 
-pub use compress::{compress_to_vec, Compressor, StoredOnlyCompressor};
-pub use decompress::{decompress_to_vec, DecompressionError, Decompressor};
+#[cfg(feature = "async-io")]
+pub use async_io::{AsyncDecompressReader, AsyncDecompressWriter};
+#[cfg(feature = "decompress")]
+pub use back::inflate_back;
+#[cfg(feature = "compress")]
+pub use compress::{
+    append_final_empty_block, compress_bound, compress_stored_vectored, compress_to_vec,
+    compress_with_base, BatchCompressor, BlockWriter, ChunkedWriter, CompressionProgress,
+    Compressor, FlushPoint, SeekableCompressor, StoredOnlyCompressor, ZlibHeader,
+    EMPTY_ZLIB_STREAM, FORMAT_VERSION,
+};
+#[cfg(feature = "gzip")]
+pub use crc32::Crc32;
+#[cfg(feature = "perf-stats")]
+pub use decompress::PerfStats;
+#[cfg(feature = "decompress")]
+pub use decompress::{
+    decompress_all, decompress_chunks_to_vec, decompress_cow, decompress_each, decompress_exact,
+    decompress_to_growable, decompress_to_vec, decompress_to_vec_bounded, decompress_to_vec_strict,
+    decompress_to_vec_with_capacity, decompress_u16_be, decompress_with_base, find_sync_markers,
+    is_fdeflate_stream, repair_zlib_trailer, AnyDecompressor, BlockHeaderInfo, BlockSummary,
+    BlockType, BlockTypeSummary, CustomDecompressor, DecompressionError, DecompressionWarning,
+    Decompressor, DecompressorScratch, ExactOutput, GrowableOutput, ReadStatus, ResumePoint,
+    SmallDecompressor, Update, RECOMMENDED_INPUT_BUFFER_SIZE, RECOMMENDED_OUTPUT_BUFFER_SIZE,
+};
+#[cfg(all(feature = "gzip", feature = "compress"))]
+pub use gzfile::compress_file;
+#[cfg(all(feature = "gzip", feature = "decompress"))]
+pub use gzfile::{decompress_file, GzFile};
+#[cfg(all(feature = "gzip", feature = "decompress"))]
+pub use gzip::repair_gzip_trailer;
+#[cfg(all(feature = "gzip", feature = "compress"))]
+pub use gzip::{append_gzip_member, GzipWriter};
+#[cfg(feature = "gzip")]
+pub use gzip::{probe_gzip, GzipHeader, GzipInfo, EMPTY_GZIP_MEMBER};
+#[cfg(feature = "compress")]
+pub use huffman::HuffmanTable;
+#[cfg(all(target_os = "linux", feature = "io-uring", feature = "decompress"))]
+pub use io_uring::decompress_file_io_uring;
+#[cfg(all(unix, feature = "mmap"))]
+pub use mmap::{decompress_file_mmap, decompress_file_mmap_to_slice};
+#[cfg(feature = "decompress")]
+pub use push::PushDecompressor;
+#[cfg(feature = "decompress")]
+pub use raw::{RawDecompressor, RawErrorCode, RawResult, RawStatus};
+#[cfg(feature = "decompress")]
+pub use reader::DecompressReader;
+#[cfg(feature = "decompress")]
+pub use seek::{SeekIndex, SeekableReader};
+#[cfg(feature = "decompress")]
+pub use streaming::StreamingDecompressor;
+#[cfg(feature = "decompress")]
+pub use writer::DecompressWriter;
 
 /// Build a length limited huffman tree.
 ///
 /// Dynamic programming algorithm from fpnge.
+#[cfg(feature = "compress")]
 #[doc(hidden)]
 pub fn compute_code_lengths(
     freqs: &[u64],
@@ -93,6 +195,7 @@ pub fn compute_code_lengths(
     }
 }
 
+#[cfg(any(feature = "compress", feature = "decompress"))]
 const fn compute_codes<const NSYMS: usize>(lengths: &[u8; NSYMS]) -> Option<[u16; NSYMS]> {
     let mut codes = [0u16; NSYMS];
 
@@ -0,0 +1,95 @@
+//! Helpers for decompressing memory-mapped files.
+//!
+//! Mapping the file avoids the `read()` syscalls and the intermediate buffer that would otherwise
+//! be needed to feed the decompressor. With the `forbid-unsafe` feature, the file is read into
+//! memory instead.
+#![cfg_attr(not(feature = "forbid-unsafe"), allow(unsafe_code))]
+
+#[cfg(not(feature = "forbid-unsafe"))]
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+#[cfg(not(feature = "forbid-unsafe"))]
+use memmap2::{Advice, Mmap};
+
+use crate::windowed::OutputWindow;
+use crate::{DecompressionError, Decompressor};
+
+#[cfg(not(feature = "forbid-unsafe"))]
+fn map_file(path: &Path) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    // SAFETY: The mapping is only read from. As with any memory-mapped file, the contents may
+    // change if another process modifies the file, but that can at worst produce garbage output
+    // or a decompression error since every byte is treated as untrusted input.
+    let map = unsafe { Mmap::map(&file)? };
+    map.advise(Advice::Sequential)?;
+    Ok(map)
+}
+
+/// Mapping a file requires unsafe code, so read the whole file instead.
+#[cfg(feature = "forbid-unsafe")]
+fn map_file(path: &Path) -> io::Result<Vec<u8>> {
+    std::fs::read(path)
+}
+
+/// Decompresses the zlib stream in the file at `path`, writing the output to `writer`.
+///
+/// The file is memory-mapped rather than read, unless the `forbid-unsafe` feature is enabled.
+/// Returns the number of decompressed bytes.
+pub fn decompress_file_mmap<P: AsRef<Path>, W: Write>(path: P, mut writer: W) -> io::Result<u64> {
+    let map = map_file(path.as_ref())?;
+    let mut decompressor = Decompressor::new();
+    let (_, written) = OutputWindow::new().decode_to(&mut decompressor, &map, true, &mut writer)?;
+    if !decompressor.is_done() {
+        return Err(DecompressionError::InsufficientInput.into());
+    }
+    Ok(written)
+}
+
+/// Decompresses the zlib stream in the file at `path` into `output`.
+///
+/// The file is memory-mapped rather than read, unless the `forbid-unsafe` feature is enabled.
+/// Returns the number of decompressed bytes, or an error if `output` is too small to hold them.
+pub fn decompress_file_mmap_to_slice<P: AsRef<Path>>(
+    path: P,
+    output: &mut [u8],
+) -> io::Result<usize> {
+    let map = map_file(path.as_ref())?;
+    let mut decoder = Decompressor::new();
+    let (_, produced) = decoder.read(&map, output, 0, true)?;
+    if !decoder.is_done() {
+        return Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            "output buffer is too small",
+        ));
+    }
+    Ok(produced)
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let data = crate::test_utils::test_data(300_000);
+        let path = std::env::temp_dir().join(format!("fdeflate-mmap-{}.zlib", std::process::id()));
+        std::fs::write(&path, crate::compress_to_vec(&data)).unwrap();
+
+        let mut output = Vec::new();
+        let written = decompress_file_mmap(&path, &mut output).unwrap();
+        assert_eq!(written, data.len() as u64);
+        assert!(output == data);
+
+        let mut output = vec![0; data.len() + 2];
+        let written = decompress_file_mmap_to_slice(&path, &mut output).unwrap();
+        assert_eq!(written, data.len());
+        assert!(output[..written] == data[..]);
+
+        let mut output = vec![0; data.len() / 2];
+        assert!(decompress_file_mmap_to_slice(&path, &mut output).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+use crate::windowed::OutputWindow;
+use crate::{DecompressionError, Decompressor};
+
+/// Decompressor with separate calls for supplying input and retrieving output.
+///
+/// [`Decompressor::read`] consumes input and produces output in the same call, which is awkward
+/// when input arrives and output is wanted at different times, like in event-driven parsers.
+/// This wrapper buffers both sides internally: [`feed`](PushDecompressor::feed) queues
+/// compressed data whenever it arrives, and [`drain`](PushDecompressor::drain) decompresses as
+/// much of it as fits into the given buffer.
+///
+/// ```
+/// # #[cfg(feature = "compress")] {
+/// # use fdeflate::PushDecompressor;
+/// # let compressed = fdeflate::compress_to_vec(b"Hello world!");
+/// let mut decompressor = PushDecompressor::new();
+/// for packet in compressed.chunks(5) {
+///     decompressor.feed(packet);
+/// }
+/// decompressor.end_input();
+///
+/// let mut output = Vec::new();
+/// let mut buf = [0; 4];
+/// while !decompressor.is_done() {
+///     let n = decompressor.drain(&mut buf).unwrap();
+///     output.extend_from_slice(&buf[..n]);
+/// }
+/// assert_eq!(output, b"Hello world!");
+/// # }
+/// ```
+pub struct PushDecompressor {
+    decompressor: Box<Decompressor>,
+    input: Vec<u8>,
+    input_start: usize,
+    /// Total number of bytes fed so far.
+    input_total: u64,
+    end_of_input: bool,
+    output: OutputWindow,
+}
+
+impl PushDecompressor {
+    /// Create a new decompressor.
+    pub fn new() -> Self {
+        Self::from_decompressor(Box::default())
+    }
+
+    /// Wrap an existing decompressor, for instance one that has already been configured.
+    pub fn from_decompressor(decompressor: Box<Decompressor>) -> Self {
+        Self {
+            decompressor,
+            input: Vec::new(),
+            input_start: 0,
+            input_total: 0,
+            end_of_input: false,
+            output: OutputWindow::new(),
+        }
+    }
+
+    /// Queue more compressed data.
+    pub fn feed(&mut self, data: &[u8]) {
+        if self.input_start > self.input.len() / 2 {
+            self.input.drain(..self.input_start);
+            self.input_start = 0;
+        }
+        self.input.extend_from_slice(data);
+        self.input_total += data.len() as u64;
+    }
+
+    /// Signal that all of the compressed data has been fed.
+    ///
+    /// Afterwards, [`drain`](PushDecompressor::drain) reports a stream that ends early as
+    /// [`DecompressionError::InsufficientInput`].
+    pub fn end_input(&mut self) {
+        self.end_of_input = true;
+    }
+
+    /// Returns the number of fed bytes that haven't been decoded yet.
+    ///
+    /// Once the stream is done, these are the bytes that followed it.
+    pub fn pending_input(&self) -> u64 {
+        self.input_total - self.decompressor.byte_position()
+    }
+
+    /// Decompress queued data into `buf`, returning the number of bytes written.
+    ///
+    /// Returns zero if `buf` is empty, if the stream is done, or if more input has to be fed
+    /// before any further output can be produced.
+    pub fn drain(&mut self, buf: &mut [u8]) -> Result<usize, DecompressionError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.fill_output()?;
+        let available = self.output.buffered();
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.output.consume(n);
+        Ok(n)
+    }
+
+    /// Returns true once the end of the stream has been reached and all output drained.
+    pub fn is_done(&self) -> bool {
+        self.decompressor.is_done() && self.output.buffered().is_empty()
+    }
+
+    /// Decompress until some output is buffered, the queued input runs out, or the stream ends.
+    fn fill_output(&mut self) -> Result<(), DecompressionError> {
+        while self.output.buffered().is_empty() && !self.decompressor.is_done() {
+            let (consumed, produced) = self.output.decode(
+                &mut self.decompressor,
+                &self.input[self.input_start..],
+                self.end_of_input,
+            )?;
+            self.input_start += consumed;
+
+            if consumed == 0 && produced == 0 && !self.decompressor.is_done() {
+                if self.end_of_input {
+                    return Err(DecompressionError::InsufficientInput);
+                }
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for PushDecompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleaved_feed_and_drain() {
+        let data = crate::test_utils::test_data(300_000);
+        let mut compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+        compressed.extend_from_slice(b"trailer");
+
+        let mut decompressor = PushDecompressor::new();
+        let mut output = Vec::new();
+        let mut buf = [0; 1500];
+        for packet in compressed.chunks(997) {
+            decompressor.feed(packet);
+            loop {
+                let n = decompressor.drain(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                output.extend_from_slice(&buf[..n]);
+            }
+        }
+        assert!(decompressor.is_done());
+        assert_eq!(output, data);
+        assert_eq!(decompressor.pending_input(), b"trailer".len() as u64);
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let compressed = crate::compress_to_vec(&[7; 100_000]);
+        let mut decompressor = PushDecompressor::new();
+        decompressor.feed(&compressed[..compressed.len() - 3]);
+
+        let mut buf = vec![0; 200_000];
+        let mut produced = 0;
+        loop {
+            let n = decompressor.drain(&mut buf[produced..]).unwrap();
+            if n == 0 {
+                break;
+            }
+            produced += n;
+        }
+        assert_eq!(produced, 100_000);
+        assert!(!decompressor.is_done());
+
+        decompressor.end_input();
+        assert!(matches!(
+            decompressor.drain(&mut buf),
+            Err(DecompressionError::InsufficientInput)
+        ));
+    }
+}
@@ -0,0 +1,320 @@
+//! Low-level decompression API for FFI bindings.
+//!
+//! [`RawDecompressor`] wraps [`Decompressor`] behind a deliberately small interface: create,
+//! reset, run on caller-provided buffers, and query counters. Results are reported as plain
+//! `#[repr(C)]` values rather than `Result`s, and the interface is kept stable across internal
+//! refactors so that wrappers for other languages don't need to track changes to
+//! [`Decompressor::read`].
+
+use crate::{DecompressionError, Decompressor};
+
+/// Outcome of a call to [`RawDecompressor::run`].
+///
+/// The numeric values are stable and will not be reassigned.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RawStatus {
+    /// Some progress may have been made. Call `run` again with more input or more output space.
+    Ok = 0,
+    /// The end of the stream was reached and its checksum verified.
+    StreamEnd = 1,
+    /// `output_position` was out of bounds. No progress was made and the decompressor state is
+    /// unchanged.
+    BufferError = 2,
+    /// The stream is invalid or a limit was exceeded. The decompressor must be reset before it
+    /// can be used again.
+    DataError = 3,
+}
+
+/// Numeric code for a [`DecompressionError`].
+///
+/// The values are stable and will not be reassigned. They are grouped by kind of failure, with
+/// gaps left so that codes for future errors can be added next to related ones.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RawErrorCode {
+    /// No error occurred.
+    None = 0,
+
+    /// See [`DecompressionError::BadZlibHeader`].
+    BadZlibHeader = 100,
+    /// See [`DecompressionError::InsufficientInput`].
+    InsufficientInput = 101,
+    /// See [`DecompressionError::InvalidBlockType`].
+    InvalidBlockType = 102,
+    /// See [`DecompressionError::InvalidUncompressedBlockLength`].
+    InvalidUncompressedBlockLength = 103,
+    /// See [`DecompressionError::InvalidHlit`].
+    InvalidHlit = 104,
+    /// See [`DecompressionError::InvalidHdist`].
+    InvalidHdist = 105,
+    /// See [`DecompressionError::InvalidCodeLengthRepeat`].
+    InvalidCodeLengthRepeat = 106,
+    /// See [`DecompressionError::BadCodeLengthHuffmanTree`].
+    BadCodeLengthHuffmanTree = 107,
+    /// See [`DecompressionError::BadLiteralLengthHuffmanTree`].
+    BadLiteralLengthHuffmanTree = 108,
+    /// See [`DecompressionError::BadDistanceHuffmanTree`].
+    BadDistanceHuffmanTree = 109,
+    /// See [`DecompressionError::InvalidLiteralLengthCode`].
+    InvalidLiteralLengthCode = 110,
+    /// See [`DecompressionError::InvalidDistanceCode`].
+    InvalidDistanceCode = 111,
+    /// See [`DecompressionError::InputStartsWithRun`].
+    InputStartsWithRun = 112,
+    /// See [`DecompressionError::DistanceTooFarBack`].
+    DistanceTooFarBack = 113,
+    /// See [`DecompressionError::WrongChecksum`].
+    WrongChecksum = 114,
+    /// See [`DecompressionError::ExtraInput`].
+    ExtraInput = 115,
+
+    /// See [`DecompressionError::InvalidResumePoint`].
+    InvalidResumePoint = 200,
+
+    /// See [`DecompressionError::OutputLimitExceeded`].
+    OutputLimitExceeded = 300,
+    /// See [`DecompressionError::ExpansionRatioExceeded`].
+    ExpansionRatioExceeded = 301,
+    /// See [`DecompressionError::Cancelled`].
+    Cancelled = 302,
+    /// See [`DecompressionError::OutputSizeMismatch`].
+    OutputSizeMismatch = 303,
+}
+
+impl From<&DecompressionError> for RawErrorCode {
+    fn from(err: &DecompressionError) -> Self {
+        match err {
+            DecompressionError::BadZlibHeader => RawErrorCode::BadZlibHeader,
+            DecompressionError::InsufficientInput => RawErrorCode::InsufficientInput,
+            DecompressionError::InvalidBlockType => RawErrorCode::InvalidBlockType,
+            DecompressionError::InvalidUncompressedBlockLength => {
+                RawErrorCode::InvalidUncompressedBlockLength
+            }
+            DecompressionError::InvalidHlit => RawErrorCode::InvalidHlit,
+            DecompressionError::InvalidHdist => RawErrorCode::InvalidHdist,
+            DecompressionError::InvalidCodeLengthRepeat => RawErrorCode::InvalidCodeLengthRepeat,
+            DecompressionError::BadCodeLengthHuffmanTree => RawErrorCode::BadCodeLengthHuffmanTree,
+            DecompressionError::BadLiteralLengthHuffmanTree => {
+                RawErrorCode::BadLiteralLengthHuffmanTree
+            }
+            DecompressionError::BadDistanceHuffmanTree => RawErrorCode::BadDistanceHuffmanTree,
+            DecompressionError::InvalidLiteralLengthCode => RawErrorCode::InvalidLiteralLengthCode,
+            DecompressionError::InvalidDistanceCode => RawErrorCode::InvalidDistanceCode,
+            DecompressionError::InputStartsWithRun => RawErrorCode::InputStartsWithRun,
+            DecompressionError::DistanceTooFarBack => RawErrorCode::DistanceTooFarBack,
+            DecompressionError::WrongChecksum => RawErrorCode::WrongChecksum,
+            DecompressionError::ExtraInput => RawErrorCode::ExtraInput,
+            DecompressionError::InvalidResumePoint => RawErrorCode::InvalidResumePoint,
+            DecompressionError::OutputLimitExceeded => RawErrorCode::OutputLimitExceeded,
+            DecompressionError::ExpansionRatioExceeded => RawErrorCode::ExpansionRatioExceeded,
+            DecompressionError::Cancelled => RawErrorCode::Cancelled,
+            DecompressionError::OutputSizeMismatch => RawErrorCode::OutputSizeMismatch,
+        }
+    }
+}
+
+impl From<DecompressionError> for RawErrorCode {
+    fn from(err: DecompressionError) -> Self {
+        Self::from(&err)
+    }
+}
+
+/// Status and byte counts returned by [`RawDecompressor::run`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RawResult {
+    /// Outcome of the call.
+    pub status: RawStatus,
+    /// Number of bytes consumed from the input.
+    pub consumed: usize,
+    /// Number of bytes written to the output, starting at `output_position`.
+    pub produced: usize,
+}
+
+/// Decompressor with a minimal interface intended as a stable base for FFI wrappers.
+pub struct RawDecompressor {
+    inner: Box<Decompressor>,
+    ignore_adler32: bool,
+    error: Option<DecompressionError>,
+}
+
+impl RawDecompressor {
+    /// Create a new decompressor.
+    pub fn new() -> Self {
+        Self {
+            inner: Box::default(),
+            ignore_adler32: false,
+            error: None,
+        }
+    }
+
+    /// Skip verification of the Adler-32 checksum at the end of the stream.
+    ///
+    /// The setting is kept across calls to [`RawDecompressor::reset`].
+    pub fn set_ignore_adler32(&mut self, ignore: bool) {
+        self.ignore_adler32 = ignore;
+        if ignore {
+            self.inner.ignore_adler32();
+        }
+    }
+
+    /// Reset the decompressor so that it can decode a new stream.
+    pub fn reset(&mut self) {
+        self.inner.reset();
+        if self.ignore_adler32 {
+            self.inner.ignore_adler32();
+        }
+        self.error = None;
+    }
+
+    /// Decompress from `input` into `output`, starting at `output_position`.
+    ///
+    /// The bytes of `output` before `output_position` must hold the previously decompressed data
+    /// (at least the last 32 KiB, or everything if less has been produced) since back-references
+    /// are resolved against them. `end_of_input` indicates that no further input will be
+    /// provided.
+    pub fn run(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        output_position: usize,
+        end_of_input: bool,
+    ) -> RawResult {
+        let mut result = RawResult {
+            status: RawStatus::Ok,
+            consumed: 0,
+            produced: 0,
+        };
+
+        if self.error.is_some() {
+            result.status = RawStatus::DataError;
+        } else if self.inner.is_done() {
+            result.status = RawStatus::StreamEnd;
+        } else if output_position > output.len() {
+            result.status = RawStatus::BufferError;
+        } else {
+            match self
+                .inner
+                .read(input, output, output_position, end_of_input)
+            {
+                Ok((consumed, produced)) => {
+                    result.consumed = consumed;
+                    result.produced = produced;
+                    if self.inner.is_done() {
+                        result.status = RawStatus::StreamEnd;
+                    }
+                }
+                Err(err) => {
+                    self.error = Some(err);
+                    result.status = RawStatus::DataError;
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the error that caused the last [`RawStatus::DataError`], if any.
+    pub fn last_error(&self) -> Option<&DecompressionError> {
+        self.error.as_ref()
+    }
+
+    /// Returns the code of the error that caused the last [`RawStatus::DataError`], or
+    /// [`RawErrorCode::None`].
+    pub fn error_code(&self) -> RawErrorCode {
+        self.error
+            .as_ref()
+            .map_or(RawErrorCode::None, RawErrorCode::from)
+    }
+
+    /// Returns the total number of input bytes consumed since creation or the last reset.
+    pub fn total_in(&self) -> u64 {
+        self.inner.total_input()
+    }
+
+    /// Returns the total number of bytes produced since creation or the last reset.
+    pub fn total_out(&self) -> u64 {
+        self.inner.total_output()
+    }
+}
+
+impl Default for RawDecompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_to_end() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let compressed = crate::compress_to_vec(&data);
+
+        let mut decompressor = RawDecompressor::new();
+        let mut output = vec![0; data.len() + 2];
+        let mut input = &compressed[..];
+        let mut position = 0;
+        loop {
+            let chunk = &input[..input.len().min(1000)];
+            let result = decompressor.run(chunk, &mut output, position, chunk.len() == input.len());
+            input = &input[result.consumed..];
+            position += result.produced;
+            if result.status == RawStatus::StreamEnd {
+                break;
+            }
+            assert_eq!(result.status, RawStatus::Ok);
+        }
+        assert_eq!(&output[..position], &data[..]);
+        assert_eq!(decompressor.total_out(), data.len() as u64);
+
+        let result = decompressor.run(&[], &mut output, 0, true);
+        assert_eq!(result.status, RawStatus::StreamEnd);
+    }
+
+    #[test]
+    fn errors_and_reset() {
+        let compressed = crate::compress_to_vec(b"hello");
+        let mut decompressor = RawDecompressor::new();
+
+        let mut output = [0; 1];
+        let result = decompressor.run(&compressed, &mut output, 2, true);
+        assert_eq!(result.status, RawStatus::BufferError);
+        assert_eq!(result.consumed, 0);
+        let result = decompressor.run(&compressed, &mut output, 0, true);
+        assert_eq!(result.status, RawStatus::Ok);
+        assert_eq!(&output[..result.produced], b"h");
+        decompressor.reset();
+
+        let mut output = [0; 16];
+        let result = decompressor.run(&[0xff; 16], &mut output, 0, true);
+        assert_eq!(result.status, RawStatus::DataError);
+        assert!(decompressor.last_error().is_some());
+        assert_eq!(decompressor.error_code(), RawErrorCode::BadZlibHeader);
+        let result = decompressor.run(&compressed, &mut output, 0, true);
+        assert_eq!(result.status, RawStatus::DataError);
+
+        decompressor.reset();
+        assert_eq!(decompressor.error_code(), RawErrorCode::None);
+        assert_eq!(decompressor.total_in(), 0);
+        let result = decompressor.run(&compressed, &mut output, 0, true);
+        assert_eq!(result.status, RawStatus::StreamEnd);
+        assert_eq!(&output[..result.produced], b"hello");
+    }
+
+    #[test]
+    fn stable_error_codes() {
+        assert_eq!(RawErrorCode::None as i32, 0);
+        assert_eq!(
+            RawErrorCode::from(DecompressionError::WrongChecksum) as i32,
+            114
+        );
+        assert_eq!(
+            RawErrorCode::from(DecompressionError::Cancelled) as i32,
+            302
+        );
+        assert_eq!(RawStatus::DataError as i32, 3);
+    }
+}
@@ -0,0 +1,352 @@
+use std::io::{self, BufRead, Read};
+
+use crate::windowed::OutputWindow;
+use crate::{DecompressionError, Decompressor, RECOMMENDED_INPUT_BUFFER_SIZE};
+
+/// Decompresses a zlib stream read from an underlying reader.
+///
+/// Besides [`Read`], this implements [`BufRead`] by exposing its internal buffer of decompressed
+/// data, so line-oriented parsing doesn't need an extra [`io::BufReader`] and copy:
+///
+/// ```
+/// # #[cfg(feature = "compress")] {
+/// # use std::io::BufRead;
+/// # use fdeflate::DecompressReader;
+/// # let compressed = fdeflate::compress_to_vec(b"first line\nsecond line\n");
+/// let reader = DecompressReader::new(&compressed[..]);
+/// let lines: Vec<String> = reader.lines().collect::<Result<_, _>>().unwrap();
+/// assert_eq!(lines, ["first line", "second line"]);
+/// # }
+/// ```
+///
+/// The underlying reader is read [`RECOMMENDED_INPUT_BUFFER_SIZE`] bytes at a time, so it doesn't
+/// need to be wrapped in an [`io::BufReader`] either. [`Read::read_to_end`] appends the buffered
+/// output directly rather than going through small intermediate reads.
+pub struct DecompressReader<R> {
+    reader: R,
+    decompressor: Box<Decompressor>,
+    input: Vec<u8>,
+    input_start: usize,
+    input_end: usize,
+    reader_done: bool,
+    output: OutputWindow,
+    expected_len: Option<u64>,
+}
+
+impl<R: Read> DecompressReader<R> {
+    /// Create a new reader that decompresses the zlib stream read from `reader`.
+    pub fn new(reader: R) -> Self {
+        Self::with_decompressor(reader, Box::default())
+    }
+
+    /// Create a reader that decodes the stream in `reader` with an already configured
+    /// decompressor.
+    pub(crate) fn with_decompressor(reader: R, decompressor: Box<Decompressor>) -> Self {
+        Self {
+            reader,
+            decompressor,
+            input: vec![0; RECOMMENDED_INPUT_BUFFER_SIZE],
+            input_start: 0,
+            input_end: 0,
+            reader_done: false,
+            output: OutputWindow::new(),
+            expected_len: None,
+        }
+    }
+
+    /// Create a reader for a zlib stream that must decompress to exactly `expected_len` bytes.
+    ///
+    /// Like [`Read::take`], this yields at most `expected_len` bytes, but it also checks that the
+    /// stream ends there: reads fail with [`DecompressionError::OutputSizeMismatch`] as soon as
+    /// the stream produces more data, or if it ends with less. Once all the data has been read,
+    /// [`DecompressReader::has_trailing_data`] reports whether input follows the stream.
+    pub fn with_expected_len(reader: R, expected_len: u64) -> Self {
+        let mut this = Self::new(reader);
+        this.decompressor.set_output_limit(expected_len);
+        this.expected_len = Some(expected_len);
+        this
+    }
+
+    /// Returns whether the underlying reader has data past the end of the zlib stream.
+    ///
+    /// Returns false if the stream hasn't been fully decompressed yet. This may read from the
+    /// underlying reader to find out.
+    pub fn has_trailing_data(&mut self) -> io::Result<bool> {
+        if !self.decompressor.is_done() {
+            return Ok(false);
+        }
+        Ok(!self.remaining_input().fill_buf()?.is_empty())
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the underlying reader.
+    ///
+    /// Reading from it directly will corrupt the decompressed stream unless decompression is
+    /// already done.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the underlying reader.
+    ///
+    /// Any input that was read from it but not yet decompressed, including data past the end of
+    /// the zlib stream, is lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Returns the decompressed data that has been buffered but not consumed yet.
+    #[cfg(feature = "gzip")]
+    pub(crate) fn buffered(&self) -> &[u8] {
+        self.output.buffered()
+    }
+
+    /// Returns the input that hasn't been passed to the decompressor: first the buffered input,
+    /// then the rest of the underlying reader.
+    ///
+    /// Once the stream is done, this is the data following it, for instance the trailer of a
+    /// gzip member.
+    pub(crate) fn remaining_input(&mut self) -> RemainingInput<'_, R> {
+        RemainingInput { inner: self }
+    }
+
+    /// Returns the decompressor.
+    #[cfg(feature = "gzip")]
+    pub(crate) fn decompressor(&self) -> &Decompressor {
+        &self.decompressor
+    }
+
+    /// Returns the decompressor.
+    #[cfg(feature = "gzip")]
+    pub(crate) fn decompressor_mut(&mut self) -> &mut Decompressor {
+        &mut self.decompressor
+    }
+
+    /// Start decoding another stream from the remaining input, with `decompressor`.
+    #[cfg(feature = "gzip")]
+    pub(crate) fn restart(&mut self, decompressor: Box<Decompressor>) {
+        self.decompressor = decompressor;
+        self.output.reset(&[]);
+    }
+
+    /// Move any unconsumed input to the start of the buffer and read more after it.
+    ///
+    /// The last few consumed bytes are kept, since the decompressor may turn out to have read
+    /// them ahead past the end of the stream.
+    fn refill(&mut self) -> io::Result<()> {
+        let keep_from = self.input_start.saturating_sub(8);
+        self.input.copy_within(keep_from..self.input_end, 0);
+        self.input_start -= keep_from;
+        self.input_end -= keep_from;
+
+        let n = loop {
+            match self.reader.read(&mut self.input[self.input_end..]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                result => break result?,
+            }
+        };
+        self.input_end += n;
+        self.reader_done = n == 0;
+        Ok(())
+    }
+
+    /// Decompress until some output is buffered or the stream ends.
+    fn fill_output(&mut self) -> io::Result<()> {
+        while self.output.buffered().is_empty() && !self.decompressor.is_done() {
+            let (consumed, produced) = match self.output.decode(
+                &mut self.decompressor,
+                &self.input[self.input_start..self.input_end],
+                self.reader_done,
+            ) {
+                Err(DecompressionError::OutputLimitExceeded) if self.expected_len.is_some() => {
+                    return Err(DecompressionError::OutputSizeMismatch.into())
+                }
+                result => result?,
+            };
+            self.input_start += consumed;
+            if self.decompressor.is_done() {
+                // Hand back the input that was read ahead past the end of the stream.
+                self.input_start -= self.decompressor.read_ahead_len() as usize;
+            }
+
+            if consumed == 0 && produced == 0 && !self.decompressor.is_done() {
+                if self.reader_done {
+                    return Err(DecompressionError::InsufficientInput.into());
+                }
+                self.refill()?;
+            }
+        }
+        if let Some(expected_len) = self.expected_len {
+            if self.decompressor.is_done() && self.decompressor.total_output() != expected_len {
+                return Err(DecompressionError::OutputSizeMismatch.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecompressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let start = buf.len();
+        loop {
+            let available = self.fill_buf()?;
+            if available.is_empty() {
+                return Ok(buf.len() - start);
+            }
+            let n = available.len();
+            buf.extend_from_slice(available);
+            self.consume(n);
+        }
+    }
+}
+
+impl<R: Read> BufRead for DecompressReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.fill_output()?;
+        Ok(self.output.buffered())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.output.consume(amt);
+    }
+}
+
+/// Input of a [`DecompressReader`] that hasn't been passed to its decompressor, see
+/// [`DecompressReader::remaining_input`].
+pub(crate) struct RemainingInput<'a, R> {
+    inner: &'a mut DecompressReader<R>,
+}
+
+impl<R: Read> Read for RemainingInput<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for RemainingInput<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let inner = &mut *self.inner;
+        if inner.input_start == inner.input_end && !inner.reader_done {
+            inner.refill()?;
+        }
+        Ok(&inner.input[inner.input_start..inner.input_end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.input_start = (self.inner.input_start + amt).min(self.inner.input_end);
+    }
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_to_end() {
+        let data = crate::test_utils::test_data(300_000);
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        let mut output = Vec::new();
+        DecompressReader::new(&compressed[..])
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, data);
+
+        let truncated = &compressed[..compressed.len() / 2];
+        let err = DecompressReader::new(truncated)
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn expected_len() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let input = crate::compress_to_vec(&data);
+
+        let mut reader = DecompressReader::with_expected_len(&input[..], data.len() as u64);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+        assert!(!reader.has_trailing_data().unwrap());
+
+        for expected_len in [data.len() - 1, data.len() + 1] {
+            let err = DecompressReader::with_expected_len(&input[..], expected_len as u64)
+                .read_to_end(&mut Vec::new())
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        for trailer in [&b"t"[..], b"trailer"] {
+            let mut input = input.clone();
+            input.extend_from_slice(trailer);
+            let mut reader = DecompressReader::with_expected_len(&input[..], data.len() as u64);
+            reader.read_to_end(&mut Vec::new()).unwrap();
+            assert!(reader.has_trailing_data().unwrap());
+        }
+    }
+
+    /// Yields one byte per call, with an interruption before each.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        interrupt: bool,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            let n = self.data.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn interrupted_reads() {
+        let data = vec![3; 10_000];
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+        let trickle = Trickle {
+            data: &compressed,
+            interrupt: false,
+        };
+
+        let mut reader = DecompressReader::new(trickle);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+        assert!(reader.into_inner().data.is_empty());
+    }
+
+    #[test]
+    fn buf_read_lines() {
+        let text: String = (0..10_000).map(|i| format!("line {}\n", i)).collect();
+        let compressed = crate::compress_to_vec(text.as_bytes());
+
+        let reader = DecompressReader::new(&compressed[..]);
+        let lines: Vec<String> = reader.lines().collect::<Result<_, _>>().unwrap();
+        assert_eq!(lines.len(), 10_000);
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(*line, format!("line {}", i));
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+
+use crate::windowed::OutputWindow;
+use crate::{DecompressionError, Decompressor, ResumePoint, RECOMMENDED_INPUT_BUFFER_SIZE};
+
+/// A position in the decompressed data from which decoding can start.
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    /// Offset of the checkpoint in the decompressed data.
+    output_offset: u64,
+    resume_point: ResumePoint,
+    /// The (up to) 32 KiB of output preceding the checkpoint.
+    window: Vec<u8>,
+}
+
+/// Index of positions in a compressed stream at which decompression can start.
+///
+/// Building the index decompresses the whole stream once, recording a checkpoint roughly every
+/// `span` bytes of output. Each checkpoint stores a [`ResumePoint`] plus the 32 KiB of output
+/// preceding it, so larger spans make the index smaller but seeking slower. The index is used by
+/// [`SeekableReader`] for random access.
+///
+/// Both zlib streams and, with the `gzip` feature, single-member gzip files are supported.
+#[derive(Debug, Clone)]
+pub struct SeekIndex {
+    /// Offset of the deflate data in the file, after any gzip header.
+    data_start: u64,
+    gzip: bool,
+    checkpoints: Vec<Checkpoint>,
+    uncompressed_size: u64,
+}
+
+impl SeekIndex {
+    /// Build an index of the compressed stream read from `reader`.
+    pub fn build<R: Read>(reader: R, span: u64) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let (mut decompressor, data_start, gzip) = new_decompressor(&mut reader)?;
+
+        let mut index = Self {
+            data_start,
+            gzip,
+            checkpoints: Vec::new(),
+            uncompressed_size: 0,
+        };
+        let mut input = vec![0; RECOMMENDED_INPUT_BUFFER_SIZE];
+        let mut input_start = 0;
+        let mut input_end = 0;
+        let mut reader_done = false;
+        let mut output = OutputWindow::new();
+        let mut next_checkpoint = span.max(1);
+        while !decompressor.is_done() {
+            if decompressor.total_output() >= next_checkpoint {
+                if let Some(resume_point) = decompressor.resume_point() {
+                    index.checkpoints.push(Checkpoint {
+                        output_offset: decompressor.total_output(),
+                        resume_point,
+                        window: output.history().to_vec(),
+                    });
+                    next_checkpoint = decompressor.total_output().saturating_add(span.max(1));
+                }
+            }
+
+            // Stop decoding when the next checkpoint is due. There is no resume point in the middle
+            // of a back-reference, so from then on decode in small steps until one of them ends
+            // between two symbols.
+            let until_checkpoint = next_checkpoint.saturating_sub(decompressor.total_output());
+            decompressor
+                .set_max_output_per_call(until_checkpoint.clamp(1, usize::MAX as u64) as usize);
+            let (consumed, produced) = output.decode(
+                &mut decompressor,
+                &input[input_start..input_end],
+                reader_done,
+            )?;
+            output.consume(produced);
+            input_start += consumed;
+
+            if consumed == 0 && produced == 0 && !decompressor.is_done() {
+                if reader_done {
+                    return Err(DecompressionError::InsufficientInput.into());
+                }
+                input.copy_within(input_start..input_end, 0);
+                input_end -= input_start;
+                input_start = 0;
+                let n = reader.read(&mut input[input_end..])?;
+                input_end += n;
+                reader_done = n == 0;
+            }
+        }
+        index.uncompressed_size = decompressor.total_output();
+        Ok(index)
+    }
+
+    /// Create an index from the flush points recorded by a
+    /// [`SeekableCompressor`](crate::SeekableCompressor), without decompressing anything.
+    ///
+    /// `uncompressed_size` is the total size of the data that was compressed. Since no data
+    /// before a full flush is referenced after it, the checkpoints don't need to store any
+    /// preceding output.
+    #[cfg(feature = "compress")]
+    pub fn from_flush_points(flush_points: &[crate::FlushPoint], uncompressed_size: u64) -> Self {
+        let checkpoints = flush_points
+            .iter()
+            .map(|point| Checkpoint {
+                output_offset: point.uncompressed_offset,
+                resume_point: ResumePoint {
+                    block_start: point.compressed_offset * 8,
+                    position: point.compressed_offset * 8,
+                },
+                window: Vec::new(),
+            })
+            .collect();
+        Self {
+            data_start: 0,
+            gzip: false,
+            checkpoints,
+            uncompressed_size,
+        }
+    }
+
+    /// Returns the number of checkpoints in the index, not counting the start of the stream.
+    pub fn checkpoint_count(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// Returns the size of the decompressed data.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+}
+
+/// Create a decompressor for the stream in `reader`, after reading its gzip header if there is
+/// one. Also returns the offset of the deflate data and whether the stream is gzip.
+fn new_decompressor<R: Read>(
+    reader: &mut BufReader<R>,
+) -> io::Result<(Box<Decompressor>, u64, bool)> {
+    #[cfg(feature = "gzip")]
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        let (_, header_len) = crate::GzipHeader::read_from(reader)?;
+        let decompressor = Box::new(Decompressor::new_raw());
+        return Ok((decompressor, header_len, true));
+    }
+    #[cfg(not(feature = "gzip"))]
+    let _ = reader.fill_buf()?;
+    Ok((Box::default(), 0, false))
+}
+
+/// Decompressing reader that supports seeking, using a [`SeekIndex`].
+///
+/// Seeking backwards, or far enough forwards to pass a checkpoint, restarts decompression from
+/// the checkpoint closest to the target and then skips ahead with
+/// [`Decompressor::skip_output`]. Short forward seeks just skip ahead from the current position.
+/// Checksums are only verified when the stream is read from the start without seeking.
+///
+/// ```
+/// # #[cfg(feature = "compress")] {
+/// # use std::io::{Cursor, Read, Seek, SeekFrom};
+/// # use fdeflate::{SeekIndex, SeekableReader};
+/// # let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+/// # let compressed = fdeflate::compress_to_vec(&data);
+/// let index = SeekIndex::build(&compressed[..], 64 * 1024).unwrap();
+/// let mut reader = SeekableReader::new(Cursor::new(&compressed), index).unwrap();
+///
+/// let mut buf = [0; 100];
+/// reader.seek(SeekFrom::Start(500_000)).unwrap();
+/// reader.read_exact(&mut buf).unwrap();
+/// assert_eq!(buf[..], data[500_000..500_100]);
+/// # }
+/// ```
+pub struct SeekableReader<R> {
+    reader: R,
+    index: SeekIndex,
+    decompressor: Box<Decompressor>,
+    input: Vec<u8>,
+    input_start: usize,
+    input_end: usize,
+    reader_done: bool,
+    output: OutputWindow,
+    /// Offset in the decompressed data of the start of the buffered output.
+    position: u64,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    /// Create a reader for the stream in `reader`, which `index` must have been built from.
+    pub fn new(reader: R, index: SeekIndex) -> io::Result<Self> {
+        let mut this = Self {
+            reader,
+            index,
+            decompressor: Box::default(),
+            input: vec![0; RECOMMENDED_INPUT_BUFFER_SIZE],
+            input_start: 0,
+            input_end: 0,
+            reader_done: false,
+            output: OutputWindow::new(),
+            position: 0,
+        };
+        this.restart(None)?;
+        Ok(this)
+    }
+
+    /// Returns the index used by the reader.
+    pub fn index(&self) -> &SeekIndex {
+        &self.index
+    }
+
+    /// Move any unconsumed input to the start of the buffer and read more after it.
+    fn refill(&mut self) -> io::Result<()> {
+        self.input.copy_within(self.input_start..self.input_end, 0);
+        self.input_end -= self.input_start;
+        self.input_start = 0;
+
+        let n = self.reader.read(&mut self.input[self.input_end..])?;
+        self.input_end += n;
+        self.reader_done = n == 0;
+        Ok(())
+    }
+
+    /// Restart decompression at the given checkpoint, or at the start of the stream.
+    fn restart(&mut self, checkpoint: Option<usize>) -> io::Result<()> {
+        self.input_start = 0;
+        self.input_end = 0;
+        self.reader_done = false;
+
+        let checkpoint = match checkpoint {
+            Some(i) => &self.index.checkpoints[i],
+            None => {
+                if self.index.gzip {
+                    self.reader.seek(SeekFrom::Start(self.index.data_start))?;
+                    *self.decompressor = Decompressor::new_raw();
+                } else {
+                    self.reader.seek(SeekFrom::Start(0))?;
+                    *self.decompressor = Decompressor::new();
+                }
+                self.output.reset(&[]);
+                self.position = 0;
+                return Ok(());
+            }
+        };
+
+        let resume_point = checkpoint.resume_point;
+        self.output.reset(&checkpoint.window);
+        self.position = checkpoint.output_offset;
+
+        self.reader.seek(SeekFrom::Start(
+            self.index.data_start + resume_point.block_start / 8,
+        ))?;
+        // Resuming in the middle of a block needs all of the input from the start of the block,
+        // so grow the input buffer until it fits.
+        loop {
+            if self.input_end == self.input.len() {
+                self.input.resize(self.input.len() * 2, 0);
+            }
+            self.refill()?;
+            match Decompressor::resume(&resume_point, &self.input[..self.input_end]) {
+                Ok((decompressor, consumed)) => {
+                    *self.decompressor = decompressor;
+                    self.input_start = consumed;
+                    return Ok(());
+                }
+                Err(DecompressionError::InsufficientInput) if !self.reader_done => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Decompress and discard output until `position` reaches `target` or the stream ends.
+    fn skip_to(&mut self, target: u64) -> io::Result<()> {
+        let buffered = self.output.buffered().len() as u64;
+        if target - self.position <= buffered {
+            self.output.consume((target - self.position) as usize);
+            self.position = target;
+            return Ok(());
+        }
+        self.output.consume(buffered as usize);
+        self.position += buffered;
+
+        while self.position < target && !self.decompressor.is_done() {
+            let (consumed, skipped) = self.output.skip(
+                &mut self.decompressor,
+                &self.input[self.input_start..self.input_end],
+                target - self.position,
+                self.reader_done,
+            )?;
+            self.input_start += consumed;
+            self.position += skipped;
+
+            if consumed == 0 && skipped == 0 && !self.decompressor.is_done() {
+                if self.reader_done {
+                    return Err(DecompressionError::InsufficientInput.into());
+                }
+                self.refill()?;
+            }
+        }
+        // Seeking past the end is allowed, but reads from there return nothing.
+        self.position = target;
+        Ok(())
+    }
+
+    /// Decompress until some output is buffered or the stream ends.
+    fn fill_output(&mut self) -> io::Result<()> {
+        while self.output.buffered().is_empty() && !self.decompressor.is_done() {
+            let (consumed, produced) = self.output.decode(
+                &mut self.decompressor,
+                &self.input[self.input_start..self.input_end],
+                self.reader_done,
+            )?;
+            self.input_start += consumed;
+
+            if consumed == 0 && produced == 0 && !self.decompressor.is_done() {
+                if self.reader_done {
+                    return Err(DecompressionError::InsufficientInput.into());
+                }
+                self.refill()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.index.uncompressed_size {
+            return Ok(0);
+        }
+        self.fill_output()?;
+        let available = self.output.buffered();
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.output.consume(n);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => checked_add_signed(self.index.uncompressed_size, offset),
+            SeekFrom::Current(offset) => checked_add_signed(self.position, offset),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        // Restart from the last checkpoint before the target, unless it is behind the current
+        // position and the target isn't.
+        let checkpoints = &self.index.checkpoints;
+        let checkpoint = checkpoints.partition_point(|c| c.output_offset <= target);
+        let checkpoint_offset = match checkpoint {
+            0 => 0,
+            i => checkpoints[i - 1].output_offset,
+        };
+        if target < self.position || checkpoint_offset > self.position {
+            self.restart(checkpoint.checked_sub(1))?;
+        }
+        self.skip_to(target)?;
+        Ok(target)
+    }
+}
+
+fn checked_add_signed(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.unsigned_abs())
+    }
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn check_random_access(compressed: &[u8], data: &[u8]) {
+        let index = SeekIndex::build(compressed, 20_000).unwrap();
+        assert_eq!(index.uncompressed_size(), data.len() as u64);
+        assert!(index.checkpoint_count() >= 14);
+
+        let mut reader = SeekableReader::new(Cursor::new(compressed), index).unwrap();
+        let mut buf = vec![0; 1000];
+        for &offset in &[123_456, 5, 250_000, 250_500, 0, 299_500, 40_000, 123_000] {
+            assert_eq!(reader.seek(SeekFrom::Start(offset)).unwrap(), offset);
+            reader.read_exact(&mut buf[..500]).unwrap();
+            assert!(buf[..500] == data[offset as usize..][..500]);
+        }
+
+        assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), 299_990);
+        assert_eq!(reader.read(&mut buf).unwrap(), 10);
+        assert!(buf[..10] == data[299_990..]);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        assert_eq!(reader.seek(SeekFrom::Current(-1000)).unwrap(), 299_000);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert!(rest == data[299_000..]);
+
+        assert_eq!(reader.seek(SeekFrom::Start(400_000)).unwrap(), 400_000);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert!(reader.seek(SeekFrom::Current(-500_000)).is_err());
+    }
+
+    #[test]
+    fn flush_points() {
+        let data = crate::test_utils::test_data(300_000);
+        let mut compressor = crate::SeekableCompressor::new(Vec::new(), 40_000).unwrap();
+        for chunk in data.chunks(7777) {
+            compressor.write_data(chunk).unwrap();
+        }
+        let (compressed, flush_points) = compressor.finish().unwrap();
+        assert_eq!(flush_points.len(), 7);
+        assert_eq!(crate::decompress_to_vec(&compressed).unwrap(), data);
+
+        for point in &flush_points {
+            let offset = point.uncompressed_offset as usize;
+            assert_eq!(offset % 40_000, 0);
+            let (mut decompressor, position) = Decompressor::with_window(&[], &mut []);
+            let mut output = vec![0; data.len() - offset + 2];
+            let (_, produced) = decompressor
+                .read(
+                    &compressed[point.compressed_offset as usize..],
+                    &mut output,
+                    position,
+                    true,
+                )
+                .unwrap();
+            assert!(output[..produced] == data[offset..]);
+        }
+
+        let index = SeekIndex::from_flush_points(&flush_points, data.len() as u64);
+        let mut reader = SeekableReader::new(Cursor::new(&compressed), index).unwrap();
+        let mut buf = [0; 500];
+        for &offset in &[123_456, 5, 280_000, 40_000, 0, 79_999] {
+            reader.seek(SeekFrom::Start(offset)).unwrap();
+            reader.read_exact(&mut buf).unwrap();
+            assert!(buf[..] == data[offset as usize..][..500]);
+        }
+    }
+
+    #[test]
+    fn random_access() {
+        let data = crate::test_utils::test_data(300_000);
+        check_random_access(&crate::compress_to_vec(&data), &data);
+        check_random_access(&miniz_oxide::deflate::compress_to_vec_zlib(&data, 6), &data);
+
+        #[cfg(feature = "gzip")]
+        {
+            let mut writer = crate::GzipWriter::new(Vec::new(), &Default::default()).unwrap();
+            writer.write_data(&data).unwrap();
+            check_random_access(&writer.finish().unwrap(), &data);
+        }
+    }
+}
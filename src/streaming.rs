@@ -0,0 +1,131 @@
+use crate::{DecompressionError, Decompressor};
+
+/// Streaming decompressor where the end of the input is signalled with an explicit
+/// [`finish`](StreamingDecompressor::finish) call.
+///
+/// [`Decompressor::read`] takes an `end_of_input` flag on every call, and whether a truncated
+/// stream is reported as [`DecompressionError::InsufficientInput`] depends on it. This wrapper
+/// splits the two cases into separate methods: [`decompress`](StreamingDecompressor::decompress)
+/// never treats running out of input as an error, while `finish` always does.
+///
+/// ```
+/// # #[cfg(feature = "compress")] {
+/// # use fdeflate::StreamingDecompressor;
+/// # let compressed = fdeflate::compress_to_vec(b"Hello world!");
+/// # let (first, second) = compressed.split_at(compressed.len() / 2);
+/// let mut decompressor = StreamingDecompressor::new();
+/// let mut output = vec![0; 1024];
+///
+/// let (consumed, mut position) = decompressor.decompress(first, &mut output, 0).unwrap();
+/// let mut remaining = [&first[consumed..], second].concat();
+/// while !decompressor.is_done() {
+///     let (consumed, produced) = decompressor.finish(&remaining, &mut output, position).unwrap();
+///     remaining.drain(..consumed);
+///     position += produced;
+/// }
+/// assert_eq!(&output[..position], b"Hello world!");
+/// # }
+/// ```
+pub struct StreamingDecompressor {
+    inner: Box<Decompressor>,
+}
+
+impl StreamingDecompressor {
+    /// Create a new decompressor.
+    pub fn new() -> Self {
+        Self {
+            inner: Box::default(),
+        }
+    }
+
+    /// Wrap an existing decompressor, for instance one that has already been configured.
+    pub fn from_decompressor(inner: Box<Decompressor>) -> Self {
+        Self { inner }
+    }
+
+    /// Returns a mutable reference to the underlying decompressor, to change its settings.
+    pub fn decompressor_mut(&mut self) -> &mut Decompressor {
+        &mut self.inner
+    }
+
+    /// Decompress `input` into `output`, starting at `output_position`, with more input to come.
+    ///
+    /// Returns the number of bytes consumed from `input` and the number of bytes written to
+    /// `output`. Unconsumed input must be passed again on the next call. Running out of input is
+    /// never an error here, even if the stream is incomplete. See [`Decompressor::read`] for the
+    /// requirements on `output`.
+    pub fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        output_position: usize,
+    ) -> Result<(usize, usize), DecompressionError> {
+        self.inner.read(input, output, output_position, false)
+    }
+
+    /// Decompress the final part of the input.
+    ///
+    /// `input` must contain all remaining compressed data. This returns the same values as
+    /// [`decompress`](StreamingDecompressor::decompress) and must be called again (with any
+    /// unconsumed input) until [`is_done`](StreamingDecompressor::is_done) returns true, because
+    /// `output` may fill up first. If the stream ends before it is complete, this returns
+    /// [`DecompressionError::InsufficientInput`].
+    pub fn finish(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        output_position: usize,
+    ) -> Result<(usize, usize), DecompressionError> {
+        self.inner.read(input, output, output_position, true)
+    }
+
+    /// Returns true once the end of the stream has been reached.
+    pub fn is_done(&self) -> bool {
+        self.inner.is_done()
+    }
+}
+
+impl Default for StreamingDecompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_stream() {
+        let compressed = crate::compress_to_vec(&[7; 1000]);
+        let truncated = &compressed[..compressed.len() - 2];
+
+        let mut decompressor = StreamingDecompressor::new();
+        let mut output = vec![0; 2048];
+        let (consumed, produced) = decompressor.decompress(truncated, &mut output, 0).unwrap();
+        assert!(!decompressor.is_done());
+
+        let result = decompressor.finish(&truncated[consumed..], &mut output, produced);
+        assert!(matches!(result, Err(DecompressionError::InsufficientInput)));
+    }
+
+    #[test]
+    fn finish_with_small_output() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 7) as u8).collect();
+        let compressed = crate::compress_to_vec(&data);
+
+        let mut decompressor = StreamingDecompressor::new();
+        let mut output = vec![0; data.len() + 2];
+        let mut input = &compressed[..];
+        let mut position = 0;
+        while !decompressor.is_done() {
+            let end = output.len().min(position + 1000);
+            let (consumed, produced) = decompressor
+                .finish(input, &mut output[..end], position)
+                .unwrap();
+            input = &input[consumed..];
+            position += produced;
+        }
+        assert_eq!(&output[..position], &data[..]);
+    }
+}
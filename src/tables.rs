@@ -1,3 +1,20 @@
+//! Constant tables from the deflate specification,
+//! [RFC 1951](https://www.rfc-editor.org/rfc/rfc1951).
+//!
+//! These are the tables that every deflate encoder and decoder needs, exposed for projects that
+//! implement related formats or analyze deflate streams. Length codes are the literal/length
+//! symbols 257 to 285; the tables indexed by length code start at symbol 257.
+//!
+//! ```
+//! use fdeflate::tables::{LEN_SYM_TO_LEN_BASE, LEN_SYM_TO_LEN_EXTRA, LENGTH_TO_SYMBOL};
+//!
+//! // A match of length 100 is encoded as symbol 279 followed by 4 extra bits holding 1.
+//! let symbol = LENGTH_TO_SYMBOL[100 - 3];
+//! assert_eq!(symbol, 279);
+//! let index = symbol as usize - 257;
+//! assert_eq!((LEN_SYM_TO_LEN_BASE[index], LEN_SYM_TO_LEN_EXTRA[index]), (99, 4));
+//! ```
+
 /// Hard-coded Huffman codes used regardless of the input.
 ///
 /// These values work well for PNGs with some form of filtering enabled, but will likely make most
@@ -17,13 +34,14 @@ pub(crate) const HUFFMAN_LENGTHS: [u8; 286] = [
     12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 12, 9,
 ];
 
+#[cfg(feature = "compress")]
 pub(crate) const HUFFMAN_CODES: [u16; 286] = match crate::compute_codes(&HUFFMAN_LENGTHS) {
     Some(codes) => codes,
     None => panic!("HUFFMAN_LENGTHS is invalid"),
 };
 
-/// Length code for length values (derived from deflate spec).
-pub(crate) const LENGTH_TO_SYMBOL: [u16; 256] = [
+/// Length code for each match length, indexed by the length minus 3.
+pub const LENGTH_TO_SYMBOL: [u16; 256] = [
     257, 258, 259, 260, 261, 262, 263, 264, 265, 265, 266, 266, 267, 267, 268, 268, 269, 269, 269,
     269, 270, 270, 270, 270, 271, 271, 271, 271, 272, 272, 272, 272, 273, 273, 273, 273, 273, 273,
     273, 273, 274, 274, 274, 274, 274, 274, 274, 274, 275, 275, 275, 275, 275, 275, 275, 275, 276,
@@ -40,8 +58,9 @@ pub(crate) const LENGTH_TO_SYMBOL: [u16; 256] = [
     284, 284, 284, 284, 284, 284, 284, 284, 285,
 ];
 
-/// Number of extra bits for length values (derived from deflate spec).
-pub(crate) const LENGTH_TO_LEN_EXTRA: [u8; 256] = [
+/// Number of extra bits that follow the length code for each match length, indexed by the length
+/// minus 3.
+pub const LENGTH_TO_LEN_EXTRA: [u8; 256] = [
     0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
     3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
     4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
@@ -52,39 +71,42 @@ pub(crate) const LENGTH_TO_LEN_EXTRA: [u8; 256] = [
     5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 0,
 ];
 
+#[cfg(feature = "compress")]
 pub(crate) const BITMASKS: [u32; 17] = [
     0x0000, 0x0001, 0x0003, 0x0007, 0x000F, 0x001F, 0x003F, 0x007F, 0x00FF, 0x01FF, 0x03FF, 0x07FF,
     0x0FFF, 0x1FFF, 0x3FFF, 0x7FFF, 0xFFFF,
 ];
 
-/// Order of the length code length alphabet (derived from deflate spec).
-pub(crate) const CLCL_ORDER: [usize; 19] = [
+/// Order in which the code lengths of the code length alphabet are stored in a dynamic block
+/// header.
+pub const CLCL_ORDER: [usize; 19] = [
     16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
 ];
 
-/// Number of extra bits for each length code (derived from deflate spec).
-pub(crate) const LEN_SYM_TO_LEN_EXTRA: [u8; 29] = [
+/// Number of extra bits that follow each length code, indexed by the symbol minus 257.
+pub const LEN_SYM_TO_LEN_EXTRA: [u8; 29] = [
     0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
 ];
 
-/// The base length for each length code (derived from deflate spec).
-pub(crate) const LEN_SYM_TO_LEN_BASE: [usize; 29] = [
+/// Shortest match length that each length code represents, indexed by the symbol minus 257.
+pub const LEN_SYM_TO_LEN_BASE: [usize; 29] = [
     3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
     163, 195, 227, 258,
 ];
 
-/// Number of extra bits for each distance code (derived from deflate spec.)
-pub(crate) const DIST_SYM_TO_DIST_EXTRA: [u8; 30] = [
+/// Number of extra bits that follow each distance code.
+pub const DIST_SYM_TO_DIST_EXTRA: [u8; 30] = [
     0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
     13,
 ];
 
-/// The base distance for each distance code (derived from deflate spec).
-pub(crate) const DIST_SYM_TO_DIST_BASE: [u16; 30] = [
+/// Shortest distance that each distance code represents.
+pub const DIST_SYM_TO_DIST_BASE: [u16; 30] = [
     1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
     2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
 ];
 
+#[cfg(feature = "decompress")]
 pub(crate) const FDEFLATE_LITLEN_DECODE_TABLE: [u32; 4096] = [
     0x8204, 0x28206, 0x18205, 0xfa8208, 0x2008206, 0x38207, 0xff8205, 0xf4820a, 0x1008205,
     0xfe8206, 0x2018207, 0x98209, 0xfa008208, 0xfd8207, 0x2ff8207, 0xb010a, 0x8204, 0x2028208,
@@ -564,6 +586,7 @@ pub(crate) const FDEFLATE_LITLEN_DECODE_TABLE: [u32; 4096] = [
     0xf38108, 0x8102, 0x68106, 0xff8103, 0xe3050c,
 ];
 
+#[cfg(feature = "decompress")]
 pub(crate) const FDEFLATE_DIST_DECODE_TABLE: [u32; 512] = [
     0x10001, 0x0, 0x10001, 0x0, 0x10001, 0x0, 0x10001, 0x0, 0x10001, 0x0, 0x10001, 0x0, 0x10001,
     0x0, 0x10001, 0x0, 0x10001, 0x0, 0x10001, 0x0, 0x10001, 0x0, 0x10001, 0x0, 0x10001, 0x0,
@@ -607,7 +630,11 @@ pub(crate) const FDEFLATE_DIST_DECODE_TABLE: [u32; 512] = [
     0x0, 0x10001, 0x0, 0x10001, 0x0,
 ];
 
-pub(crate) const FIXED_CODE_LENGTHS: [u8; 320] = make_fixed_code_lengths();
+/// Code lengths of the fixed Huffman codes used by block type 1.
+///
+/// The first 288 entries are the literal/length code and the remaining 32 the distance code,
+/// laid out like the code lengths of a dynamic block header.
+pub const FIXED_CODE_LENGTHS: [u8; 320] = make_fixed_code_lengths();
 const fn make_fixed_code_lengths() -> [u8; 320] {
     let mut i = 0;
     let mut lengths = [0; 320];
@@ -633,3 +660,28 @@ const fn make_fixed_code_lengths() -> [u8; 320] {
     }
     lengths
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consistent_with_each_other() {
+        for length in 3..=258 {
+            let index = LENGTH_TO_SYMBOL[length - 3] as usize - 257;
+            let extra = LEN_SYM_TO_LEN_EXTRA[index];
+            assert_eq!(LENGTH_TO_LEN_EXTRA[length - 3], extra);
+            assert!(LEN_SYM_TO_LEN_BASE[index] <= length);
+            assert!(length < LEN_SYM_TO_LEN_BASE[index] + (1 << extra) || length == 258);
+        }
+        for i in 0..29 {
+            let dist = DIST_SYM_TO_DIST_BASE[i] as u32 + (1 << DIST_SYM_TO_DIST_EXTRA[i]);
+            assert_eq!(dist, DIST_SYM_TO_DIST_BASE[i + 1] as u32);
+        }
+        assert_eq!(DIST_SYM_TO_DIST_BASE[29] as u32 + (1 << 13), 32769);
+
+        let mut order = CLCL_ORDER;
+        order.sort_unstable();
+        assert!(order.iter().copied().eq(0..19));
+    }
+}
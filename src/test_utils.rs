@@ -0,0 +1,11 @@
+//! Helpers shared by the unit tests. The integration tests include this file too.
+
+/// Returns `len` bytes of test data.
+///
+/// The data mixes a short repeating pattern with a slowly changing one, so it compresses well with
+/// both literals and long matches, and spans many windows for large lengths.
+pub(crate) fn test_data(len: usize) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| (i % 251) as u8 ^ (i / 997) as u8)
+        .collect()
+}
@@ -0,0 +1,128 @@
+use std::io::{self, Write};
+
+use crate::decompress::WINDOW_SIZE;
+use crate::{CustomDecompressor, DecompressionError, RECOMMENDED_OUTPUT_BUFFER_SIZE};
+
+/// Output buffer for driving a [`CustomDecompressor`] that keeps the most recent 32 KiB of output
+/// ahead of the data produced, so that back-references can be resolved while the output is
+/// handed out a piece at a time.
+///
+/// Output that has been produced but not yet consumed is never discarded. Once the space after
+/// it runs low, everything before the last 32 KiB of output is dropped to make room.
+pub(crate) struct OutputWindow<B = Vec<u8>> {
+    buffer: B,
+    /// Start of the output that hasn't been consumed yet.
+    start: usize,
+    /// End of the output.
+    end: usize,
+}
+
+impl OutputWindow {
+    pub(crate) fn new() -> Self {
+        Self::from_buffer(vec![0; WINDOW_SIZE + 2 * RECOMMENDED_OUTPUT_BUFFER_SIZE])
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> OutputWindow<B> {
+    /// Use `buffer` for the window and the output. It should be longer than 32 KiB.
+    pub(crate) fn from_buffer(buffer: B) -> Self {
+        Self {
+            buffer,
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// Returns the output that hasn't been consumed yet.
+    pub(crate) fn buffered(&self) -> &[u8] {
+        &self.buffer.as_ref()[self.start..self.end]
+    }
+
+    /// Mark `amt` bytes of the buffered output as consumed.
+    pub(crate) fn consume(&mut self, amt: usize) {
+        self.start = (self.start + amt).min(self.end);
+    }
+
+    /// Returns the last 32 KiB of output, or all of it if there is less.
+    pub(crate) fn history(&self) -> &[u8] {
+        &self.buffer.as_ref()[self.end.saturating_sub(WINDOW_SIZE)..self.end]
+    }
+
+    /// Discard all output, and continue as if `history` had just been produced and consumed.
+    pub(crate) fn reset(&mut self, history: &[u8]) {
+        debug_assert!(history.len() <= WINDOW_SIZE);
+        self.buffer.as_mut()[..history.len()].copy_from_slice(history);
+        self.start = history.len();
+        self.end = history.len();
+    }
+
+    /// Drop output before the last 32 KiB if there isn't much room left after it.
+    fn make_room(&mut self) {
+        if self.buffer.as_ref().len() - self.end >= RECOMMENDED_OUTPUT_BUFFER_SIZE {
+            return;
+        }
+        let keep_from = self.start.min(self.end.saturating_sub(WINDOW_SIZE));
+        if keep_from > 0 {
+            self.buffer.as_mut().copy_within(keep_from..self.end, 0);
+            self.start -= keep_from;
+            self.end -= keep_from;
+        }
+    }
+
+    /// Decompress from `input` into the buffer, returning the number of bytes consumed and
+    /// produced. The new output is added to the end of the buffered output.
+    pub(crate) fn decode<const LITLEN_TABLE_SIZE: usize>(
+        &mut self,
+        decompressor: &mut CustomDecompressor<LITLEN_TABLE_SIZE>,
+        input: &[u8],
+        end_of_input: bool,
+    ) -> Result<(usize, usize), DecompressionError> {
+        self.make_room();
+        let (consumed, produced) =
+            decompressor.read(input, self.buffer.as_mut(), self.end, end_of_input)?;
+        self.end += produced;
+        Ok((consumed, produced))
+    }
+
+    /// Decompress and discard up to `n` bytes of output, along with any buffered output, using
+    /// [`CustomDecompressor::skip_output`]. Returns the number of bytes consumed and skipped.
+    pub(crate) fn skip<const LITLEN_TABLE_SIZE: usize>(
+        &mut self,
+        decompressor: &mut CustomDecompressor<LITLEN_TABLE_SIZE>,
+        input: &[u8],
+        n: u64,
+        end_of_input: bool,
+    ) -> Result<(usize, u64), DecompressionError> {
+        let result =
+            decompressor.skip_output(input, self.buffer.as_mut(), &mut self.end, n, end_of_input);
+        self.start = self.end;
+        result
+    }
+
+    /// Decompress as much of `input` as possible, writing all of the output to `writer`.
+    ///
+    /// Returns the number of bytes of input consumed and the number of bytes written. Stops
+    /// early once the end of the stream is reached, or if more input is needed.
+    pub(crate) fn decode_to<W: Write, const LITLEN_TABLE_SIZE: usize>(
+        &mut self,
+        decompressor: &mut CustomDecompressor<LITLEN_TABLE_SIZE>,
+        input: &[u8],
+        end_of_input: bool,
+        writer: &mut W,
+    ) -> io::Result<(usize, u64)> {
+        let mut consumed = 0;
+        let mut written = 0;
+        while !decompressor.is_done() {
+            let (n, produced) = self.decode(decompressor, &input[consumed..], end_of_input)?;
+            consumed += n;
+            if produced > 0 {
+                writer.write_all(&self.buffer.as_ref()[self.end - produced..self.end])?;
+                self.start = self.end;
+                written += produced as u64;
+            } else if n == 0 {
+                break;
+            }
+        }
+        Ok((consumed, written))
+    }
+}
@@ -0,0 +1,155 @@
+use std::io::{self, Write};
+
+use crate::windowed::OutputWindow;
+use crate::{DecompressionError, Decompressor};
+
+/// Decompresses a zlib stream written to it, forwarding the output to an underlying writer.
+///
+/// This is the push-based counterpart of [`DecompressReader`](crate::DecompressReader): compressed
+/// data can be written in chunks of any size as it arrives, and decompressed data is passed on to
+/// the inner writer as soon as it is available.
+///
+/// ```
+/// # #[cfg(feature = "compress")] {
+/// # use std::io::Write;
+/// # use fdeflate::DecompressWriter;
+/// # let compressed = fdeflate::compress_to_vec(b"Hello world!");
+/// let mut writer = DecompressWriter::new(Vec::new());
+/// for packet in compressed.chunks(3) {
+///     writer.write_all(packet).unwrap();
+/// }
+/// assert_eq!(writer.finish().unwrap(), b"Hello world!");
+/// # }
+/// ```
+pub struct DecompressWriter<W: Write> {
+    writer: W,
+    decompressor: Box<Decompressor>,
+    /// Input that couldn't be consumed yet, to be prepended to the next write.
+    pending: Vec<u8>,
+    output: OutputWindow,
+}
+
+impl<W: Write> DecompressWriter<W> {
+    /// Create a new writer that forwards the decompressed data to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            decompressor: Box::default(),
+            pending: Vec::new(),
+            output: OutputWindow::new(),
+        }
+    }
+
+    /// Returns whether the end of the compressed stream has been reached.
+    pub fn is_done(&self) -> bool {
+        self.decompressor.is_done()
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Check that the stream is complete, flush the underlying writer, and return it.
+    ///
+    /// Returns an error if the data written so far ends before the end of the zlib stream.
+    pub fn finish(mut self) -> io::Result<W> {
+        let pending = std::mem::take(&mut self.pending);
+        self.decompress(&pending, true)?;
+        if !self.decompressor.is_done() {
+            return Err(DecompressionError::InsufficientInput.into());
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Decompress as much of `input` as possible, returning the number of bytes consumed.
+    fn decompress(&mut self, input: &[u8], end_of_input: bool) -> io::Result<usize> {
+        let (consumed, _) = self.output.decode_to(
+            &mut self.decompressor,
+            input,
+            end_of_input,
+            &mut self.writer,
+        )?;
+        Ok(consumed)
+    }
+}
+
+impl<W: Write> Write for DecompressWriter<W> {
+    /// Decompress `buf` and write the output to the underlying writer.
+    ///
+    /// All of `buf` is accepted until the end of the compressed stream is reached; any data after
+    /// that is rejected by returning `Ok(0)`.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.decompressor.is_done() {
+            return Ok(0);
+        }
+
+        // Input accepted by earlier writes, whether decompressed or still pending.
+        let accepted = self.decompressor.total_input() + self.pending.len() as u64;
+        if self.pending.is_empty() {
+            let consumed = self.decompress(buf, false)?;
+            self.pending.extend_from_slice(&buf[consumed..]);
+        } else {
+            let mut pending = std::mem::take(&mut self.pending);
+            pending.extend_from_slice(buf);
+            let consumed = self.decompress(&pending, false)?;
+            pending.drain(..consumed);
+            self.pending = pending;
+        }
+        if self.decompressor.is_done() {
+            // The decompressor may have read ahead past the end of the stream.
+            self.pending.clear();
+            return Ok(self.decompressor.byte_position().saturating_sub(accepted) as usize);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunked_writes() {
+        let data = crate::test_utils::test_data(300_000);
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+
+        for chunk_size in [1, 7, 4096, compressed.len()] {
+            let mut writer = DecompressWriter::new(Vec::new());
+            for chunk in compressed.chunks(chunk_size) {
+                writer.write_all(chunk).unwrap();
+            }
+            assert!(writer.is_done());
+            assert!(writer.finish().unwrap() == data);
+        }
+    }
+
+    #[test]
+    fn trailing_and_missing_data() {
+        let compressed = crate::compress_to_vec(b"Hello world!");
+
+        let mut writer = DecompressWriter::new(Vec::new());
+        let mut input = compressed.clone();
+        input.extend_from_slice(b"trailer");
+        assert_eq!(writer.write(&input).unwrap(), compressed.len());
+        assert_eq!(writer.write(b"more").unwrap(), 0);
+        assert_eq!(writer.finish().unwrap(), b"Hello world!");
+
+        let mut writer = DecompressWriter::new(Vec::new());
+        writer
+            .write_all(&compressed[..compressed.len() - 2])
+            .unwrap();
+        let err = writer.finish().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,371 @@
+#!/usr/bin/env python3
+"""Regenerates the regression corpus in this directory.
+
+Each `<name>.zlib` file is a zlib stream and `<name>.raw` is its decompressed contents. Streams
+are produced by the system zlib with a range of levels, strategies and window sizes, or written
+by hand below to exercise unusual Huffman trees and block sequences. Every stream is checked
+against zlib's decoder before being written.
+
+The remaining streams come from other encoders, whose block splitting and Huffman trees differ
+from zlib's:
+
+- `libdeflate-*`: libdeflate, loaded from the system library.
+- `zopfli-*`: the `zopfli` command, whose gzip output is rewrapped as zlib.
+- `png-libpng-*`: IDAT data of images written by the system libpng.
+- `png-oxipng-*`: IDAT data after recompressing those images with the `oxipng` command, using
+  its libdeflate and zopfli backends.
+
+Fixtures for an encoder that isn't available are left as they are.
+"""
+
+import ctypes
+import ctypes.util
+import os
+import random
+import shutil
+import struct
+import subprocess
+import sys
+import tempfile
+import zlib
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+
+def text_data():
+    rng = random.Random(1)
+    words = [
+        "deflate", "huffman", "stream", "block", "window", "literal", "length", "distance",
+        "the", "a", "of", "and", "to", "in", "is", "png", "image", "row", "filter", "zlib",
+    ]
+    lines = []
+    while sum(len(l) for l in lines) < 12000:
+        lines.append(" ".join(rng.choice(words) for _ in range(rng.randint(3, 12))) + ".\n")
+    return "".join(lines).encode()
+
+
+def image_data():
+    # 64x48 RGB image with PNG filter bytes, cycling through the five filter types.
+    width, height = 64, 48
+    out = bytearray()
+    for y in range(height):
+        out.append(y % 5)
+        for x in range(width):
+            out += bytes(((x * 4) & 0xFF, (y * 5) & 0xFF, ((x ^ y) * 3) & 0xFF))
+    return bytes(out)
+
+
+def photo_pixels():
+    # 160x120 RGBA image with smooth gradients, noise and a few flat areas, so that PNG encoders
+    # pick a mix of filters.
+    rng = random.Random(3)
+    width, height = 160, 120
+    out = bytearray()
+    for y in range(height):
+        for x in range(width):
+            if 40 <= x < 80 and 30 <= y < 70:
+                out += b"\x20\x80\xe0\xff"
+                continue
+            noise = rng.randint(-6, 6)
+            r = (x * 255 // width + noise) & 0xFF
+            g = (y * 255 // height + noise) & 0xFF
+            b = ((x + y) * 2 + rng.randint(0, 3)) & 0xFF
+            out += bytes((r, g, b, 255 if y < 100 else (x * 3) & 0xFF))
+    return width, height, bytes(out)
+
+
+class BitWriter:
+    def __init__(self):
+        self.out = bytearray()
+        self.acc = 0
+        self.nbits = 0
+
+    def bits(self, value, n):
+        self.acc |= value << self.nbits
+        self.nbits += n
+        while self.nbits >= 8:
+            self.out.append(self.acc & 0xFF)
+            self.acc >>= 8
+            self.nbits -= 8
+
+    def code(self, code, length):
+        # Huffman codes are stored starting from the most significant bit.
+        self.bits(int(format(code, "0{}b".format(length))[::-1], 2), length)
+
+    def align(self):
+        if self.nbits:
+            self.bits(0, 8 - self.nbits)
+
+    def finish(self, data):
+        self.align()
+        return b"\x78\x01" + bytes(self.out) + struct.pack(">I", zlib.adler32(data))
+
+
+def canonical_codes(lengths):
+    codes = {}
+    code = 0
+    for length in range(1, 16):
+        for sym, l in sorted(lengths.items()):
+            if l == length:
+                codes[sym] = (code, length)
+                code += 1
+        code <<= 1
+    return codes
+
+
+def write_dynamic_header(w, litlen, dist, last):
+    w.bits(last, 1)
+    w.bits(2, 2)
+    hlit = max(litlen) + 1
+    hdist = max(dist) + 1
+    w.bits(hlit - 257, 5)
+    w.bits(hdist - 1, 5)
+    # All code length symbols 0-15 get 4-bit codes; the repeat codes are unused.
+    order = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15]
+    w.bits(19 - 4, 4)
+    for sym in order:
+        w.bits(4 if sym < 16 else 0, 3)
+    cl_codes = canonical_codes({sym: 4 for sym in range(16)})
+    for sym in range(hlit):
+        w.code(*cl_codes[litlen.get(sym, 0)])
+    for sym in range(hdist):
+        w.code(*cl_codes[dist.get(sym, 0)])
+
+
+def deep_tree():
+    # Literal/length tree with one code of each length from 1 to 14 and two of length 15.
+    symbols = [ord(c) for c in "etaoinshrdlucmf"] + [256]
+    litlen = {sym: min(i + 1, 15) for i, sym in enumerate(symbols)}
+    dist = {0: 1}
+    data = b"".join(bytes([s]) * (i % 3 + 1) for i, s in enumerate(symbols[:-1])) * 20
+
+    w = BitWriter()
+    write_dynamic_header(w, litlen, dist, 1)
+    codes = canonical_codes(litlen)
+    for b in data:
+        w.code(*codes[b])
+    w.code(*codes[256])
+    return data, w.finish(data)
+
+
+def single_distance_code():
+    # Dynamic block whose distance tree has a single one-bit code (distance 1), used for runs.
+    litlen = {ord("x"): 2, ord("y"): 2, 256: 2, 284: 2}
+    dist = {0: 1}
+    codes = canonical_codes(litlen)
+
+    w = BitWriter()
+    write_dynamic_header(w, litlen, dist, 1)
+    data = b""
+    for _ in range(5):
+        w.code(*codes[ord("x")])
+        w.code(*codes[284])  # length 227-258
+        w.bits(30, 5)  # length 257
+        w.code(0, 1)  # distance 1
+        w.code(*codes[ord("y")])
+        data += b"x" * 258 + b"y"
+    w.code(*codes[256])
+    return data, w.finish(data)
+
+
+def empty_stored_blocks():
+    # A run of empty stored blocks followed by a fixed block.
+    w = BitWriter()
+    for _ in range(8):
+        w.bits(0, 1)
+        w.bits(0, 2)
+        w.align()
+        w.bits(0x0000, 16)
+        w.bits(0xFFFF, 16)
+    w.bits(1, 1)
+    w.bits(1, 2)
+    data = b"after empty blocks"
+    for b in data:
+        if b < 144:
+            w.code(0x30 + b, 8)
+        else:
+            w.code(0x190 + b - 144, 9)
+    w.code(0, 7)
+    return data, w.finish(data)
+
+
+def max_distance():
+    # 32 KiB of stored data followed by a fixed block copying from exactly 32768 bytes back.
+    rng = random.Random(2)
+    prefix = bytes(rng.randrange(256) for _ in range(32768))
+    w = BitWriter()
+    w.bits(0, 1)
+    w.bits(0, 2)
+    w.align()
+    w.bits(32768, 16)
+    w.bits(32768 ^ 0xFFFF, 16)
+    w.out += prefix
+    w.bits(1, 1)
+    w.bits(1, 2)
+    w.code(0xC5, 8)  # length 258
+    w.code(29, 5)  # distance 24577-32768
+    w.bits(8191, 13)
+    w.code(0, 7)
+    data = prefix + prefix[:258]
+    return data, w.finish(data)
+
+
+def zlib_stream(data, level=6, wbits=15, strategy=zlib.Z_DEFAULT_STRATEGY, flushes=0):
+    c = zlib.compressobj(level, zlib.DEFLATED, wbits, 9, strategy)
+    out = b""
+    step = len(data) // (flushes + 1) + 1
+    for i in range(0, len(data), step):
+        out += c.compress(data[i : i + step])
+        if flushes:
+            out += c.flush(zlib.Z_SYNC_FLUSH)
+    return data, out + c.flush()
+
+
+def libdeflate_stream(data, level):
+    path = ctypes.util.find_library("deflate")
+    if path is None:
+        return None
+    lib = ctypes.CDLL(path)
+    lib.libdeflate_alloc_compressor.restype = ctypes.c_void_p
+    lib.libdeflate_zlib_compress.restype = ctypes.c_size_t
+    lib.libdeflate_zlib_compress.argtypes = [
+        ctypes.c_void_p, ctypes.c_char_p, ctypes.c_size_t, ctypes.c_void_p, ctypes.c_size_t,
+    ]
+    lib.libdeflate_free_compressor.argtypes = [ctypes.c_void_p]
+    compressor = lib.libdeflate_alloc_compressor(level)
+    out = ctypes.create_string_buffer(len(data) + 1024)
+    size = lib.libdeflate_zlib_compress(compressor, data, len(data), out, len(out))
+    lib.libdeflate_free_compressor(compressor)
+    assert size > 0
+    return data, out.raw[:size]
+
+
+def gzip_to_zlib(gz, data):
+    # Skip the gzip header, keeping only the deflate stream, and add a zlib header and trailer.
+    assert gz[:3] == b"\x1f\x8b\x08"
+    flags = gz[3]
+    pos = 10
+    if flags & 4:
+        pos += 2 + struct.unpack("<H", gz[pos : pos + 2])[0]
+    for flag in (8, 16):
+        if flags & flag:
+            pos = gz.index(b"\0", pos) + 1
+    if flags & 2:
+        pos += 2
+    return b"\x78\xda" + gz[pos:-8] + struct.pack(">I", zlib.adler32(data))
+
+
+def zopfli_stream(data):
+    if shutil.which("zopfli") is None:
+        return None
+    with tempfile.TemporaryDirectory() as tmp:
+        path = os.path.join(tmp, "data")
+        with open(path, "wb") as f:
+            f.write(data)
+        subprocess.run(["zopfli", path], check=True)
+        with open(path + ".gz", "rb") as f:
+            gz = f.read()
+    return data, gzip_to_zlib(gz, data)
+
+
+class PngImage(ctypes.Structure):
+    _fields_ = [
+        ("opaque", ctypes.c_void_p),
+        ("version", ctypes.c_uint32),
+        ("width", ctypes.c_uint32),
+        ("height", ctypes.c_uint32),
+        ("format", ctypes.c_uint32),
+        ("flags", ctypes.c_uint32),
+        ("colormap_entries", ctypes.c_uint32),
+        ("warning_or_error", ctypes.c_uint32),
+        ("message", ctypes.c_char * 64),
+    ]
+
+
+def libpng_encode(width, height, pixels, rgba):
+    path = ctypes.util.find_library("png16")
+    if path is None:
+        return None
+    lib = ctypes.CDLL(path)
+    image = PngImage(version=1, width=width, height=height, format=3 if rgba else 2)
+    if not rgba:
+        pixels = b"".join(pixels[i : i + 3] for i in range(0, len(pixels), 4))
+    size = ctypes.c_size_t(0)
+    args = [ctypes.byref(image), None, ctypes.byref(size), 0, pixels, 0, None]
+    assert lib.png_image_write_to_memory(*args), image.message
+    out = ctypes.create_string_buffer(size.value)
+    args[1] = out
+    assert lib.png_image_write_to_memory(*args), image.message
+    return out.raw[: size.value]
+
+
+def oxipng_encode(png, *options):
+    if png is None or shutil.which("oxipng") is None:
+        return None
+    command = ["oxipng", "--force", "--stdout", *options, "-"]
+    return subprocess.run(command, input=png, stdout=subprocess.PIPE, check=True).stdout
+
+
+def png_stream(png):
+    # Concatenate the IDAT chunks of a PNG file.
+    if png is None:
+        return None
+    assert png[:8] == b"\x89PNG\r\n\x1a\n"
+    pos = 8
+    stream = b""
+    while pos < len(png):
+        length, kind = struct.unpack(">I4s", png[pos : pos + 8])
+        if kind == b"IDAT":
+            stream += png[pos + 8 : pos + 8 + length]
+        pos += 12 + length
+    return zlib.decompress(stream), stream
+
+
+def main():
+    text = text_data()
+    image = image_data()
+    cases = {}
+    for level in range(10):
+        cases["zlib-level{}".format(level)] = zlib_stream(text, level)
+    for wbits in (9, 12):
+        cases["zlib-window{}".format(wbits)] = zlib_stream(text, 9, wbits)
+    strategies = {
+        "filtered": zlib.Z_FILTERED,
+        "huffman-only": zlib.Z_HUFFMAN_ONLY,
+        "rle": zlib.Z_RLE,
+        "fixed": zlib.Z_FIXED,
+    }
+    for name, strategy in strategies.items():
+        cases["zlib-image-{}".format(name)] = zlib_stream(image, 6, 15, strategy)
+    cases["zlib-image-sync-flush"] = zlib_stream(image, 6, 15, zlib.Z_DEFAULT_STRATEGY, 5)
+    cases["deep-tree"] = deep_tree()
+    cases["single-distance-code"] = single_distance_code()
+    cases["empty-stored-blocks"] = empty_stored_blocks()
+    cases["max-distance"] = max_distance()
+
+    width, height, pixels = photo_pixels()
+    for level in (1, 6, 12):
+        cases["libdeflate-level{}".format(level)] = libdeflate_stream(text, level)
+    cases["libdeflate-image"] = libdeflate_stream(image, 12)
+    cases["zopfli-text"] = zopfli_stream(text)
+    cases["zopfli-image"] = zopfli_stream(image)
+    for name, rgba in (("rgb", False), ("rgba", True)):
+        png = libpng_encode(width, height, pixels, rgba)
+        cases["png-libpng-{}".format(name)] = png_stream(png)
+        cases["png-oxipng-libdeflate-{}".format(name)] = png_stream(oxipng_encode(png, "-o", "4"))
+        cases["png-oxipng-zopfli-{}".format(name)] = png_stream(oxipng_encode(png, "-o", "4", "-z"))
+
+    for name, case in sorted(cases.items()):
+        if case is None:
+            print("skipping {}: encoder not available".format(name), file=sys.stderr)
+            continue
+        data, stream = case
+        assert zlib.decompress(stream) == data, name
+        with open(os.path.join(HERE, name + ".zlib"), "wb") as f:
+            f.write(stream)
+        with open(os.path.join(HERE, name + ".raw"), "wb") as f:
+            f.write(data)
+
+
+if __name__ == "__main__":
+    main()
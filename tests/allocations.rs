@@ -0,0 +1,93 @@
+//! Checks that decompression doesn't allocate once the decompressor has been created.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use fdeflate::{CustomDecompressor, Decompressor, SmallDecompressor};
+use rand::Rng;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+/// Decompress `input` in small pieces, returning the number of allocations made while doing so.
+fn count_allocations<const N: usize>(
+    mut decompressor: CustomDecompressor<N>,
+    input: &[u8],
+    expected: &[u8],
+) -> usize {
+    let mut output = vec![0; expected.len() + 2];
+    let mut input_index = 0;
+    let mut output_index = 0;
+
+    let before = allocations();
+    while !decompressor.is_done() {
+        let end = (input_index + 1000).min(input.len());
+        let (consumed, produced) = decompressor
+            .read(
+                &input[input_index..end],
+                &mut output,
+                output_index,
+                end == input.len(),
+            )
+            .unwrap();
+        input_index += consumed;
+        output_index += produced;
+    }
+    let count = allocations() - before;
+
+    assert_eq!(&output[..output_index], expected);
+    count
+}
+
+#[test]
+fn read_does_not_allocate() {
+    let mut rng = rand::thread_rng();
+    let mut data: Vec<u8> = (0..200_000)
+        .map(|_| rng.gen::<u8>() & rng.gen::<u8>() & rng.gen::<u8>())
+        .collect();
+    data.extend((0..100_000u32).map(|i| (i % 251) as u8));
+
+    let mut inputs = vec![fdeflate::compress_to_vec(&data)];
+    for level in [0, 1, 6, 9] {
+        inputs.push(miniz_oxide::deflate::compress_to_vec_zlib(&data, level));
+    }
+
+    for input in &inputs {
+        assert_eq!(count_allocations(Decompressor::new(), input, &data), 0);
+        assert_eq!(count_allocations(SmallDecompressor::new(), input, &data), 0);
+        assert_eq!(
+            count_allocations(CustomDecompressor::<256>::new(), input, &data),
+            0
+        );
+
+        let mut lenient = Decompressor::new();
+        lenient.set_lenient(true);
+        assert_eq!(count_allocations(lenient, input, &data), 0);
+    }
+}
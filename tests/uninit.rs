@@ -0,0 +1,29 @@
+//! Checks decompression into uninitialized memory.
+
+use std::mem::MaybeUninit;
+
+use fdeflate::Decompressor;
+
+#[path = "../src/test_utils.rs"]
+mod test_utils;
+
+#[test]
+fn read_uninit() {
+    let data = test_utils::test_data(300_000);
+    let compressed = fdeflate::compress_to_vec(&data);
+
+    for chunk_size in [1, 1000, 1 << 20] {
+        let mut decompressor = Decompressor::new();
+        let mut chunk = vec![MaybeUninit::uninit(); chunk_size];
+        let mut output = Vec::new();
+        let mut input = &compressed[..];
+        while !decompressor.is_done() {
+            let (consumed, produced) = decompressor.read_uninit(input, &mut chunk, true).unwrap();
+            input = &input[consumed..];
+            // SAFETY: `read_uninit` initialized the first `produced` elements.
+            output.extend(chunk[..produced].iter().map(|b| unsafe { b.assume_init() }));
+        }
+        assert!(input.is_empty());
+        assert!(output == data);
+    }
+}